@@ -0,0 +1,271 @@
+use super::filters::{ColorMode, ImageFilter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrayscaleWeights {
+    Rec601,
+    Rec709,
+    Average,
+}
+
+// Row-major 4x5 matrix mapping (r, g, b, a, 1) to (r, g, b, a), with values and offsets in 0..1.
+// Images are opaque, so alpha enters as one and the alpha row is ignored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorMatrix {
+    pub matrix: [f32; 20],
+}
+
+impl ColorMatrix {
+    pub fn new(matrix: [f32; 20]) -> Self {
+        Self { matrix }
+    }
+
+    // Rows of red, green and blue weights without offsets
+    fn from_rows(rows: [[f32; 3]; 3]) -> Self {
+        let mut matrix = [0.0; 20];
+        for (row, weights) in rows.iter().enumerate() {
+            matrix[row * 5..row * 5 + 3].copy_from_slice(weights);
+        }
+        matrix[18] = 1.0;
+        Self { matrix }
+    }
+
+    pub fn identity() -> Self {
+        Self::from_rows([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    pub fn sepia() -> Self {
+        Self::from_rows([
+            [0.393, 0.769, 0.189],
+            [0.349, 0.686, 0.168],
+            [0.272, 0.534, 0.131],
+        ])
+    }
+
+    pub fn grayscale(weights: GrayscaleWeights) -> Self {
+        let row = match weights {
+            GrayscaleWeights::Rec601 => [0.299, 0.587, 0.114],
+            GrayscaleWeights::Rec709 => [0.2126, 0.7152, 0.0722],
+            GrayscaleWeights::Average => [1.0 / 3.0; 3],
+        };
+        Self::from_rows([row; 3])
+    }
+
+    // Each output channel takes the value of the given input channel
+    pub fn channel_swap(red: Channel, green: Channel, blue: Channel) -> Self {
+        let row = |channel: Channel| {
+            let mut weights = [0.0; 3];
+            weights[channel as usize] = 1.0;
+            weights
+        };
+        Self::from_rows([row(red), row(green), row(blue)])
+    }
+
+    // Rows of input weights for each output channel
+    pub fn channel_mixer(red: [f32; 3], green: [f32; 3], blue: [f32; 3]) -> Self {
+        Self::from_rows([red, green, blue])
+    }
+
+    // Zero is grayscale, one leaves the image unchanged and larger values oversaturate
+    pub fn saturation(saturation: f32) -> Self {
+        let s = saturation;
+        Self::from_rows([
+            [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+        ])
+    }
+
+    // Rotates hues around the luminance axis, keeping luminance roughly constant
+    pub fn hue_rotation(degrees: f32) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self::from_rows([
+            [
+                0.213 + cos * 0.787 - sin * 0.213,
+                0.715 - cos * 0.715 - sin * 0.715,
+                0.072 - cos * 0.072 + sin * 0.928,
+            ],
+            [
+                0.213 - cos * 0.213 + sin * 0.143,
+                0.715 + cos * 0.285 + sin * 0.140,
+                0.072 - cos * 0.072 - sin * 0.283,
+            ],
+            [
+                0.213 - cos * 0.213 - sin * 0.787,
+                0.715 - cos * 0.715 + sin * 0.715,
+                0.072 + cos * 0.928 + sin * 0.072,
+            ],
+        ])
+    }
+
+    pub fn invert() -> Self {
+        let mut inverted = Self::from_rows([[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]]);
+        for row in 0..3 {
+            inverted.matrix[row * 5 + 4] = 1.0;
+        }
+        inverted
+    }
+
+    // Applies this matrix and then the other one
+    pub fn then(&self, other: &ColorMatrix) -> ColorMatrix {
+        let (a, b) = (&self.matrix, &other.matrix);
+        let mut matrix = [0.0; 20];
+        for row in 0..4 {
+            for column in 0..5 {
+                let mut value = (0..4)
+                    .map(|k| b[row * 5 + k] * a[k * 5 + column])
+                    .sum::<f32>();
+                if column == 4 {
+                    value += b[row * 5 + 4];
+                }
+                matrix[row * 5 + column] = value;
+            }
+        }
+        ColorMatrix { matrix }
+    }
+
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let input = [rgb[0], rgb[1], rgb[2], 1.0, 1.0];
+        let mut output = [0.0; 3];
+        for (row, value) in output.iter_mut().enumerate() {
+            *value = input
+                .iter()
+                .zip(&self.matrix[row * 5..row * 5 + 5])
+                .map(|(input, weight)| input * weight)
+                .sum::<f32>()
+                .clamp(0.0, 1.0);
+        }
+        output
+    }
+}
+
+impl ImageFilter for ColorMatrix {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void colorMatrix(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                uint pixel = (uint)inputImage[idx];
+                float4 rgba = (float4)(
+                    ((pixel >> 16) & 0xFF) / 255.0f,
+                    ((pixel >> 8) & 0xFF) / 255.0f,
+                    (pixel & 0xFF) / 255.0f,
+                    1.0f);
+
+                uint bytes[3];
+                for (int row = 0; row < 3; row++) {
+                    float4 weights = vload4(0, options + row * 5);
+                    float value = dot(weights, rgba) + options[row * 5 + 4];
+                    bytes[row] = (uint)(clamp(value, 0.0f, 1.0f) * 255.0f + 0.5f);
+                }
+                outputImage[idx] = (float)((bytes[0] << 16) | (bytes[1] << 8) | bytes[2]);
+            }
+            "#,
+            "colorMatrix",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        self.matrix.to_vec()
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let matrix = ColorMatrix::new(options[..20].try_into().expect("Matrix needs 20 values"));
+        pixels
+            .iter()
+            .map(|&pixel| {
+                let pixel = pixel as u32;
+                let rgb = [16, 8, 0].map(|shift| ((pixel >> shift) & 0xFF) as f32 / 255.0);
+                let [r, g, b] = matrix.apply(rgb).map(|value| (value * 255.0 + 0.5) as u32);
+                ((r << 16) | (g << 8) | b) as f32
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    const COLOUR: [f32; 3] = [0.8, 0.4, 0.2];
+
+    #[test]
+    fn identity_and_full_saturation_keep_colours() {
+        assert!(close(ColorMatrix::identity().apply(COLOUR), COLOUR));
+        assert!(close(ColorMatrix::saturation(1.0).apply(COLOUR), COLOUR));
+        assert!(close(ColorMatrix::hue_rotation(0.0).apply(COLOUR), COLOUR));
+    }
+
+    #[test]
+    fn grayscale_and_zero_saturation_are_gray() {
+        let [r, g, b] = ColorMatrix::grayscale(GrayscaleWeights::Rec601).apply(COLOUR);
+        assert!((r - (0.299 * 0.8 + 0.587 * 0.4 + 0.114 * 0.2)).abs() < 1e-5);
+        assert_eq!((r, r), (g, b));
+
+        let [r, g, b] = ColorMatrix::saturation(0.0).apply(COLOUR);
+        assert!((r - g).abs() < 1e-5 && (g - b).abs() < 1e-5);
+    }
+
+    #[test]
+    fn channel_swap_moves_channels() {
+        let swap = ColorMatrix::channel_swap(Channel::Blue, Channel::Red, Channel::Green);
+        assert!(close(swap.apply(COLOUR), [0.2, 0.8, 0.4]));
+    }
+
+    #[test]
+    fn invert_twice_is_identity() {
+        let invert = ColorMatrix::invert();
+        assert!(close(invert.apply(COLOUR), [0.2, 0.6, 0.8]));
+        assert!(close(invert.then(&invert).apply(COLOUR), COLOUR));
+    }
+
+    #[test]
+    fn then_applies_in_order() {
+        let (sepia, swap) = (
+            ColorMatrix::sepia(),
+            ColorMatrix::channel_swap(Channel::Green, Channel::Green, Channel::Green),
+        );
+        let expected = swap.apply(sepia.apply(COLOUR));
+        assert!(close(sepia.then(&swap).apply(COLOUR), expected));
+    }
+
+    #[test]
+    fn results_are_clamped() {
+        let bright = ColorMatrix::channel_mixer([2.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]);
+        assert!(close(bright.apply(COLOUR), [1.0, 0.4, 0.0]));
+    }
+
+    #[test]
+    fn packed_pixels_round_trip() {
+        let matrix = ColorMatrix::invert();
+        let options = matrix.compute_options(&[], (1, 1));
+        let output = matrix.process_cpu(&[0x336699 as f32], &options, (1, 1));
+        assert_eq!(output, vec![0xCC9966 as f32]);
+    }
+}
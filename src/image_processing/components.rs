@@ -0,0 +1,312 @@
+use super::filters::{ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+
+// Pixels above this are foreground, which covers binary masks and Canny's strong edges
+const FOREGROUND_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ComponentStats {
+    pub label: u32,
+    pub area: u32,
+    // (x, y, width, height)
+    pub bounding_box: (u32, u32, u32, u32),
+    pub centroid: (f32, f32),
+}
+
+pub struct ConnectedComponents {
+    pub connectivity: Connectivity,
+    // Components outside this area range are dropped and become background
+    pub min_area: u32,
+    pub max_area: Option<u32>,
+}
+
+impl ConnectedComponents {
+    pub fn new(connectivity: Connectivity) -> Self {
+        Self {
+            connectivity,
+            min_area: 0,
+            max_area: None,
+        }
+    }
+
+    // Label image with 0 for background and components numbered from 1 in scan order
+    pub fn label(&self, pixels: &[f32], dimensions: (u32, u32)) -> (Vec<u32>, Vec<ComponentStats>) {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let neighbours: &[(isize, isize)] = match self.connectivity {
+            Connectivity::Four => &[(-1, 0), (0, -1)],
+            Connectivity::Eight => &[(-1, 0), (-1, -1), (0, -1), (1, -1)],
+        };
+
+        // First pass assigns provisional labels and records which ones touch
+        let mut parents: Vec<usize> = vec![0];
+        let mut provisional = vec![0usize; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                if pixels[index] <= FOREGROUND_THRESHOLD {
+                    continue;
+                }
+
+                let mut label = 0;
+                for &(dx, dy) in neighbours {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    if nx < 0 || ny < 0 || nx >= width as isize {
+                        continue;
+                    }
+                    let neighbour = provisional[ny as usize * width + nx as usize];
+                    if neighbour == 0 {
+                        continue;
+                    }
+                    if label == 0 {
+                        label = find(&mut parents, neighbour);
+                    } else {
+                        label = union(&mut parents, label, neighbour);
+                    }
+                }
+                if label == 0 {
+                    parents.push(parents.len());
+                    label = parents.len() - 1;
+                }
+                provisional[index] = label;
+            }
+        }
+
+        // Second pass resolves the equivalences and gathers the statistics
+        let mut roots = vec![usize::MAX; parents.len()];
+        let mut stats: Vec<(u32, [usize; 4], [f64; 2])> = Vec::new();
+        let mut labels = vec![0u32; width * height];
+        for (index, &label) in provisional.iter().enumerate() {
+            if label == 0 {
+                continue;
+            }
+            let root = find(&mut parents, label);
+            if roots[root] == usize::MAX {
+                roots[root] = stats.len();
+                stats.push((0, [usize::MAX, usize::MAX, 0, 0], [0.0, 0.0]));
+            }
+
+            let component = roots[root];
+            let (x, y) = (index % width, index / width);
+            let (area, bounds, sums) = &mut stats[component];
+            *area += 1;
+            *bounds = [
+                bounds[0].min(x),
+                bounds[1].min(y),
+                bounds[2].max(x),
+                bounds[3].max(y),
+            ];
+            sums[0] += x as f64;
+            sums[1] += y as f64;
+            labels[index] = component as u32 + 1;
+        }
+
+        // Drop components outside the size range and renumber the rest
+        let max_area = self.max_area.unwrap_or(u32::MAX);
+        let mut renumbered = vec![0u32; stats.len() + 1];
+        let mut components = Vec::new();
+        for (component, (area, bounds, sums)) in stats.into_iter().enumerate() {
+            if area < self.min_area || area > max_area {
+                continue;
+            }
+            let label = components.len() as u32 + 1;
+            renumbered[component + 1] = label;
+            components.push(ComponentStats {
+                label,
+                area,
+                bounding_box: (
+                    bounds[0] as u32,
+                    bounds[1] as u32,
+                    (bounds[2] - bounds[0] + 1) as u32,
+                    (bounds[3] - bounds[1] + 1) as u32,
+                ),
+                centroid: (
+                    (sums[0] / area as f64) as f32,
+                    (sums[1] / area as f64) as f32,
+                ),
+            });
+        }
+        labels
+            .iter_mut()
+            .for_each(|label| *label = renumbered[*label as usize]);
+
+        (labels, components)
+    }
+}
+
+impl ImageFilter for ConnectedComponents {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void labelColors(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                int label = (int)options[1 + idx];
+                if (label == 0) {
+                    outputImage[idx] = 0.0f;
+                    return;
+                }
+
+                // Golden ratio steps keep neighbouring labels far apart in hue
+                float whole;
+                float hue = fract(label * 0.618034f, &whole) * 6.0f;
+                float saturation = 0.65f, value = 0.95f;
+                float3 rgb = clamp(fabs(fmod(hue + (float3)(0.0f, 4.0f, 2.0f), 6.0f) - 3.0f) - 1.0f, 0.0f, 1.0f);
+                rgb = value * (1.0f - saturation + saturation * rgb);
+
+                uint3 bytes = convert_uint3(rgb * 255.0f + 0.5f);
+                outputImage[idx] = (float)((bytes.x << 16) | (bytes.y << 8) | bytes.z);
+            }
+            "#,
+            "labelColors",
+        ))
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let grayscale = ImageConverter::convert_rgb_to_grayscale(
+            &ImageConverter::convert_packed_to_rgb(pixels),
+        );
+        let (labels, components) = self.label(&grayscale, dimensions);
+
+        let mut options = vec![components.len() as f32];
+        options.extend(labels.iter().map(|&label| label as f32));
+        options
+    }
+
+    // Packed so that every component can get its own colour
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        options[1..1 + pixels.len()]
+            .iter()
+            .map(|&label| {
+                if label == 0.0 {
+                    return 0.0;
+                }
+
+                let hue = (label * 0.618034).fract() * 6.0;
+                let (saturation, value) = (0.65, 0.95);
+                let channel = |offset: f32| {
+                    let rgb = (((hue + offset) % 6.0) - 3.0).abs() - 1.0;
+                    let rgb = value * (1.0 - saturation + saturation * rgb.clamp(0.0, 1.0));
+                    (rgb * 255.0 + 0.5) as u32
+                };
+                ((channel(0.0) << 16) | (channel(4.0) << 8) | channel(2.0)) as f32
+            })
+            .collect()
+    }
+}
+
+fn find(parents: &mut [usize], mut label: usize) -> usize {
+    while parents[label] != label {
+        // Path halving
+        parents[label] = parents[parents[label]];
+        label = parents[label];
+    }
+    label
+}
+
+// Joins two sets under the smaller root and returns it
+fn union(parents: &mut [usize], a: usize, b: usize) -> usize {
+    let (a, b) = (find(parents, a), find(parents, b));
+    let (root, child) = if a < b { (a, b) } else { (b, a) };
+    parents[child] = root;
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn image(rows: &[&str]) -> (Vec<f32>, (u32, u32)) {
+        let pixels = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| (c == '#') as i32 as f32))
+            .collect();
+        (pixels, (rows[0].len() as u32, rows.len() as u32))
+    }
+
+    #[test]
+    fn diagonal_pixels_connect_only_with_eight_connectivity() {
+        let (pixels, dimensions) = image(&["#..", ".#.", "..#"]);
+        let (_, four) = ConnectedComponents::new(Connectivity::Four).label(&pixels, dimensions);
+        let (_, eight) = ConnectedComponents::new(Connectivity::Eight).label(&pixels, dimensions);
+        assert_eq!(four.len(), 3);
+        assert_eq!(eight.len(), 1);
+    }
+
+    #[test]
+    fn branches_that_meet_later_share_a_label() {
+        // The two arms of the U get different provisional labels
+        let (pixels, dimensions) = image(&["#...#", "#...#", "#####"]);
+        let (labels, components) =
+            ConnectedComponents::new(Connectivity::Four).label(&pixels, dimensions);
+
+        assert_eq!(components.len(), 1);
+        assert_eq!(labels[0], labels[4]);
+        assert_eq!(
+            components[0],
+            ComponentStats {
+                label: 1,
+                area: 9,
+                bounding_box: (0, 0, 5, 3),
+                centroid: (2.0, 12.0 / 9.0),
+            }
+        );
+    }
+
+    #[test]
+    fn area_limits_drop_components_and_renumber() {
+        let (pixels, dimensions) = image(&["#.##.###", "........"]);
+        let filter = ConnectedComponents {
+            min_area: 2,
+            max_area: Some(2),
+            ..ConnectedComponents::new(Connectivity::Eight)
+        };
+        let (labels, components) = filter.label(&pixels, dimensions);
+
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].label, 1);
+        assert_eq!(&labels[..8], &[0, 0, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn components_get_distinct_colours() {
+        let (pixels, dimensions) = image(&["#.#", "...", "#.#"]);
+        let packed: Vec<f32> = pixels.iter().map(|&p| p * 0xFFFFFF as f32).collect();
+        let output = run(
+            &ConnectedComponents::new(Connectivity::Four),
+            &packed,
+            dimensions,
+        );
+
+        assert_eq!(output[1], 0.0);
+        let mut colours = vec![output[0], output[2], output[6], output[8]];
+        colours.dedup();
+        assert_eq!(colours.len(), 4);
+        assert!(colours.iter().all(|&colour| colour != 0.0));
+    }
+}
@@ -0,0 +1,306 @@
+use super::error::{check_size, FilterError};
+use super::filters::{ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+use super::image_processor::{Backend, ImageProcessor};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    // The overlay replaces the input, so the mask and opacity act as its alpha
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    SoftLight,
+    Difference,
+    // Clipped at white
+    Add,
+}
+
+enum Overlay {
+    Image(Vec<u32>, (u32, u32)),
+    // Runs on the input, e.g. a Canny stage whose edges are screened onto the original
+    Filter(Box<dyn ImageFilter>, Backend),
+}
+
+// Composites another image over the input
+pub struct Composite {
+    // Checked against its dimensions in `new`, images of any other size pass through unchanged
+    overlay: Overlay,
+    pub mode: BlendMode,
+    pub opacity: f32,
+    // 0 to 1, scales the opacity at each pixel. Checked in `with_mask` and ignored for images of
+    // any other size
+    mask: Option<(Vec<f32>, (u32, u32))>,
+}
+
+impl BlendMode {
+    // Blends one channel of the overlay onto the base, both 0 to 1
+    pub fn apply(&self, base: f32, overlay: f32) -> f32 {
+        let (a, b) = (base, overlay);
+        match self {
+            BlendMode::Normal => b,
+            BlendMode::Multiply => a * b,
+            BlendMode::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+            BlendMode::Overlay => {
+                if a < 0.5 {
+                    2.0 * a * b
+                } else {
+                    1.0 - 2.0 * (1.0 - a) * (1.0 - b)
+                }
+            }
+            // W3C compositing formula
+            BlendMode::SoftLight => {
+                if b <= 0.5 {
+                    a - (1.0 - 2.0 * b) * a * (1.0 - a)
+                } else {
+                    let d = if a <= 0.25 {
+                        ((16.0 * a - 12.0) * a + 4.0) * a
+                    } else {
+                        a.sqrt()
+                    };
+                    a + (2.0 * b - 1.0) * (d - a)
+                }
+            }
+            BlendMode::Difference => (a - b).abs(),
+            BlendMode::Add => (a + b).min(1.0),
+        }
+    }
+}
+
+impl Composite {
+    pub fn new(
+        overlay: Vec<u32>,
+        dimensions: (u32, u32),
+        mode: BlendMode,
+    ) -> Result<Self, FilterError> {
+        check_size("Overlay", overlay.len(), dimensions)?;
+        Ok(Self {
+            overlay: Overlay::Image(overlay, dimensions),
+            mode,
+            opacity: 1.0,
+            mask: None,
+        })
+    }
+
+    // Composites the output of the filter onto its own input, which passes through unchanged
+    // if the filter resizes it
+    pub fn filtered(filter: Box<dyn ImageFilter>, mode: BlendMode) -> Self {
+        Self {
+            overlay: Overlay::Filter(filter, Backend::available()),
+            mode,
+            opacity: 1.0,
+            mask: None,
+        }
+    }
+
+    // Backend the filtered overlay runs on, image overlays have nothing to run
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        if let Overlay::Filter(_, overlay_backend) = &mut self.overlay {
+            *overlay_backend = backend;
+        }
+        self
+    }
+
+    pub fn with_mask(
+        mut self,
+        mask: Vec<f32>,
+        dimensions: (u32, u32),
+    ) -> Result<Self, FilterError> {
+        check_size("Mask", mask.len(), dimensions)?;
+        self.mask = Some((mask, dimensions));
+        Ok(self)
+    }
+
+    // Overlay for an input of the given size, None if it cannot be composited onto it
+    fn overlay(&self, pixels: &[f32], dimensions: (u32, u32)) -> Option<Vec<u32>> {
+        match &self.overlay {
+            Overlay::Image(overlay, overlay_dimensions) => {
+                (*overlay_dimensions == dimensions).then(|| overlay.clone())
+            }
+            Overlay::Filter(filter, backend) => {
+                if filter.output_dimensions(dimensions) != dimensions {
+                    return None;
+                }
+                let input = ImageConverter::convert_packed_to_rgb(pixels);
+                let processor = ImageProcessor::new(&input, dimensions, &[]).with_backend(*backend);
+                Some(processor.process_filter(filter.as_ref()))
+            }
+        }
+    }
+}
+
+impl ImageFilter for Composite {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float blendChannel(int mode, float a, float b) {
+                switch (mode) {
+                    case 0: return b;
+                    case 1: return a * b;
+                    case 2: return 1.0f - (1.0f - a) * (1.0f - b);
+                    case 3: return a < 0.5f ? 2.0f * a * b : 1.0f - 2.0f * (1.0f - a) * (1.0f - b);
+                    case 4: {
+                        if (b <= 0.5f)
+                            return a - (1.0f - 2.0f * b) * a * (1.0f - a);
+                        float d = a <= 0.25f ? ((16.0f * a - 12.0f) * a + 4.0f) * a : sqrt(a);
+                        return a + (2.0f * b - 1.0f) * (d - a);
+                    }
+                    case 5: return fabs(a - b);
+                    default: return min(a + b, 1.0f);
+                }
+            }
+
+            __kernel void composite(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                int count = width * height;
+                int mode = (int)options[0];
+                float weight = clamp(options[1] * options[2 + count + idx], 0.0f, 1.0f);
+
+                uint base = (uint)inputImage[idx];
+                uint overlay = (uint)options[2 + idx];
+                uint bytes[3];
+                for (int channel = 0; channel < 3; channel++) {
+                    int shift = 16 - channel * 8;
+                    float a = ((base >> shift) & 0xFF) / 255.0f;
+                    float b = ((overlay >> shift) & 0xFF) / 255.0f;
+                    float value = a + (blendChannel(mode, a, b) - a) * weight;
+                    bytes[channel] = (uint)(clamp(value, 0.0f, 1.0f) * 255.0f + 0.5f);
+                }
+                outputImage[idx] = (float)((bytes[0] << 16) | (bytes[1] << 8) | bytes[2]);
+            }
+            "#,
+            "composite",
+        ))
+    }
+
+    // The overlay as packed pixels followed by the mask, all ones when there is none. An overlay
+    // that does not fit is left out by zeroing the opacity
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let Some(overlay) = self.overlay(pixels, dimensions) else {
+            let mut options = vec![self.mode as i32 as f32, 0.0];
+            options.resize(2 + 2 * pixels.len(), 0.0);
+            return options;
+        };
+
+        let mut options = vec![self.mode as i32 as f32, self.opacity];
+        options.extend(ImageConverter::convert_rgb_to_packed(&overlay));
+        match &self.mask {
+            Some((mask, mask_dimensions)) if *mask_dimensions == dimensions => options.extend(mask),
+            _ => options.extend(std::iter::repeat_n(1.0, pixels.len())),
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let count = pixels.len();
+        let (overlay, mask) = options[2..2 + 2 * count].split_at(count);
+
+        pixels
+            .iter()
+            .zip(overlay)
+            .zip(mask)
+            .map(|((&base, &overlay), &mask)| {
+                let weight = (options[1] * mask).clamp(0.0, 1.0);
+                let (base, overlay) = (base as u32, overlay as u32);
+                let [r, g, b] = [16, 8, 0].map(|shift| {
+                    let a = ((base >> shift) & 0xFF) as f32 / 255.0;
+                    let b = ((overlay >> shift) & 0xFF) as f32 / 255.0;
+                    let value = a + (self.mode.apply(a, b) - a) * weight;
+                    (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u32
+                });
+                ((r << 16) | (g << 8) | b) as f32
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processing::filters::GaussianBlur;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    #[test]
+    fn blend_formulas() {
+        let close = |mode: BlendMode, a: f32, b: f32, expected: f32| {
+            assert!((mode.apply(a, b) - expected).abs() < 1e-6, "{mode:?}");
+        };
+        close(BlendMode::Normal, 0.2, 0.7, 0.7);
+        close(BlendMode::Multiply, 0.5, 0.4, 0.2);
+        close(BlendMode::Screen, 0.5, 0.4, 0.7);
+        close(BlendMode::Overlay, 0.25, 0.4, 0.2);
+        close(BlendMode::Overlay, 0.75, 0.4, 0.7);
+        close(BlendMode::SoftLight, 0.5, 0.5, 0.5);
+        close(BlendMode::SoftLight, 0.25, 1.0, 0.5);
+        close(BlendMode::Difference, 0.2, 0.7, 0.5);
+        close(BlendMode::Add, 0.6, 0.7, 1.0);
+    }
+
+    #[test]
+    fn neutral_overlays_leave_the_base() {
+        for a in [0.0, 0.3, 0.8, 1.0] {
+            assert_eq!(BlendMode::Multiply.apply(a, 1.0), a);
+            assert_eq!(BlendMode::Screen.apply(a, 0.0), a);
+            assert_eq!(BlendMode::Difference.apply(a, 0.0), a);
+            assert_eq!(BlendMode::SoftLight.apply(a, 0.5), a);
+        }
+    }
+
+    #[test]
+    fn opacity_and_mask_mix_with_the_base() {
+        let mut composite = Composite::new(vec![0xFFFFFF; 2], (2, 1), BlendMode::Normal)
+            .unwrap()
+            .with_mask(vec![1.0, 0.0], (2, 1))
+            .unwrap();
+        composite.opacity = 0.5;
+
+        let output = run(&composite, &[0.0; 2], (2, 1));
+        assert_eq!(output, vec![0x808080 as f32, 0.0]);
+    }
+
+    #[test]
+    fn inputs_must_match_their_dimensions() {
+        assert!(Composite::new(vec![0; 5], (3, 2), BlendMode::Add).is_err());
+        let composite = Composite::new(vec![0; 6], (3, 2), BlendMode::Add).unwrap();
+        assert!(composite.with_mask(vec![1.0; 4], (3, 2)).is_err());
+    }
+
+    #[test]
+    fn other_sizes_pass_through() {
+        let composite = Composite::new(vec![0xFFFFFF; 6], (3, 2), BlendMode::Normal).unwrap();
+        let pixels = vec![0x123456 as f32; 4];
+        assert_eq!(run(&composite, &pixels, (2, 2)), pixels);
+    }
+
+    #[test]
+    fn filtered_overlay_runs_on_the_input() {
+        // Blurring a flat image changes nothing, so the difference is black
+        let composite = Composite::filtered(Box::new(GaussianBlur), BlendMode::Difference)
+            .with_backend(Backend::Cpu);
+        let output = run(&composite, &[0x336699 as f32; 25], (5, 5));
+        for pixel in output {
+            let pixel = pixel as u32;
+            assert!([16, 8, 0].iter().all(|shift| (pixel >> shift) & 0xFF <= 1));
+        }
+    }
+}
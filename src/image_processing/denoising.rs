@@ -0,0 +1,328 @@
+use super::filters::{ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DenoiseMode {
+    Grayscale,
+    // Patches are compared in YCbCr with separate strengths for luma and chroma
+    Color,
+}
+
+pub struct NonLocalMeans {
+    // Odd side lengths of the compared patches and of the area searched for them
+    pub patch_size: u32,
+    pub search_window: u32,
+    // Filtering strength, larger values smooth more
+    pub h: f32,
+    pub chroma_h: f32,
+    pub mode: DenoiseMode,
+}
+
+impl NonLocalMeans {
+    pub fn new(h: f32, mode: DenoiseMode) -> Self {
+        Self {
+            patch_size: 7,
+            search_window: 21,
+            h,
+            chroma_h: h,
+            mode,
+        }
+    }
+
+    fn radii(&self) -> (usize, usize) {
+        (
+            (self.patch_size.max(1) | 1) as usize / 2,
+            (self.search_window.max(3) | 1) as usize / 2,
+        )
+    }
+}
+
+impl ImageFilter for NonLocalMeans {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float3 toYCbCr(float packed) {
+                uint pixel = (uint)packed;
+                float r = ((pixel >> 16) & 0xFF) / 255.0f;
+                float g = ((pixel >> 8) & 0xFF) / 255.0f;
+                float b = (pixel & 0xFF) / 255.0f;
+                float y = 0.299f * r + 0.587f * g + 0.114f * b;
+                return (float3)(y, 0.564f * (b - y), 0.713f * (r - y));
+            }
+
+            float3 fetch(__global const float* image, int width, int height, int x, int y, int color) {
+                float value = image[clamp(y, 0, height - 1) * width + clamp(x, 0, width - 1)];
+                return color ? toYCbCr(value) : (float3)(value, 0.0f, 0.0f);
+            }
+
+            uint toByte(float value) {
+                return (uint)(clamp(value, 0.0f, 1.0f) * 255.0f + 0.5f);
+            }
+
+            __kernel void nonLocalMeans(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int patchRadius = (int)options[0];
+                int searchRadius = (int)options[1];
+                float3 scale = (float3)(options[2], options[3], options[3]);
+                int color = (int)options[4];
+                float area = (2 * patchRadius + 1) * (2 * patchRadius + 1);
+
+                float3 sum = (float3)(0.0f);
+                float weights = 0.0f, maxWeight = 0.0f;
+                for (int oy = -searchRadius; oy <= searchRadius; oy++) {
+                    for (int ox = -searchRadius; ox <= searchRadius; ox++) {
+                        int nx = x + ox;
+                        int ny = y + oy;
+                        if ((ox == 0 && oy == 0) || nx < 0 || ny < 0 || nx >= width || ny >= height)
+                            continue;
+
+                        float distance = 0.0f;
+                        for (int ky = -patchRadius; ky <= patchRadius; ky++) {
+                            for (int kx = -patchRadius; kx <= patchRadius; kx++) {
+                                float3 difference =
+                                    fetch(inputImage, width, height, x + kx, y + ky, color) -
+                                    fetch(inputImage, width, height, nx + kx, ny + ky, color);
+                                distance += dot(difference * difference, scale);
+                            }
+                        }
+
+                        float weight = exp(-distance / area);
+                        sum += weight * fetch(inputImage, width, height, nx, ny, color);
+                        weights += weight;
+                        maxWeight = max(maxWeight, weight);
+                    }
+                }
+
+                // The centre would always match itself perfectly, so it gets the best other weight
+                float centre = weights > 0.0f ? maxWeight : 1.0f;
+                float3 value = (sum + centre * fetch(inputImage, width, height, x, y, color)) / (weights + centre);
+
+                int idx = y * width + x;
+                if (!color) {
+                    outputImage[idx] = value.x;
+                    return;
+                }
+
+                uint r = toByte(value.x + 1.403f * value.z);
+                uint g = toByte(value.x - 0.344f * value.y - 0.714f * value.z);
+                uint b = toByte(value.x + 1.773f * value.y);
+                outputImage[idx] = (float)((r << 16) | (g << 8) | b);
+            }
+            "#,
+            "nonLocalMeans",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let (patch_radius, search_radius) = self.radii();
+        vec![
+            patch_radius as f32,
+            search_radius as f32,
+            1.0 / self.h.max(f32::EPSILON).powi(2),
+            1.0 / self.chroma_h.max(f32::EPSILON).powi(2),
+            (self.mode == DenoiseMode::Color) as i32 as f32,
+        ]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        match self.mode {
+            DenoiseMode::Grayscale => ColorMode::Grayscale,
+            DenoiseMode::Color => ColorMode::Packed,
+        }
+    }
+
+    // Patch distances for one search offset at a time come from an integral image of the
+    // squared differences, which makes the cost independent of the patch size
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let (patch_radius, search_radius) = (options[0] as isize, options[1] as isize);
+        let color = options[4] != 0.0;
+        let scale = [options[2], options[3], options[3]];
+        let area = ((2 * patch_radius + 1) * (2 * patch_radius + 1)) as f64;
+
+        let channels = if color {
+            let (y, cb, cr) = ImageConverter::convert_rgb_to_ycbcr(
+                &ImageConverter::convert_packed_to_rgb(pixels),
+            );
+            vec![y, cb, cr]
+        } else {
+            vec![pixels.to_vec()]
+        };
+        let value = |channel: &[f32], x: isize, y: isize| {
+            channel[(y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize]
+        };
+
+        // Differences cover every pixel that a patch around an image pixel can reach
+        let (table_width, table_height) = (width + 2 * patch_radius, height + 2 * patch_radius);
+        let stride = (table_width + 1) as usize;
+        let mut table = vec![0.0f64; stride * (table_height + 1) as usize];
+
+        let count = (width * height) as usize;
+        let mut sums = vec![[0.0f64; 3]; count];
+        let mut weights = vec![0.0f64; count];
+        let mut max_weights = vec![0.0f64; count];
+
+        for oy in -search_radius..=search_radius {
+            for ox in -search_radius..=search_radius {
+                if ox == 0 && oy == 0 {
+                    continue;
+                }
+
+                for v in 0..table_height {
+                    let y = v - patch_radius;
+                    for u in 0..table_width {
+                        let x = u - patch_radius;
+                        let difference: f32 = channels
+                            .iter()
+                            .zip(scale)
+                            .map(|(channel, scale)| {
+                                let d = value(channel, x, y) - value(channel, x + ox, y + oy);
+                                scale * d * d
+                            })
+                            .sum();
+
+                        let i = (v as usize + 1) * stride + u as usize + 1;
+                        table[i] = difference as f64 + table[i - 1] + table[i - stride]
+                            - table[i - stride - 1];
+                    }
+                }
+
+                for y in 0..height {
+                    let ny = y + oy;
+                    if ny < 0 || ny >= height {
+                        continue;
+                    }
+                    for x in 0..width {
+                        let nx = x + ox;
+                        if nx < 0 || nx >= width {
+                            continue;
+                        }
+
+                        let (x0, y0) = (x as usize, y as usize);
+                        let (x1, y1) = (
+                            x0 + 2 * patch_radius as usize + 1,
+                            y0 + 2 * patch_radius as usize + 1,
+                        );
+                        let distance = table[y1 * stride + x1]
+                            - table[y0 * stride + x1]
+                            - table[y1 * stride + x0]
+                            + table[y0 * stride + x0];
+                        let weight = (-distance / area).exp();
+
+                        let idx = (y * width + x) as usize;
+                        let neighbour = (ny * width + nx) as usize;
+                        for (sum, channel) in sums[idx].iter_mut().zip(&channels) {
+                            *sum += weight * channel[neighbour] as f64;
+                        }
+                        weights[idx] += weight;
+                        max_weights[idx] = max_weights[idx].max(weight);
+                    }
+                }
+            }
+        }
+
+        (0..count)
+            .map(|idx| {
+                let own = if weights[idx] > 0.0 {
+                    max_weights[idx]
+                } else {
+                    1.0
+                };
+                let mut result = [0.0f32; 3];
+                for (c, channel) in channels.iter().enumerate() {
+                    result[c] =
+                        ((sums[idx][c] + own * channel[idx] as f64) / (weights[idx] + own)) as f32;
+                }
+
+                if !color {
+                    return result[0];
+                }
+
+                let [y, cb, cr] = result;
+                let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+                let r = to_byte(y + 1.403 * cr);
+                let g = to_byte(y - 0.344 * cb - 0.714 * cr);
+                let b = to_byte(y + 1.773 * cb);
+                ((r << 16) | (g << 8) | b) as f32
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn small(h: f32, mode: DenoiseMode) -> NonLocalMeans {
+        NonLocalMeans {
+            patch_size: 3,
+            search_window: 7,
+            ..NonLocalMeans::new(h, mode)
+        }
+    }
+
+    // Deterministic noise of about +-0.05
+    fn noise(i: usize) -> f32 {
+        ((i * 7919 % 101) as f32 / 100.0 - 0.5) * 0.1
+    }
+
+    fn variance(values: &[f32]) -> f32 {
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32
+    }
+
+    #[test]
+    fn flat_image_is_unchanged() {
+        let output = run(&small(0.1, DenoiseMode::Grayscale), &[0.4; 64], (8, 8));
+        assert!(output.iter().all(|&value| (value - 0.4).abs() < 1e-6));
+    }
+
+    #[test]
+    fn noise_is_reduced() {
+        let pixels: Vec<f32> = (0..256).map(|i| 0.5 + noise(i)).collect();
+        let output = run(&small(0.1, DenoiseMode::Grayscale), &pixels, (16, 16));
+        assert!(variance(&output) < variance(&pixels) / 4.0);
+    }
+
+    #[test]
+    fn edges_survive_denoising() {
+        let pixels: Vec<f32> = (0..256)
+            .map(|i| if i % 16 < 8 { 0.2 } else { 0.8 } + noise(i))
+            .collect();
+        let output = run(&small(0.1, DenoiseMode::Grayscale), &pixels, (16, 16));
+        for (i, value) in output.iter().enumerate() {
+            let expected = if i % 16 < 8 { 0.2 } else { 0.8 };
+            assert!((value - expected).abs() < 0.1, "{i} {value}");
+        }
+    }
+
+    #[test]
+    fn colour_mode_keeps_a_flat_colour() {
+        let pixels = vec![0x3070B0 as f32; 64];
+        let output = run(&small(0.1, DenoiseMode::Color), &pixels, (8, 8));
+        for value in output {
+            let (r, g, b) = (
+                (value as u32 >> 16) & 0xFF,
+                (value as u32 >> 8) & 0xFF,
+                value as u32 & 0xFF,
+            );
+            assert!(r.abs_diff(0x30) <= 1 && g.abs_diff(0x70) <= 1 && b.abs_diff(0xB0) <= 1);
+        }
+    }
+}
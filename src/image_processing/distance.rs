@@ -0,0 +1,401 @@
+use super::filters::{ColorMode, ImageFilter};
+
+// Stands in for "no feature pixel reachable" while staying finite on the GPU
+const FAR: f32 = 1e20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChamferMask {
+    CityBlock,
+    Chessboard,
+    // 3 for straight steps and 4 for diagonal ones
+    ThreeFour,
+    // Adds knight moves weighted 11 to the 5-7 mask
+    FiveSevenEleven,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DistanceMetric {
+    Euclidean,
+    Chamfer(ChamferMask),
+}
+
+pub struct DistanceTransform {
+    pub metric: DistanceMetric,
+    // Measure from background pixels to the nearest foreground one, as for edge maps
+    pub invert: bool,
+    // Distance in pixels that maps to white in the output
+    pub max_distance: f32,
+}
+
+impl ChamferMask {
+    // Neighbours preceding a pixel in raster order, with weights in pixel units
+    pub fn entries(&self) -> Vec<(i32, i32, f32)> {
+        match self {
+            ChamferMask::CityBlock => vec![(-1, 0, 1.0), (0, -1, 1.0)],
+            ChamferMask::Chessboard => {
+                vec![(-1, 0, 1.0), (-1, -1, 1.0), (0, -1, 1.0), (1, -1, 1.0)]
+            }
+            ChamferMask::ThreeFour => vec![
+                (-1, 0, 1.0),
+                (-1, -1, 4.0 / 3.0),
+                (0, -1, 1.0),
+                (1, -1, 4.0 / 3.0),
+            ],
+            ChamferMask::FiveSevenEleven => vec![
+                (-1, 0, 1.0),
+                (-1, -1, 7.0 / 5.0),
+                (0, -1, 1.0),
+                (1, -1, 7.0 / 5.0),
+                (-2, -1, 11.0 / 5.0),
+                (-1, -2, 11.0 / 5.0),
+                (1, -2, 11.0 / 5.0),
+                (2, -1, 11.0 / 5.0),
+            ],
+        }
+    }
+
+    // Smallest wavefront slope for which every mask neighbour is finished before the pixel
+    fn wavefront_step(&self) -> u32 {
+        self.entries()
+            .iter()
+            .filter(|&&(_, dy, _)| dy < 0)
+            .map(|&(dx, dy, _)| (dx.max(0) / -dy) as u32 + 1)
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+impl DistanceTransform {
+    pub fn new(metric: DistanceMetric) -> Self {
+        Self {
+            metric,
+            invert: false,
+            max_distance: 32.0,
+        }
+    }
+
+    // Distance in pixels from every pixel to the nearest feature pixel, infinite if there is none
+    pub fn compute(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let features: Vec<bool> = pixels
+            .iter()
+            .map(|&pixel| (pixel >= 0.5) == self.invert)
+            .collect();
+
+        let distances = match self.metric {
+            DistanceMetric::Euclidean => {
+                let mut squared: Vec<f32> = features
+                    .iter()
+                    .map(|&feature| if feature { 0.0 } else { FAR })
+                    .collect();
+
+                // Exact squared distances are separable into a column and a row pass
+                let mut column = vec![0.0; height];
+                for x in 0..width {
+                    for y in 0..height {
+                        column[y] = squared[y * width + x];
+                    }
+                    for (y, value) in lower_envelope(&column).into_iter().enumerate() {
+                        squared[y * width + x] = value;
+                    }
+                }
+                for row in squared.chunks_mut(width) {
+                    let envelope = lower_envelope(row);
+                    row.copy_from_slice(&envelope);
+                }
+                squared.iter().map(|value| value.sqrt()).collect()
+            }
+            DistanceMetric::Chamfer(mask) => {
+                let entries = mask.entries();
+                let mut distances: Vec<f32> = features
+                    .iter()
+                    .map(|&feature| if feature { 0.0 } else { FAR })
+                    .collect();
+
+                // Forward scan over the preceding neighbours, then backward over the mirrored ones
+                for direction in [1, -1] {
+                    for i in 0..width * height {
+                        let i = if direction == 1 {
+                            i
+                        } else {
+                            width * height - 1 - i
+                        };
+                        let (x, y) = ((i % width) as i32, (i / width) as i32);
+                        for &(dx, dy, weight) in &entries {
+                            let (nx, ny) = (x + direction * dx, y + direction * dy);
+                            if nx >= 0 && ny >= 0 && nx < width as i32 && ny < height as i32 {
+                                let neighbour = distances[ny as usize * width + nx as usize];
+                                distances[i] = distances[i].min(neighbour + weight);
+                            }
+                        }
+                    }
+                }
+                distances
+            }
+        };
+
+        distances
+            .into_iter()
+            .map(|distance| {
+                if distance >= FAR.sqrt() {
+                    f32::INFINITY
+                } else {
+                    distance
+                }
+            })
+            .collect()
+    }
+}
+
+impl ImageFilter for DistanceTransform {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            #define FAR 1e20f
+
+            int isFeature(__global const float* image, int idx, int invert) {
+                return (image[idx] >= 0.5f) == invert;
+            }
+
+            // Brute force lower envelope along a column, then a row, of the squared distances
+            void euclidean(
+                __global float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                int width, int height, int pass) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                if (pass == 0) {
+                    float best = FAR;
+                    for (int ny = 0; ny < height; ny++) {
+                        if (isFeature(inputImage, ny * width + x, (int)options[1]))
+                            best = min(best, (float)((ny - y) * (ny - y)));
+                    }
+                    outputImage[idx] = best;
+                } else if (pass == 1) {
+                    inputImage[idx] = outputImage[idx];
+                } else {
+                    float best = FAR;
+                    for (int nx = 0; nx < width; nx++)
+                        best = min(best, (nx - x) * (nx - x) + inputImage[y * width + nx]);
+                    outputImage[idx] = min(sqrt(best) * options[2], 1.0f);
+                }
+            }
+
+            // Raster scans run as diagonal wavefronts, one work item per row
+            void chamfer(
+                __global float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                int width, int height, int pass) {
+
+                int y = get_global_id(0);
+                if (y >= height)
+                    return;
+
+                int step = (int)options[3];
+                int scan = (int)options[4];
+                if (pass == 2 * scan) {
+                    for (int x = 0; x < width; x++)
+                        outputImage[y * width + x] = min(outputImage[y * width + x] * options[2], 1.0f);
+                    return;
+                }
+
+                int direction = pass < scan ? 1 : -1;
+                int x = pass % scan - step * y;
+                if (x < 0 || x >= width)
+                    return;
+                if (direction < 0) {
+                    x = width - 1 - x;
+                    y = height - 1 - y;
+                }
+
+                int idx = y * width + x;
+                float value = direction > 0
+                    ? (isFeature(inputImage, idx, (int)options[1]) ? 0.0f : FAR)
+                    : outputImage[idx];
+
+                int count = (int)options[5];
+                for (int i = 0; i < count; i++) {
+                    int nx = x + direction * (int)options[6 + i * 3];
+                    int ny = y + direction * (int)options[7 + i * 3];
+                    if (nx >= 0 && ny >= 0 && nx < width && ny < height)
+                        value = min(value, outputImage[ny * width + nx] + options[8 + i * 3]);
+                }
+                outputImage[idx] = value;
+            }
+
+            __kernel void distanceTransform(
+                __global float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height,
+                const int pass) {
+
+                if ((int)options[0] == 0)
+                    euclidean(inputImage, outputImage, options, width, height, pass);
+                else
+                    chamfer(inputImage, outputImage, options, width, height, pass);
+            }
+            "#,
+            "distanceTransform",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let mut options = vec![
+            0.0,
+            self.invert as i32 as f32,
+            1.0 / self.max_distance.max(f32::EPSILON),
+        ];
+        match self.metric {
+            DistanceMetric::Euclidean => options.extend([0.0, 0.0, 0.0]),
+            DistanceMetric::Chamfer(mask) => {
+                let step = mask.wavefront_step();
+                let entries = mask.entries();
+                options[0] = 1.0;
+                options.extend([
+                    step as f32,
+                    (dimensions.0 + step * dimensions.1.saturating_sub(1)) as f32,
+                    entries.len() as f32,
+                ]);
+                for (dx, dy, weight) in entries {
+                    options.extend([dx as f32, dy as f32, weight]);
+                }
+            }
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Grayscale
+    }
+
+    fn get_schedule(&self, dimensions: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        match self.metric {
+            DistanceMetric::Euclidean => Some((3, dimensions)),
+            DistanceMetric::Chamfer(mask) => {
+                let scan = dimensions.0 + mask.wavefront_step() * dimensions.1.saturating_sub(1);
+                Some((2 * scan + 1, (dimensions.1, 1)))
+            }
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        self.compute(pixels, dimensions)
+            .iter()
+            .map(|distance| (distance * options[2]).min(1.0))
+            .collect()
+    }
+}
+
+// One-dimensional squared distance transform of sampled values (Felzenszwalb and Huttenlocher)
+fn lower_envelope(values: &[f32]) -> Vec<f32> {
+    let n = values.len();
+    let mut vertices = vec![0usize; n];
+    let mut bounds = vec![0.0f32; n + 1];
+    let mut k = 0;
+    bounds[0] = f32::NEG_INFINITY;
+    bounds[1] = f32::INFINITY;
+
+    let intersection = |a: usize, b: usize| {
+        ((values[b] + (b * b) as f32) - (values[a] + (a * a) as f32)) / (2.0 * (b - a) as f32)
+    };
+    for q in 1..n {
+        let mut s = intersection(vertices[k], q);
+        while s <= bounds[k] {
+            k -= 1;
+            s = intersection(vertices[k], q);
+        }
+        k += 1;
+        vertices[k] = q;
+        bounds[k] = s;
+        bounds[k + 1] = f32::INFINITY;
+    }
+
+    k = 0;
+    (0..n)
+        .map(|q| {
+            while bounds[k + 1] < q as f32 {
+                k += 1;
+            }
+            let offset = q as f32 - vertices[k] as f32;
+            offset * offset + values[vertices[k]]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    // Foreground everywhere except a single background pixel at the origin
+    fn one_hole() -> Vec<f32> {
+        let mut pixels = vec![1.0; 64];
+        pixels[0] = 0.0;
+        pixels
+    }
+
+    fn distance_at(metric: DistanceMetric, x: usize, y: usize) -> f32 {
+        DistanceTransform::new(metric).compute(&one_hole(), (8, 8))[y * 8 + x]
+    }
+
+    #[test]
+    fn euclidean_distances_are_exact() {
+        assert_eq!(distance_at(DistanceMetric::Euclidean, 0, 0), 0.0);
+        assert_eq!(distance_at(DistanceMetric::Euclidean, 3, 4), 5.0);
+        assert_eq!(distance_at(DistanceMetric::Euclidean, 7, 0), 7.0);
+    }
+
+    #[test]
+    fn chamfer_masks_follow_their_step_costs() {
+        let chamfer = |mask| distance_at(DistanceMetric::Chamfer(mask), 3, 4);
+        assert_eq!(chamfer(ChamferMask::CityBlock), 7.0);
+        assert_eq!(chamfer(ChamferMask::Chessboard), 4.0);
+        assert!((chamfer(ChamferMask::ThreeFour) - (3.0 * 4.0 / 3.0 + 1.0)).abs() < 1e-5);
+        // Knight moves get within a few percent of the true distance
+        assert!((chamfer(ChamferMask::FiveSevenEleven) - 5.0).abs() < 0.25);
+    }
+
+    #[test]
+    fn no_features_is_infinitely_far() {
+        let distances =
+            DistanceTransform::new(DistanceMetric::Euclidean).compute(&[1.0; 9], (3, 3));
+        assert!(distances.iter().all(|distance| distance.is_infinite()));
+    }
+
+    #[test]
+    fn invert_measures_from_the_foreground() {
+        let filter = DistanceTransform {
+            invert: true,
+            ..DistanceTransform::new(DistanceMetric::Euclidean)
+        };
+        let pixels = one_hole()
+            .iter()
+            .map(|pixel| 1.0 - pixel)
+            .collect::<Vec<_>>();
+        assert_eq!(filter.compute(&pixels, (8, 8))[4 * 8 + 3], 5.0);
+    }
+
+    #[test]
+    fn output_is_scaled_by_the_maximum_distance() {
+        let filter = DistanceTransform {
+            max_distance: 10.0,
+            ..DistanceTransform::new(DistanceMetric::Euclidean)
+        };
+        let output = run(&filter, &one_hole(), (8, 8));
+        assert_eq!(output[4 * 8 + 3], 0.5);
+        assert_eq!(run(&filter, &[1.0; 4], (2, 2)), vec![1.0; 4]);
+    }
+}
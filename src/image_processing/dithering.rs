@@ -0,0 +1,698 @@
+use super::filters::{ColorMode, ImageFilter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThresholdMap {
+    // Sizes that are not a power of two are rounded up to the next one, so Bayer(3) is 4x4
+    Bayer(u32),
+    BlueNoise(u32),
+    ClusteredDot(u32),
+}
+
+pub struct OrderedDithering {
+    pub map: ThresholdMap,
+    pub levels: u32,
+    // Dither to the nearest colours of a fixed 0xRRGGBB palette instead of per channel levels
+    pub palette: Option<Vec<u32>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffusionMatrix {
+    FloydSteinberg,
+    JarvisJudiceNinke,
+    Stucki,
+    Atkinson,
+    Sierra,
+}
+
+pub struct ErrorDiffusionDithering {
+    pub matrix: DiffusionMatrix,
+    // Alternate the scan direction on every row
+    pub serpentine: bool,
+    pub levels: u32,
+}
+
+impl ThresholdMap {
+    pub fn size(&self) -> u32 {
+        match self {
+            ThresholdMap::Bayer(size) => size.next_power_of_two(),
+            ThresholdMap::BlueNoise(size) | ThresholdMap::ClusteredDot(size) => *size,
+        }
+        .max(1)
+    }
+
+    // Thresholds in (0, 1), row-major
+    pub fn thresholds(&self) -> Vec<f32> {
+        let size = self.size() as usize;
+        let ranks = match self {
+            ThresholdMap::Bayer(_) => bayer_ranks(size),
+            ThresholdMap::BlueNoise(_) => blue_noise_ranks(size),
+            ThresholdMap::ClusteredDot(_) => clustered_dot_ranks(size),
+        };
+
+        let count = ranks.len() as f32;
+        ranks
+            .iter()
+            .map(|&rank| (rank as f32 + 0.5) / count)
+            .collect()
+    }
+}
+
+impl OrderedDithering {
+    pub fn new(map: ThresholdMap) -> Self {
+        Self {
+            map,
+            levels: 2,
+            palette: None,
+        }
+    }
+}
+
+impl ImageFilter for OrderedDithering {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float3 unpackColor(float packed) {
+                uint pixel = (uint)packed;
+                return (float3)(
+                    (float)((pixel >> 16) & 0xFF),
+                    (float)((pixel >> 8) & 0xFF),
+                    (float)(pixel & 0xFF)) / 255.0f;
+            }
+
+            __kernel void orderedDithering(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                int matrixSize = (int)options[0];
+                int paletteCount = (int)options[2];
+                float threshold = options[4 + (y % matrixSize) * matrixSize + x % matrixSize];
+
+                if (paletteCount == 0) {
+                    // Round up to the next level when the fractional part reaches the threshold
+                    float steps = options[1] - 1.0f;
+                    float scaled = clamp(inputImage[idx], 0.0f, 1.0f) * steps;
+                    float base = floor(scaled);
+                    float level = base + (scaled - base >= threshold ? 1.0f : 0.0f);
+                    outputImage[idx] = min(level, steps) / steps;
+                    return;
+                }
+
+                // Palette mode works on packed pixels
+                __global const float* palette = options + 4 + matrixSize * matrixSize;
+                float3 color = unpackColor(inputImage[idx]) + (threshold - 0.5f) * options[3];
+
+                int best = 0;
+                float bestDistance = FLT_MAX;
+                for (int i = 0; i < paletteCount; i++) {
+                    float3 difference = color - unpackColor(palette[i]);
+                    float distance = dot(difference, difference);
+                    if (distance < bestDistance) {
+                        bestDistance = distance;
+                        best = i;
+                    }
+                }
+                outputImage[idx] = palette[best];
+            }
+            "#,
+            "orderedDithering",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let palette = self.palette.as_deref().unwrap_or(&[]);
+        // Roughly the distance between neighbouring colours of an evenly spread palette
+        let spread = 1.0 / (palette.len().max(1) as f32).cbrt();
+
+        let mut options = vec![
+            self.map.size() as f32,
+            self.levels.max(2) as f32,
+            palette.len() as f32,
+            spread,
+        ];
+        options.extend(self.map.thresholds());
+        options.extend(palette.iter().map(|&color| (color & 0xFFFFFF) as f32));
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        if self.palette.is_some() {
+            ColorMode::Packed
+        } else {
+            ColorMode::Rgb
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let width = dimensions.0 as usize;
+        let matrix_size = options[0] as usize;
+        let steps = options[1] - 1.0;
+        let palette_count = options[2] as usize;
+        let spread = options[3];
+        let thresholds = &options[4..4 + matrix_size * matrix_size];
+        let palette = &options[4 + matrix_size * matrix_size..][..palette_count];
+
+        pixels
+            .iter()
+            .enumerate()
+            .map(|(index, &pixel)| {
+                let (x, y) = (index % width, index / width);
+                let threshold = thresholds[(y % matrix_size) * matrix_size + x % matrix_size];
+
+                if palette_count == 0 {
+                    let scaled = pixel.clamp(0.0, 1.0) * steps;
+                    let base = scaled.floor();
+                    let level = base + if scaled - base >= threshold { 1.0 } else { 0.0 };
+                    return level.min(steps) / steps;
+                }
+
+                let color = unpack_color(pixel).map(|c| c + (threshold - 0.5) * spread);
+                palette
+                    .iter()
+                    .copied()
+                    .min_by(|&a, &b| {
+                        color_distance(color, unpack_color(a))
+                            .total_cmp(&color_distance(color, unpack_color(b)))
+                    })
+                    .unwrap_or(pixel)
+            })
+            .collect()
+    }
+}
+
+impl DiffusionMatrix {
+    // (dx, dy, weight) of every neighbour receiving a share of the error
+    pub fn entries(&self) -> Vec<(i32, i32, f32)> {
+        let (divisor, rows): (f32, [&[f32]; 3]) = match self {
+            DiffusionMatrix::FloydSteinberg => (
+                16.0,
+                [
+                    &[0.0, 0.0, 0.0, 7.0, 0.0],
+                    &[0.0, 3.0, 5.0, 1.0, 0.0],
+                    &[0.0; 5],
+                ],
+            ),
+            DiffusionMatrix::JarvisJudiceNinke => (
+                48.0,
+                [
+                    &[0.0, 0.0, 0.0, 7.0, 5.0],
+                    &[3.0, 5.0, 7.0, 5.0, 3.0],
+                    &[1.0, 3.0, 5.0, 3.0, 1.0],
+                ],
+            ),
+            DiffusionMatrix::Stucki => (
+                42.0,
+                [
+                    &[0.0, 0.0, 0.0, 8.0, 4.0],
+                    &[2.0, 4.0, 8.0, 4.0, 2.0],
+                    &[1.0, 2.0, 4.0, 2.0, 1.0],
+                ],
+            ),
+            // Atkinson only diffuses three quarters of the error
+            DiffusionMatrix::Atkinson => (
+                8.0,
+                [
+                    &[0.0, 0.0, 0.0, 1.0, 1.0],
+                    &[0.0, 1.0, 1.0, 1.0, 0.0],
+                    &[0.0, 0.0, 1.0, 0.0, 0.0],
+                ],
+            ),
+            DiffusionMatrix::Sierra => (
+                32.0,
+                [
+                    &[0.0, 0.0, 0.0, 5.0, 3.0],
+                    &[2.0, 4.0, 5.0, 4.0, 2.0],
+                    &[0.0, 2.0, 3.0, 2.0, 0.0],
+                ],
+            ),
+        };
+
+        let mut entries = Vec::new();
+        for (dy, row) in rows.iter().enumerate() {
+            for (column, &weight) in row.iter().enumerate() {
+                if weight > 0.0 {
+                    entries.push((column as i32 - 2, dy as i32, weight / divisor));
+                }
+            }
+        }
+        entries
+    }
+}
+
+impl ErrorDiffusionDithering {
+    pub fn new(matrix: DiffusionMatrix) -> Self {
+        Self {
+            matrix,
+            serpentine: false,
+            levels: 2,
+        }
+    }
+
+    // Wavefront slope: a pixel may start once the row above is this far ahead
+    pub fn wavefront_step(&self) -> u32 {
+        let reach = self
+            .matrix
+            .entries()
+            .iter()
+            .filter(|(_, dy, _)| *dy > 0)
+            .map(|(dx, _, _)| dx.unsigned_abs())
+            .max()
+            .unwrap_or(0);
+        reach + 1
+    }
+}
+
+impl ImageFilter for ErrorDiffusionDithering {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float quantize(float value, float levels) {
+                float steps = levels - 1.0f;
+                return clamp(round(value * steps) / steps, 0.0f, 1.0f);
+            }
+
+            // Processed pixels hold their residual error in the working image, so each
+            // pixel gathers error from its already processed neighbours instead of
+            // scattering it, which keeps pixels on the same wavefront independent
+            void diffusePixel(
+                __global float* image,
+                __global float* outputImage,
+                __global const float* options,
+                int x, int y, int width, int height, int serpentine) {
+
+                int count = (int)options[3];
+                int idx = y * width + x;
+                float value = image[idx];
+
+                for (int i = 0; i < count; i++) {
+                    int dx = (int)options[4 + i * 3];
+                    int dy = (int)options[5 + i * 3];
+                    float weight = options[6 + i * 3];
+
+                    int sy = y - dy;
+                    int direction = (serpentine && (sy & 1)) ? -1 : 1;
+                    int sx = x - direction * dx;
+                    if (sx >= 0 && sy >= 0 && sx < width && sy < height)
+                        value += weight * image[sy * width + sx];
+                }
+
+                float quantized = quantize(value, options[0]);
+                outputImage[idx] = quantized;
+                image[idx] = value - quantized;
+            }
+
+            __kernel void errorDiffusion(
+                __global float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height,
+                const int pass) {
+
+                int serpentine = (int)options[1];
+
+                // Reversed rows break the wavefront ordering, so a single work item scans the image
+                if (serpentine) {
+                    for (int y = 0; y < height; y++) {
+                        for (int i = 0; i < width; i++) {
+                            int x = (y & 1) ? width - 1 - i : i;
+                            diffusePixel(inputImage, outputImage, options, x, y, width, height, 1);
+                        }
+                    }
+                    return;
+                }
+
+                int y = get_global_id(0);
+                int x = pass - (int)options[2] * y;
+                if (y >= height || x < 0 || x >= width)
+                    return;
+
+                diffusePixel(inputImage, outputImage, options, x, y, width, height, 0);
+            }
+            "#,
+            "errorDiffusion",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let entries = self.matrix.entries();
+
+        let mut options = vec![
+            self.levels.max(2) as f32,
+            self.serpentine as i32 as f32,
+            self.wavefront_step() as f32,
+            entries.len() as f32,
+        ];
+        for (dx, dy, weight) in entries {
+            options.extend([dx as f32, dy as f32, weight]);
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Rgb
+    }
+
+    fn get_schedule(&self, dimensions: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        if self.serpentine {
+            Some((1, (1, 1)))
+        } else {
+            let passes = dimensions.0 + self.wavefront_step() * dimensions.1.saturating_sub(1);
+            Some((passes, (dimensions.1, 1)))
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as i32, dimensions.1 as i32);
+        let steps = options[0] - 1.0;
+        let entries = self.matrix.entries();
+
+        let mut image = pixels.to_vec();
+        let mut output = vec![0.0; pixels.len()];
+        for y in 0..height {
+            let direction = if self.serpentine && y % 2 == 1 { -1 } else { 1 };
+
+            for i in 0..width {
+                let x = if direction < 0 { width - 1 - i } else { i };
+                let idx = (y * width + x) as usize;
+
+                let value = image[idx];
+                let quantized = ((value * steps).round() / steps).clamp(0.0, 1.0);
+                output[idx] = quantized;
+
+                let error = value - quantized;
+                for &(dx, dy, weight) in &entries {
+                    let (nx, ny) = (x + direction * dx, y + dy);
+                    if nx >= 0 && ny >= 0 && nx < width && ny < height {
+                        image[(ny * width + nx) as usize] += error * weight;
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+pub fn unpack_color(packed: f32) -> [f32; 3] {
+    let pixel = packed as u32;
+    [
+        ((pixel >> 16) & 0xFF) as f32 / 255.0,
+        ((pixel >> 8) & 0xFF) as f32 / 255.0,
+        (pixel & 0xFF) as f32 / 255.0,
+    ]
+}
+
+pub fn color_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter().zip(&b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+// Recursive construction: each quadrant of the doubled matrix offsets the previous one
+fn bayer_ranks(size: usize) -> Vec<u32> {
+    let mut ranks = vec![0];
+    let mut n = 1;
+
+    while n < size {
+        let mut next = vec![0; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let rank = 4 * ranks[y * n + x];
+                next[y * 2 * n + x] = rank;
+                next[y * 2 * n + x + n] = rank + 2;
+                next[(y + n) * 2 * n + x] = rank + 3;
+                next[(y + n) * 2 * n + x + n] = rank + 1;
+            }
+        }
+        ranks = next;
+        n *= 2;
+    }
+    ranks
+}
+
+// Dots grow outwards from the centre of each tile
+fn clustered_dot_ranks(size: usize) -> Vec<u32> {
+    let centre = size as f32 / 2.0;
+    let key = |cell: usize| {
+        let dx = (cell % size) as f32 + 0.5 - centre;
+        let dy = (cell / size) as f32 + 0.5 - centre;
+        (dx * dx + dy * dy, dy.atan2(dx))
+    };
+
+    let mut cells: Vec<usize> = (0..size * size).collect();
+    cells.sort_by(|&a, &b| key(a).partial_cmp(&key(b)).unwrap());
+
+    let mut ranks = vec![0; size * size];
+    for (rank, cell) in cells.into_iter().enumerate() {
+        ranks[cell] = rank as u32;
+    }
+    ranks
+}
+
+// Void-and-cluster (Ulichney) with a toroidal Gaussian energy
+fn blue_noise_ranks(size: usize) -> Vec<u32> {
+    let count = size * size;
+    let sigma = 1.5f32;
+
+    let wrap = |d: usize| d.min(size - d) as f32;
+    let weights: Vec<f32> = (0..count)
+        .map(|cell| {
+            let (dx, dy) = (wrap(cell % size), wrap(cell / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+
+    let toggle = |pattern: &mut Vec<bool>, energy: &mut Vec<f32>, cell: usize| {
+        pattern[cell] = !pattern[cell];
+        let sign = if pattern[cell] { 1.0 } else { -1.0 };
+        let (cx, cy) = (cell % size, cell / size);
+        for (other, value) in energy.iter_mut().enumerate() {
+            let dx = (other % size + size - cx) % size;
+            let dy = (other / size + size - cy) % size;
+            *value += sign * weights[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&cell| pattern[cell])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&cell| !pattern[cell])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // Deterministic initial pattern so the map is stable between runs
+    let mut pattern = vec![false; count];
+    let mut energy = vec![0.0; count];
+    let mut state = 0x2545F491u32;
+    let mut ones = 0;
+    while ones < (count / 10).max(1) {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        let cell = (state >> 8) as usize % count;
+        if !pattern[cell] {
+            toggle(&mut pattern, &mut energy, cell);
+            ones += 1;
+        }
+    }
+
+    // Move points from the tightest clusters into the largest voids until stable
+    for _ in 0..count {
+        let Some(cluster) = tightest_cluster(&pattern, &energy) else {
+            break;
+        };
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largest_void(&pattern, &energy).unwrap_or(cluster);
+        toggle(&mut pattern, &mut energy, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+    let (initial_pattern, initial_energy) = (pattern.clone(), energy.clone());
+
+    for rank in (0..ones).rev() {
+        if let Some(cluster) = tightest_cluster(&pattern, &energy) {
+            toggle(&mut pattern, &mut energy, cluster);
+            ranks[cluster] = rank as u32;
+        }
+    }
+
+    let (mut pattern, mut energy) = (initial_pattern, initial_energy);
+    for rank in ones..count {
+        if let Some(void) = largest_void(&pattern, &energy) {
+            toggle(&mut pattern, &mut energy, void);
+            ranks[void] = rank as u32;
+        }
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn mean(pixels: &[f32]) -> f32 {
+        pixels.iter().sum::<f32>() / pixels.len() as f32
+    }
+
+    #[test]
+    fn threshold_maps_are_permutations_of_evenly_spaced_levels() {
+        for map in [
+            ThresholdMap::Bayer(8),
+            ThresholdMap::BlueNoise(8),
+            ThresholdMap::ClusteredDot(8),
+        ] {
+            let mut thresholds = map.thresholds();
+            thresholds.sort_by(f32::total_cmp);
+            for (rank, threshold) in thresholds.iter().enumerate() {
+                assert_eq!(*threshold, (rank as f32 + 0.5) / 64.0, "{map:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn bayer_sizes_round_up_to_a_power_of_two() {
+        assert_eq!(ThresholdMap::Bayer(3).size(), 4);
+        assert_eq!(ThresholdMap::Bayer(4).size(), 4);
+        assert_eq!(ThresholdMap::Bayer(0).size(), 1);
+        assert_eq!(ThresholdMap::BlueNoise(3).size(), 3);
+    }
+
+    #[test]
+    fn ordered_dithering_matches_the_intensity_over_a_tile() {
+        let pixels = vec![0.3; 64];
+        let output = run(
+            &OrderedDithering::new(ThresholdMap::Bayer(4)),
+            &pixels,
+            (8, 8),
+        );
+
+        assert!(output.iter().all(|&v| v == 0.0 || v == 1.0));
+        // Five of the sixteen thresholds lie below 0.3
+        assert_eq!(mean(&output), 5.0 / 16.0);
+    }
+
+    #[test]
+    fn ordered_dithering_stays_between_neighbouring_levels() {
+        let filter = OrderedDithering {
+            levels: 5,
+            ..OrderedDithering::new(ThresholdMap::ClusteredDot(4))
+        };
+        let output = run(&filter, &[0.6; 16], (4, 4));
+        assert!(output.iter().all(|&v| v == 0.5 || v == 0.75));
+    }
+
+    #[test]
+    fn palette_dithering_only_uses_palette_colours() {
+        let palette = vec![0x000000, 0xFF0000, 0xFFFFFF];
+        let filter = OrderedDithering {
+            palette: Some(palette.clone()),
+            ..OrderedDithering::new(ThresholdMap::Bayer(2))
+        };
+        let pixels: Vec<f32> = (0..16).map(|i| (i * 0x0F0A05) as f32).collect();
+        let output = run(&filter, &pixels, (4, 4));
+
+        assert_eq!(filter.color_mode(), ColorMode::Packed);
+        assert!(output.iter().all(|&v| palette.contains(&(v as u32))));
+    }
+
+    #[test]
+    fn bayer_ordered_dithering_is_the_4x4_ordered_dither() {
+        use crate::image_processing::filters::BayerOrderedDithering;
+
+        let pixels: Vec<f32> = (0..64).map(|i| i as f32 / 63.0).collect();
+        assert_eq!(
+            run(&BayerOrderedDithering, &pixels, (8, 8)),
+            run(
+                &OrderedDithering::new(ThresholdMap::Bayer(4)),
+                &pixels,
+                (8, 8)
+            )
+        );
+    }
+
+    #[test]
+    fn diffusion_weights_share_the_whole_error() {
+        for matrix in [
+            DiffusionMatrix::FloydSteinberg,
+            DiffusionMatrix::JarvisJudiceNinke,
+            DiffusionMatrix::Stucki,
+            DiffusionMatrix::Sierra,
+        ] {
+            let total: f32 = matrix.entries().iter().map(|(_, _, weight)| weight).sum();
+            assert!((total - 1.0).abs() < 1e-6, "{matrix:?}");
+        }
+        let atkinson: f32 = DiffusionMatrix::Atkinson
+            .entries()
+            .iter()
+            .map(|e| e.2)
+            .sum();
+        assert_eq!(atkinson, 0.75);
+    }
+
+    #[test]
+    fn wavefront_step_follows_the_matrix_reach() {
+        assert_eq!(
+            ErrorDiffusionDithering::new(DiffusionMatrix::FloydSteinberg).wavefront_step(),
+            2
+        );
+        assert_eq!(
+            ErrorDiffusionDithering::new(DiffusionMatrix::Stucki).wavefront_step(),
+            3
+        );
+    }
+
+    #[test]
+    fn error_diffusion_keeps_the_average_intensity() {
+        let dimensions = (32, 32);
+        for value in [0.1, 0.25, 0.5, 0.8] {
+            let pixels = vec![value; 32 * 32];
+            for serpentine in [false, true] {
+                let filter = ErrorDiffusionDithering {
+                    serpentine,
+                    ..ErrorDiffusionDithering::new(DiffusionMatrix::FloydSteinberg)
+                };
+                let output = run(&filter, &pixels, dimensions);
+
+                assert!(output.iter().all(|&v| v == 0.0 || v == 1.0));
+                assert!((mean(&output) - value).abs() < 0.02, "{value} {serpentine}");
+            }
+        }
+    }
+
+    #[test]
+    fn error_diffusion_quantizes_to_the_requested_levels() {
+        let pixels: Vec<f32> = (0..64).map(|i| i as f32 / 63.0).collect();
+        let filter = ErrorDiffusionDithering {
+            levels: 3,
+            ..ErrorDiffusionDithering::new(DiffusionMatrix::Sierra)
+        };
+        let output = run(&filter, &pixels, (8, 8));
+
+        assert!(output.iter().all(|&v| [0.0, 0.5, 1.0].contains(&v)));
+        assert_eq!(output[0], 0.0);
+        assert_eq!(output[63], 1.0);
+    }
+
+    #[test]
+    fn schedule_covers_every_wavefront() {
+        let filter = ErrorDiffusionDithering::new(DiffusionMatrix::FloydSteinberg);
+        // The last pixel starts after the full width plus two columns per extra row
+        assert_eq!(filter.get_schedule((10, 4)), Some((10 + 2 * 3, (4, 1))));
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterError {
+    // A buffer holds a different number of values than its dimensions call for
+    SizeMismatch {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    // A parameter is outside the range the filter can work with
+    InvalidParameter {
+        name: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::SizeMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{name} has {actual} values but needs {expected}"),
+            FilterError::InvalidParameter { name, reason } => write!(f, "{name} {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+// Checks that a buffer holds one value per pixel
+pub fn check_size(
+    name: &'static str,
+    actual: usize,
+    dimensions: (u32, u32),
+) -> Result<(), FilterError> {
+    let expected = (dimensions.0 * dimensions.1) as usize;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(FilterError::SizeMismatch {
+            name,
+            expected,
+            actual,
+        })
+    }
+}
+
+// Also rejects NaN
+pub fn check_positive(name: &'static str, value: f32) -> Result<(), FilterError> {
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(FilterError::InvalidParameter {
+            name,
+            reason: "must be positive",
+        })
+    }
+}
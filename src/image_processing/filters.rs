@@ -1,3 +1,9 @@
+use super::dithering::{OrderedDithering, ThresholdMap};
+use super::error::{check_positive, FilterError};
+use super::image_converter::ImageConverter;
+use super::image_processor::Backend;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
 pub struct SobelFilter;
 pub struct PrewittFilter;
 pub struct CannyFilter;
@@ -5,18 +11,190 @@ pub struct GaussianBlur;
 pub struct LaplacianSharpening;
 pub struct BayerOrderedDithering;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientOperator {
+    Sobel,
+    Prewitt,
+    Scharr,
+    Roberts,
+    Kirsch,
+    Robinson,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientOutput {
+    Magnitude,
+    X,
+    Y,
+    // Gradient angle mapped to 0..1
+    Orientation,
+    // Colour image with the gradient angle as hue and the magnitude, relative to the scale of
+    // the filter, as value
+    ColorOrientation,
+    // Signed X and Y responses interleaved per pixel, so the output is twice as wide
+    Vectors,
+}
+
+pub struct GradientFilter {
+    pub operator: GradientOperator,
+    pub output: GradientOutput,
+    // Magnitude shown at full brightness by the colour orientation output, by default the
+    // largest one the operator can give
+    pub scale: Option<f32>,
+}
+
+// Scale-normalised and negated, so bright blobs give positive peaks
+pub struct LaplacianOfGaussian {
+    pub sigma: f32,
+    // Output zero crossings steeper than this instead of the response
+    pub zero_crossing: Option<f32>,
+}
+
+pub struct DifferenceOfGaussians {
+    pub narrow_sigma: f32,
+    pub wide_sigma: f32,
+    pub zero_crossing: Option<f32>,
+}
+
+// Relief lit from the given angle, counter-clockwise from the right, around mid grey
+pub struct Emboss {
+    pub angle: f32,
+    pub strength: f32,
+}
+
+// Replaces each block of the grid with its average colour
+pub struct Pixelate {
+    // At least one, checked in `new`
+    block_size: u32,
+}
+
+pub struct Posterize {
+    // Levels per channel, at least two, checked in `new`
+    levels: u32,
+}
+
+// Each pixel takes the average colour of the most common intensity in its neighbourhood
+pub struct OilPaint {
+    pub radius: u32,
+    // Number of intensity bins, 1 to 64, checked in `new`
+    intensity_levels: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KuwaharaMode {
+    // Mean of the square quadrant with the lowest variance
+    Classic,
+    // Weighted means of eight sectors of an ellipse aligned with the local edge direction
+    Anisotropic,
+}
+
+pub struct Kuwahara {
+    // At least one, checked in `new`
+    radius: u32,
+    pub mode: KuwaharaMode,
+    // How strongly the anisotropic mode favours low variance sectors
+    pub sharpness: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorMode {
+    // Filter runs once on the luminance plane
+    Grayscale,
+    // Filter runs on the red, green and blue planes separately
+    Rgb,
+    // Filter output is added back onto the original image
+    RgbResidual,
+    // Filter runs once on whole pixels, each packed as a 0xRRGGBB value
+    Packed,
+    // Filter runs on the luma plane and the original chroma is restored afterwards
+    Luminance,
+}
+
 pub trait ImageFilter {
-    fn get_kernel(&self) -> (&'static str, &'static str);
-    fn compute_options(&self, _: &[f32]) -> Vec<f32> {
+    // Source and entry point of the OpenCL kernel, None for stages that only have a CPU
+    // implementation, which every backend then runs through process_cpu
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)>;
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
         vec![]
     }
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Grayscale
+    }
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        dimensions
+    }
+    // Number of launches and work size for kernels that take an extra pass index argument
+    fn get_schedule(&self, _: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        None
+    }
+    // Floats of device memory the scheduled passes share, passed after the pass index
+    fn scratch_size(&self, _: (u32, u32)) -> usize {
+        0
+    }
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32>;
 }
 
-impl ImageFilter for SobelFilter {
-    fn get_kernel(&self) -> (&'static str, &'static str) {
-        (
+impl GradientOperator {
+    // Sobel, Prewitt, Scharr and Roberts return an X/Y pair, the compass operators return
+    // eight kernels starting east and rotating counter-clockwise in 45 degree steps
+    pub fn kernels(&self) -> Vec<[f32; 9]> {
+        match self {
+            GradientOperator::Sobel => vec![
+                [-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0],
+                [-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0],
+            ],
+            GradientOperator::Prewitt => vec![
+                [-1.0, 0.0, 1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0],
+                [-1.0, -1.0, -1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0],
+            ],
+            GradientOperator::Scharr => vec![
+                [-3.0, 0.0, 3.0, -10.0, 0.0, 10.0, -3.0, 0.0, 3.0],
+                [-3.0, -10.0, -3.0, 0.0, 0.0, 0.0, 3.0, 10.0, 3.0],
+            ],
+            // The 2x2 cross sits in the lower right corner of the 3x3 window
+            GradientOperator::Roberts => vec![
+                [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0],
+                [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, -1.0, 0.0],
+            ],
+            GradientOperator::Kirsch => {
+                compass_kernels([-3.0, -3.0, 5.0, 5.0, 5.0, -3.0, -3.0, -3.0])
+            }
+            GradientOperator::Robinson => {
+                compass_kernels([-1.0, 0.0, 1.0, 2.0, 1.0, 0.0, -1.0, -2.0])
+            }
+        }
+    }
+
+    // Upper bound of the magnitude on images in 0..1, where each kernel responds most to ones
+    // under its positive weights and zeros under the rest
+    pub fn max_magnitude(&self) -> f32 {
+        let responses: Vec<f32> = self
+            .kernels()
+            .iter()
+            .map(|kernel| kernel.iter().filter(|&&weight| weight > 0.0).sum())
+            .collect();
+        match responses.as_slice() {
+            [x, y] => x.hypot(*y),
+            _ => responses.into_iter().fold(0.0, f32::max),
+        }
+    }
+}
+
+impl GradientFilter {
+    pub fn new(operator: GradientOperator) -> Self {
+        Self {
+            operator,
+            output: GradientOutput::Magnitude,
+            scale: None,
+        }
+    }
+}
+
+impl ImageFilter for GradientFilter {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
             r#"
-            __kernel void sobelEdgeDetection(
+            __kernel void gradientOperator(
                 __global const float* inputImage,
                 __global float* outputImage,
                 __global const float* options,
@@ -28,87 +206,255 @@ impl ImageFilter for SobelFilter {
                 if (x < 1 || y < 1 || x >= width - 1 || y >= height - 1)
                     return; // Skip the borders
 
-                float edgeX = 0.0, edgeY = 0.0;
+                int output = (int)options[0];
+                int count = (int)options[1];
+
+                // The colour orientation output reads packed pixels and works on their luminance
+                float neighbourhood[9];
                 for (int i = -1; i <= 1; i++)
                 {
                     for (int j = -1; j <= 1; j++)
                     {
                         float pixel = inputImage[(y + i) * width + (x + j)];
-                        edgeX += options[(i + 1) * 3 + (j + 1)] * pixel;
-                        edgeY += options[9 + (i + 1) * 3 + (j + 1)] * pixel;
+                        if (output == 4) {
+                            uint packed = (uint)pixel;
+                            pixel = (0.2989f * ((packed >> 16) & 0xFF) + 0.5870f * ((packed >> 8) & 0xFF)
+                                + 0.1140f * (packed & 0xFF)) / 255.0f;
+                        }
+                        neighbourhood[(i + 1) * 3 + (j + 1)] = pixel;
                     }
                 }
 
-                // Calculate magnitude of gradient
-                float magnitude = sqrt(edgeX * edgeX + edgeY * edgeY);
-                outputImage[y * width + x] = magnitude;
+                float responses[8];
+                for (int k = 0; k < count; k++)
+                {
+                    float response = 0.0;
+                    for (int i = 0; i < 9; i++)
+                        response += options[2 + k * 9 + i] * neighbourhood[i];
+                    responses[k] = response;
+                }
+
+                // Compass operators give their east and north responses as X and Y
+                float edgeX = responses[0];
+                float edgeY = responses[count == 2 ? 1 : count / 4];
+                if (output == 5) {
+                    outputImage[2 * (y * width + x)] = edgeX;
+                    outputImage[2 * (y * width + x) + 1] = edgeY;
+                    return;
+                }
+
+                float value, magnitude, angle;
+                if (count == 2) {
+                    magnitude = sqrt(edgeX * edgeX + edgeY * edgeY);
+                    angle = (atan2(edgeY, edgeX) + M_PI_F) / (2.0f * M_PI_F);
+                } else {
+                    // Compass operators take the strongest of their directional responses
+                    int best = 0;
+                    for (int k = 1; k < count; k++)
+                        if (responses[k] > responses[best])
+                            best = k;
+
+                    magnitude = responses[best];
+                    angle = (float)best / count;
+                }
+
+                if (output == 1) {
+                    value = fabs(edgeX);
+                } else if (output == 2) {
+                    value = fabs(edgeY);
+                } else if (output == 0) {
+                    value = magnitude;
+                } else if (output == 3) {
+                    value = angle;
+                } else if (output == 4) {
+                    // Fully saturated HSV colour, options[2 + count * 9] holds the scale
+                    float brightness = clamp(magnitude / options[2 + count * 9], 0.0f, 1.0f);
+                    float3 rgb = clamp(fabs(fmod(angle * 6.0f + (float3)(0.0f, 4.0f, 2.0f), 6.0f) - 3.0f) - 1.0f, 0.0f, 1.0f);
+                    uint3 bytes = convert_uint3(brightness * rgb * 255.0f + 0.5f);
+                    value = (float)((bytes.x << 16) | (bytes.y << 8) | bytes.z);
+                }
+
+                outputImage[y * width + x] = value;
             }
             "#,
-            "sobelEdgeDetection",
-        )
+            "gradientOperator",
+        ))
     }
 
-    fn compute_options(&self, _: &[f32]) -> Vec<f32> {
-        let sobel_x = vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0];
-        let sobel_y = vec![-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0];
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let kernels = self.operator.kernels();
+
+        let mut options = vec![self.output as i32 as f32, kernels.len() as f32];
+        for kernel in kernels {
+            options.extend(kernel);
+        }
 
-        let mut options = Vec::new();
-        options.extend(&sobel_x);
-        options.extend(&sobel_y);
+        let scale = self
+            .scale
+            .filter(|&scale| scale > 0.0)
+            .unwrap_or_else(|| self.operator.max_magnitude());
+        options.push(scale);
         options
     }
+
+    fn color_mode(&self) -> ColorMode {
+        match self.output {
+            GradientOutput::ColorOrientation => ColorMode::Packed,
+            _ => ColorMode::Grayscale,
+        }
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        match self.output {
+            GradientOutput::Vectors => (dimensions.0 * 2, dimensions.1),
+            _ => dimensions,
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let count = options[1] as usize;
+        match self.output {
+            GradientOutput::Magnitude => self.magnitudes_and_angles(pixels, options, dimensions).0,
+            GradientOutput::Orientation => {
+                self.magnitudes_and_angles(pixels, options, dimensions).1
+            }
+            GradientOutput::X | GradientOutput::Y => {
+                let k = match (self.output, count) {
+                    (GradientOutput::X, _) => 0,
+                    (_, 2) => 1,
+                    _ => count / 4,
+                };
+                convolve_3x3(pixels, &options[2 + k * 9..11 + k * 9], dimensions)
+                    .into_iter()
+                    .map(f32::abs)
+                    .collect()
+            }
+            GradientOutput::Vectors => {
+                let y_kernel = if count == 2 { 1 } else { count / 4 };
+                let edge_x = convolve_3x3(pixels, &options[2..11], dimensions);
+                let edge_y = convolve_3x3(
+                    pixels,
+                    &options[2 + y_kernel * 9..11 + y_kernel * 9],
+                    dimensions,
+                );
+                edge_x
+                    .into_iter()
+                    .zip(edge_y)
+                    .flat_map(|(x, y)| [x, y])
+                    .collect()
+            }
+            GradientOutput::ColorOrientation => {
+                let grayscale = ImageConverter::convert_rgb_to_grayscale(
+                    &ImageConverter::convert_packed_to_rgb(pixels),
+                );
+                let (magnitudes, angles) =
+                    self.magnitudes_and_angles(&grayscale, options, dimensions);
+                let scale = options[2 + count * 9];
+
+                magnitudes
+                    .iter()
+                    .zip(&angles)
+                    .map(|(magnitude, angle)| {
+                        let brightness = (magnitude / scale).clamp(0.0, 1.0);
+                        let channel = |offset: f32| {
+                            let rgb = (((angle * 6.0 + offset) % 6.0) - 3.0).abs() - 1.0;
+                            (brightness * rgb.clamp(0.0, 1.0) * 255.0 + 0.5) as u32
+                        };
+                        ((channel(0.0) << 16) | (channel(4.0) << 8) | channel(2.0)) as f32
+                    })
+                    .collect()
+            }
+        }
+    }
 }
 
-impl ImageFilter for PrewittFilter {
-    fn get_kernel(&self) -> (&'static str, &'static str) {
-        (
-            r#"
-            __kernel void prewittEdgeDetection(
-                __global const float* inputImage,
-                __global float* outputImage,
-                __global const float* options,
-                const int width, const int height) {
+impl GradientFilter {
+    // Signed (gx, gy) per pixel of a grayscale image, the compass operators giving their
+    // east and north responses. Borders are zero
+    pub fn vectors(
+        &self,
+        pixels: &[f32],
+        dimensions: (u32, u32),
+        backend: Backend,
+    ) -> Vec<[f32; 2]> {
+        let filter = GradientFilter {
+            output: GradientOutput::Vectors,
+            ..GradientFilter::new(self.operator)
+        };
+        let options = filter.compute_options(pixels, dimensions);
+        backend
+            .process(&filter, pixels, &options, dimensions)
+            .chunks_exact(2)
+            .map(|pair| [pair[0], pair[1]])
+            .collect()
+    }
 
-                int x = get_global_id(0);
-                int y = get_global_id(1);
+    // Magnitude and angle mapped to 0..1, from the strongest response for compass operators
+    fn magnitudes_and_angles(
+        &self,
+        pixels: &[f32],
+        options: &[f32],
+        dimensions: (u32, u32),
+    ) -> (Vec<f32>, Vec<f32>) {
+        let count = options[1] as usize;
+        let responses: Vec<Vec<f32>> = (0..count)
+            .map(|k| convolve_3x3(pixels, &options[2 + k * 9..11 + k * 9], dimensions))
+            .collect();
 
-                if (x < 1 || y < 1 || x >= width - 1 || y >= height - 1)
-                    return; // Skip the borders
+        (0..pixels.len())
+            .map(|i| {
+                if count == 2 {
+                    let (edge_x, edge_y) = (responses[0][i], responses[1][i]);
+                    return (
+                        (edge_x * edge_x + edge_y * edge_y).sqrt(),
+                        (edge_y.atan2(edge_x) + PI) / (2.0 * PI),
+                    );
+                }
 
-                float edgeX = 0.0, edgeY = 0.0;
-                for (int i = -1; i <= 1; i++)
-                {
-                    for (int j = -1; j <= 1; j++)
-                    {
-                        float pixel = inputImage[(y + i) * width + (x + j)];
-                        edgeX += options[(i + 1) * 3 + (j + 1)] * pixel;
-                        edgeY += options[9 + (i + 1) * 3 + (j + 1)] * pixel;
+                let best = (1..count).fold(0, |best, k| {
+                    if responses[k][i] > responses[best][i] {
+                        k
+                    } else {
+                        best
                     }
-                }
+                });
+                (responses[best][i], best as f32 / count as f32)
+            })
+            .unzip()
+    }
+}
 
-                // Calculate magnitude of gradient
-                float magnitude = sqrt(edgeX * edgeX + edgeY * edgeY);
-                outputImage[y * width + x] = magnitude;
-            }
-            "#,
-            "prewittEdgeDetection",
-        )
+impl ImageFilter for SobelFilter {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        GradientFilter::new(GradientOperator::Sobel).get_kernel()
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        GradientFilter::new(GradientOperator::Sobel).compute_options(pixels, dimensions)
     }
 
-    fn compute_options(&self, _: &[f32]) -> Vec<f32> {
-        let prewitt_x = vec![-1.0, 0.0, 1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0];
-        let prewitt_y = vec![-1.0, -1.0, -1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        GradientFilter::new(GradientOperator::Sobel).process_cpu(pixels, options, dimensions)
+    }
+}
 
-        let mut options = Vec::new();
-        options.extend(&prewitt_x);
-        options.extend(&prewitt_y);
-        options
+impl ImageFilter for PrewittFilter {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        GradientFilter::new(GradientOperator::Prewitt).get_kernel()
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        GradientFilter::new(GradientOperator::Prewitt).compute_options(pixels, dimensions)
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        GradientFilter::new(GradientOperator::Prewitt).process_cpu(pixels, options, dimensions)
     }
 }
 
 impl ImageFilter for CannyFilter {
-    fn get_kernel(&self) -> (&'static str, &'static str) {
-        (
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
             r#"
             __kernel void cannyEdgeDetection(
                 __global const float* inputImage,
@@ -155,10 +501,10 @@ impl ImageFilter for CannyFilter {
             }
             "#,
             "cannyEdgeDetection",
-        )
+        ))
     }
 
-    fn compute_options(&self, pixels: &[f32]) -> Vec<f32> {
+    fn compute_options(&self, pixels: &[f32], _: (u32, u32)) -> Vec<f32> {
         let len = pixels.len() as f32;
         let mean = pixels.iter().sum::<f32>() / len;
         let std_dev = (pixels.iter().map(|&x| (x - mean).powi(2)).sum::<f32>() / len).sqrt();
@@ -168,11 +514,30 @@ impl ImageFilter for CannyFilter {
 
         vec![low_threshold.max(0.0), high_threshold.min(1.0)]
     }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let sobel = SobelFilter.compute_options(pixels, dimensions);
+        let (low_threshold, high_threshold) = (options[0], options[1]);
+
+        SobelFilter
+            .process_cpu(pixels, &sobel, dimensions)
+            .iter()
+            .map(|&magnitude| {
+                if magnitude > high_threshold {
+                    1.0
+                } else if magnitude > low_threshold {
+                    0.5
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
 }
 
 impl ImageFilter for GaussianBlur {
-    fn get_kernel(&self) -> (&'static str, &'static str) {
-        (
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
             r#"
             __kernel void gaussianBlur(
                 __global const float* inputImage,
@@ -214,10 +579,10 @@ impl ImageFilter for GaussianBlur {
             }
             "#,
             "gaussianBlur",
-        )
+        ))
     }
 
-    fn compute_options(&self, _: &[f32]) -> Vec<f32> {
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
         let kernel_size = 5;
         let sigma = 1.0;
 
@@ -239,11 +604,49 @@ impl ImageFilter for GaussianBlur {
         options.extend(kernel);
         options
     }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Rgb
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let kernel_size = options[0] as isize;
+        let half_kernel = kernel_size / 2;
+
+        let mut output = vec![0.0; pixels.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                let mut weight_sum = 0.0;
+
+                for ky in -half_kernel..=half_kernel {
+                    for kx in -half_kernel..=half_kernel {
+                        let (nx, ny) = (x + kx, y + ky);
+                        if nx >= 0 && ny >= 0 && nx < width && ny < height {
+                            let kernel_index = (ky + half_kernel) * kernel_size + kx + half_kernel;
+                            let weight = options[kernel_index as usize + 1];
+                            sum += pixels[(ny * width + nx) as usize] * weight;
+                            weight_sum += weight;
+                        }
+                    }
+                }
+
+                let index = (y * width + x) as usize;
+                output[index] = if weight_sum > 0.0 {
+                    sum / weight_sum
+                } else {
+                    pixels[index]
+                };
+            }
+        }
+        output
+    }
 }
 
 impl ImageFilter for LaplacianSharpening {
-    fn get_kernel(&self) -> (&'static str, &'static str) {
-        (
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
             r#"
                 __kernel void laplacianSharpening(
                     __global const float* inputImage,
@@ -271,75 +674,1424 @@ impl ImageFilter for LaplacianSharpening {
                 }
             "#,
             "laplacianSharpening",
-        )
+        ))
     }
 
-    fn compute_options(&self, _: &[f32]) -> Vec<f32> {
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
         vec![0.0, -1.0, 0.0, -1.0, 4.0, -1.0, 0.0, -1.0, 0.0]
     }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::RgbResidual
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        convolve_3x3(pixels, options, dimensions)
+    }
 }
 
+// The original 4x4 Bayer dither, kept for existing callers
 impl ImageFilter for BayerOrderedDithering {
-    fn get_kernel(&self) -> (&'static str, &'static str) {
-        (
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        OrderedDithering::new(ThresholdMap::Bayer(4)).get_kernel()
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        OrderedDithering::new(ThresholdMap::Bayer(4)).compute_options(pixels, dimensions)
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        OrderedDithering::new(ThresholdMap::Bayer(4)).color_mode()
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        OrderedDithering::new(ThresholdMap::Bayer(4)).process_cpu(pixels, options, dimensions)
+    }
+}
+
+impl LaplacianOfGaussian {
+    pub fn new(sigma: f32) -> Result<Self, FilterError> {
+        check_positive("Sigma", sigma)?;
+        Ok(Self {
+            sigma,
+            zero_crossing: None,
+        })
+    }
+}
+
+impl ImageFilter for LaplacianOfGaussian {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some(ZERO_CROSSING_KERNEL)
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let size = gaussian_kernel_size(self.sigma);
+        let half = (size / 2) as isize;
+        let variance = self.sigma * self.sigma;
+
+        let gaussian = gaussian_kernel(self.sigma, size);
+        let mut kernel: Vec<f32> = gaussian
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let x = (i % size) as isize - half;
+                let y = (i / size) as isize - half;
+                let radius = (x * x + y * y) as f32;
+                (2.0 * variance - radius) / variance * weight
+            })
+            .collect();
+
+        // Truncation leaves a small DC component, which would respond to flat regions
+        let mean = kernel.iter().sum::<f32>() / kernel.len() as f32;
+        kernel.iter_mut().for_each(|weight| *weight -= mean);
+
+        zero_crossing_options(size, self.zero_crossing, kernel)
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        convolve_zero_crossing(pixels, options, dimensions)
+    }
+}
+
+impl DifferenceOfGaussians {
+    pub fn new(narrow_sigma: f32, wide_sigma: f32) -> Result<Self, FilterError> {
+        check_positive("Narrow sigma", narrow_sigma)?;
+        check_positive("Wide sigma", wide_sigma)?;
+        // Equal sigmas cancel out and swapped ones flip the sign of the response
+        if narrow_sigma >= wide_sigma {
+            return Err(FilterError::InvalidParameter {
+                name: "Narrow sigma",
+                reason: "must be smaller than the wide sigma",
+            });
+        }
+        Ok(Self {
+            narrow_sigma,
+            wide_sigma,
+            zero_crossing: None,
+        })
+    }
+}
+
+impl ImageFilter for DifferenceOfGaussians {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some(ZERO_CROSSING_KERNEL)
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let size = gaussian_kernel_size(self.narrow_sigma.max(self.wide_sigma));
+        let narrow = gaussian_kernel(self.narrow_sigma, size);
+        let wide = gaussian_kernel(self.wide_sigma, size);
+
+        let kernel = narrow.iter().zip(&wide).map(|(n, w)| n - w).collect();
+        zero_crossing_options(size, self.zero_crossing, kernel)
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        convolve_zero_crossing(pixels, options, dimensions)
+    }
+}
+
+impl Emboss {
+    pub fn new(angle: f32) -> Self {
+        Self {
+            angle,
+            strength: 1.0,
+        }
+    }
+}
+
+impl ImageFilter for Emboss {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
             r#"
-                __kernel void bayerOrderedDithering(
-                    __global const float* inputImage,
-                    __global float* outputImage,
-                    __global const float* options,
-                    const int width,
-                    const int height) {
+            __kernel void emboss(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
 
-                    int x = get_global_id(0);
-                    int y = get_global_id(1);
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                float value = 0.5f;
+                for (int i = -1; i <= 1; i++) {
+                    for (int j = -1; j <= 1; j++) {
+                        int nx = clamp(x + j, 0, width - 1);
+                        int ny = clamp(y + i, 0, height - 1);
+                        value += options[(i + 1) * 3 + (j + 1)] * inputImage[ny * width + nx];
+                    }
+                }
+                outputImage[y * width + x] = value;
+            }
+            "#,
+            "emboss",
+        ))
+    }
+
+    // Weights grow along the light direction, with y pointing down the image
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        (0..9)
+            .map(|i| {
+                let (dx, dy) = ((i % 3) as f32 - 1.0, (i / 3) as f32 - 1.0);
+                self.strength * (dx * cos - dy * sin)
+            })
+            .collect()
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let mut value = 0.5;
+                for (k, weight) in options[..9].iter().enumerate() {
+                    let nx = (x + k as isize % 3 - 1).clamp(0, width - 1);
+                    let ny = (y + k as isize / 3 - 1).clamp(0, height - 1);
+                    value += weight * pixels[(ny * width + nx) as usize];
+                }
+                value
+            })
+            .collect()
+    }
+}
+
+impl Pixelate {
+    pub fn new(block_size: u32) -> Result<Self, FilterError> {
+        if block_size == 0 {
+            return Err(FilterError::InvalidParameter {
+                name: "Block size",
+                reason: "must be at least one",
+            });
+        }
+        Ok(Self { block_size })
+    }
+}
+
+impl ImageFilter for Pixelate {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void pixelate(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int blockSize = (int)options[0];
+                int left = x / blockSize * blockSize;
+                int top = y / blockSize * blockSize;
+                int right = min(left + blockSize, width);
+                int bottom = min(top + blockSize, height);
+
+                float sum = 0.0f;
+                for (int ny = top; ny < bottom; ny++)
+                    for (int nx = left; nx < right; nx++)
+                        sum += inputImage[ny * width + nx];
+                outputImage[y * width + x] = sum / ((right - left) * (bottom - top));
+            }
+            "#,
+            "pixelate",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        vec![self.block_size as f32]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Rgb
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let block_size = options[0] as usize;
+
+        let mut output = vec![0.0; pixels.len()];
+        for top in (0..height).step_by(block_size) {
+            for left in (0..width).step_by(block_size) {
+                let (bottom, right) = (
+                    (top + block_size).min(height),
+                    (left + block_size).min(width),
+                );
+                let sum: f32 = (top..bottom)
+                    .flat_map(|y| pixels[y * width + left..y * width + right].iter())
+                    .sum();
+                let mean = sum / ((right - left) * (bottom - top)) as f32;
+                for y in top..bottom {
+                    output[y * width + left..y * width + right].fill(mean);
+                }
+            }
+        }
+        output
+    }
+}
+
+impl Posterize {
+    pub fn new(levels: u32) -> Result<Self, FilterError> {
+        if levels < 2 {
+            return Err(FilterError::InvalidParameter {
+                name: "Levels",
+                reason: "must be at least two",
+            });
+        }
+        Ok(Self { levels })
+    }
+}
+
+impl ImageFilter for Posterize {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void posterize(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                float steps = options[0] - 1.0f;
+                outputImage[idx] = round(clamp(inputImage[idx], 0.0f, 1.0f) * steps) / steps;
+            }
+            "#,
+            "posterize",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        vec![self.levels as f32]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Rgb
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let steps = options[0] - 1.0;
+        pixels
+            .iter()
+            .map(|pixel| (pixel.clamp(0.0, 1.0) * steps).round() / steps)
+            .collect()
+    }
+}
+
+impl OilPaint {
+    pub fn new(radius: u32, intensity_levels: u32) -> Result<Self, FilterError> {
+        // The kernel keeps one histogram bin per level in a fixed size array
+        if !(1..=64).contains(&intensity_levels) {
+            return Err(FilterError::InvalidParameter {
+                name: "Intensity levels",
+                reason: "must be between 1 and 64",
+            });
+        }
+        Ok(Self {
+            radius,
+            intensity_levels,
+        })
+    }
+}
+
+impl ImageFilter for OilPaint {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float3 unpackColor(float packed) {
+                uint pixel = (uint)packed;
+                return (float3)((pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF);
+            }
+
+            __kernel void oilPaint(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int radius = (int)options[0];
+                int levels = (int)options[1];
+
+                int counts[64];
+                float3 sums[64];
+                for (int i = 0; i < levels; i++) {
+                    counts[i] = 0;
+                    sums[i] = (float3)(0.0f);
+                }
+
+                for (int dy = -radius; dy <= radius; dy++) {
+                    for (int dx = -radius; dx <= radius; dx++) {
+                        int nx = x + dx;
+                        int ny = y + dy;
+                        if (dx * dx + dy * dy > radius * radius || nx < 0 || ny < 0 || nx >= width || ny >= height)
+                            continue;
+
+                        float3 color = unpackColor(inputImage[ny * width + nx]);
+                        float intensity = (color.x + color.y + color.z) / (3.0f * 255.0f);
+                        int bin = min((int)(intensity * levels), levels - 1);
+                        counts[bin]++;
+                        sums[bin] += color;
+                    }
+                }
+
+                int best = 0;
+                for (int i = 1; i < levels; i++)
+                    if (counts[i] > counts[best])
+                        best = i;
+
+                uint3 rgb = convert_uint3(sums[best] / counts[best] + 0.5f);
+                outputImage[y * width + x] = (float)((rgb.x << 16) | (rgb.y << 8) | rgb.z);
+            }
+            "#,
+            "oilPaint",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        vec![self.radius as f32, self.intensity_levels as f32]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let (radius, levels) = (options[0] as isize, options[1] as usize);
+        let colors: Vec<[f32; 3]> = pixels.iter().map(|&pixel| unpack_color(pixel)).collect();
+
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let mut counts = vec![0u32; levels];
+                let mut sums = vec![[0.0f32; 3]; levels];
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if dx * dx + dy * dy > radius * radius
+                            || nx < 0
+                            || ny < 0
+                            || nx >= width
+                            || ny >= height
+                        {
+                            continue;
+                        }
+
+                        let color = colors[(ny * width + nx) as usize];
+                        let intensity = (color[0] + color[1] + color[2]) / (3.0 * 255.0);
+                        let bin = ((intensity * levels as f32) as usize).min(levels - 1);
+                        counts[bin] += 1;
+                        sums[bin]
+                            .iter_mut()
+                            .zip(color)
+                            .for_each(|(sum, c)| *sum += c);
+                    }
+                }
+
+                let best = (1..levels).fold(0, |best, bin| {
+                    if counts[bin] > counts[best] {
+                        bin
+                    } else {
+                        best
+                    }
+                });
+                pack_color(sums[best].map(|sum| sum / counts[best] as f32))
+            })
+            .collect()
+    }
+}
+
+impl Kuwahara {
+    pub fn new(radius: u32, mode: KuwaharaMode) -> Result<Self, FilterError> {
+        if radius == 0 {
+            return Err(FilterError::InvalidParameter {
+                name: "Radius",
+                reason: "must be at least one",
+            });
+        }
+        Ok(Self {
+            radius,
+            mode,
+            sharpness: 8.0,
+        })
+    }
+}
+
+impl ImageFilter for Kuwahara {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float3 unpackColor(float packed) {
+                uint pixel = (uint)packed;
+                return (float3)((pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF) / 255.0f;
+            }
+
+            float luminance(__global const float* image, int x, int y, int width, int height) {
+                float3 color = unpackColor(image[clamp(y, 0, height - 1) * width + clamp(x, 0, width - 1)]);
+                return dot(color, (float3)(0.299f, 0.587f, 0.114f));
+            }
+
+            float3 classic(__global const float* image, int x, int y, int width, int height, int radius) {
+                float3 best = (float3)(0.0f);
+                float lowest = INFINITY;
+                for (int quadrant = 0; quadrant < 4; quadrant++) {
+                    int sx = (quadrant & 1) ? 1 : -1;
+                    int sy = (quadrant & 2) ? 1 : -1;
+
+                    float3 sum = (float3)(0.0f);
+                    float lumaSum = 0.0f, lumaSquares = 0.0f;
+                    for (int i = 0; i <= radius; i++) {
+                        for (int j = 0; j <= radius; j++) {
+                            int nx = clamp(x + sx * j, 0, width - 1);
+                            int ny = clamp(y + sy * i, 0, height - 1);
+                            float3 color = unpackColor(image[ny * width + nx]);
+                            float luma = dot(color, (float3)(0.299f, 0.587f, 0.114f));
+                            sum += color;
+                            lumaSum += luma;
+                            lumaSquares += luma * luma;
+                        }
+                    }
+
+                    float count = (radius + 1) * (radius + 1);
+                    float variance = lumaSquares / count - (lumaSum / count) * (lumaSum / count);
+                    if (variance < lowest) {
+                        lowest = variance;
+                        best = sum / count;
+                    }
+                }
+                return best;
+            }
+
+            // Sobel gradient products of the luminance, as (E, F, G)
+            float3 gradientProducts(__global const float* image, int x, int y, int width, int height) {
+                float gx = luminance(image, x + 1, y - 1, width, height)
+                    + 2.0f * luminance(image, x + 1, y, width, height)
+                    + luminance(image, x + 1, y + 1, width, height)
+                    - luminance(image, x - 1, y - 1, width, height)
+                    - 2.0f * luminance(image, x - 1, y, width, height)
+                    - luminance(image, x - 1, y + 1, width, height);
+                float gy = luminance(image, x - 1, y + 1, width, height)
+                    + 2.0f * luminance(image, x, y + 1, width, height)
+                    + luminance(image, x + 1, y + 1, width, height)
+                    - luminance(image, x - 1, y - 1, width, height)
+                    - 2.0f * luminance(image, x, y - 1, width, height)
+                    - luminance(image, x + 1, y - 1, width, height);
+                return (float3)(gx * gx, gx * gy, gy * gy);
+            }
+
+            // Structure tensor from the gradient products smoothed with a Gaussian of sigma two
+            float3 smoothTensor(__global const float* products, int x, int y, int width, int height) {
+                float3 tensor = (float3)(0.0f);
+                float weights = 0.0f;
+                for (int dy = -6; dy <= 6; dy++) {
+                    for (int dx = -6; dx <= 6; dx++) {
+                        int px = clamp(x + dx, 0, width - 1);
+                        int py = clamp(y + dy, 0, height - 1);
+                        float weight = exp(-(dx * dx + dy * dy) / 8.0f);
+                        tensor += weight * vload3(py * width + px, products);
+                        weights += weight;
+                    }
+                }
+                return tensor / weights;
+            }
 
-                    // Check if the current pixel is within bounds
-                    if (x >= width || y >= height) {
-                        return;
+            float3 anisotropic(
+                __global const float* image, float3 tensor,
+                int x, int y, int width, int height, int radius, float sharpness) {
+
+                float e = tensor.x, f = tensor.y, g = tensor.z;
+                float root = sqrt((e - g) * (e - g) + 4.0f * f * f);
+                float major = (e + g + root) / 2.0f;
+                float minor = (e + g - root) / 2.0f;
+
+                float2 tangent = (float2)(major - e, -f);
+                tangent = length(tangent) > 0.0f ? normalize(tangent) : (float2)(0.0f, 1.0f);
+                float anisotropy = major + minor > 0.0f ? (major - minor) / (major + minor) : 0.0f;
+                float cosPhi = tangent.x, sinPhi = tangent.y;
+
+                float a = radius * clamp(1.0f + anisotropy, 0.1f, 2.0f);
+                float b = radius * clamp(1.0f / (1.0f + anisotropy), 0.1f, 2.0f);
+                int maxX = (int)sqrt(a * a * cosPhi * cosPhi + b * b * sinPhi * sinPhi);
+                int maxY = (int)sqrt(a * a * sinPhi * sinPhi + b * b * cosPhi * cosPhi);
+
+                float zeta = 2.0f / radius;
+                float sine = sin(M_PI_F / 8.0f);
+                float eta = (zeta + cos(M_PI_F / 8.0f)) / (sine * sine);
+
+                float4 means[8];
+                float3 squares[8];
+                for (int k = 0; k < 8; k++) {
+                    means[k] = (float4)(0.0f);
+                    squares[k] = (float3)(0.0f);
+                }
+
+                for (int dy = -maxY; dy <= maxY; dy++) {
+                    for (int dx = -maxX; dx <= maxX; dx++) {
+                        float2 v = (float2)(
+                            0.5f / a * (cosPhi * dx + sinPhi * dy),
+                            0.5f / b * (-sinPhi * dx + cosPhi * dy));
+                        if (dot(v, v) > 0.25f)
+                            continue;
+
+                        int nx = clamp(x + dx, 0, width - 1);
+                        int ny = clamp(y + dy, 0, height - 1);
+                        float3 color = unpackColor(image[ny * width + nx]);
+
+                        // Polynomial sector weights, the odd sectors from v rotated by 45 degrees
+                        float w[8];
+                        float2 r = M_SQRT1_2_F * (float2)(v.x - v.y, v.x + v.y);
+                        for (int half = 0; half < 2; half++) {
+                            float2 u = half ? r : v;
+                            float uxx = zeta - eta * u.x * u.x;
+                            float uyy = zeta - eta * u.y * u.y;
+                            float z;
+                            z = max(0.0f, u.y + uxx); w[half] = z * z;
+                            z = max(0.0f, -u.x + uyy); w[2 + half] = z * z;
+                            z = max(0.0f, -u.y + uxx); w[4 + half] = z * z;
+                            z = max(0.0f, u.x + uyy); w[6 + half] = z * z;
+                        }
+
+                        float sum = 0.0f;
+                        for (int k = 0; k < 8; k++)
+                            sum += w[k];
+                        if (sum <= 0.0f)
+                            continue;
+
+                        float gaussian = exp(-3.125f * dot(v, v)) / sum;
+                        for (int k = 0; k < 8; k++) {
+                            float weight = w[k] * gaussian;
+                            means[k] += (float4)(color * weight, weight);
+                            squares[k] += color * color * weight;
+                        }
                     }
+                }
+
+                float4 output = (float4)(0.0f);
+                for (int k = 0; k < 8; k++) {
+                    if (means[k].w <= 0.0f)
+                        continue;
+                    float3 mean = means[k].xyz / means[k].w;
+                    float3 variance = fabs(squares[k] / means[k].w - mean * mean);
+                    float weight = 1.0f / (1.0f + pow(255.0f * (variance.x + variance.y + variance.z), 0.5f * sharpness));
+                    output += (float4)(mean * weight, weight);
+                }
+                return output.w > 0.0f ? output.xyz / output.w : unpackColor(image[y * width + x]);
+            }
 
-                    int idx = y * width + x;
-                    float old_pixel = inputImage[idx];
+            // The anisotropic mode finds the gradient products in the first pass and smooths them
+            // into tensors in the second, both kept in the scratch buffer
+            __kernel void kuwahara(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height,
+                const int pass,
+                __global float* scratch) {
 
-                    // Get the threshold matrix size (first element in options)
-                    int matrix_size = (int)options[0];
+                int x = get_global_id(0);
+                int y = get_global_id(1);
 
-                    // Get the corresponding threshold value from the threshold matrix
-                    int matrix_x = x % matrix_size;
-                    int matrix_y = y % matrix_size;
-                    float threshold = options[1 + matrix_y * matrix_size + matrix_x]; // Flattened matrix
+                if (x >= width || y >= height)
+                    return;
 
-                    // Quantize the pixel based on the threshold
-                    float new_pixel = old_pixel >= threshold ? 255.0f : 0.0f;
+                int idx = y * width + x;
+                int radius = (int)options[0];
+                int mode = (int)options[1];
+                __global float* products = scratch;
+                __global float* tensors = products + 3 * width * height;
 
-                    // Set the output pixel to the quantized value
-                    outputImage[idx] = new_pixel;
+                if (mode != 0 && pass == 0) {
+                    vstore3(gradientProducts(inputImage, x, y, width, height), idx, products);
+                    return;
+                }
+                if (mode != 0 && pass == 1) {
+                    vstore3(smoothTensor(products, x, y, width, height), idx, tensors);
+                    return;
                 }
+
+                float3 color = mode == 0
+                    ? classic(inputImage, x, y, width, height, radius)
+                    : anisotropic(inputImage, vload3(idx, tensors), x, y, width, height, radius, options[2]);
+
+                uint3 rgb = convert_uint3(clamp(color, 0.0f, 1.0f) * 255.0f + 0.5f);
+                outputImage[idx] = (float)((rgb.x << 16) | (rgb.y << 8) | rgb.z);
+            }
             "#,
-            "bayerOrderedDithering",
-        )
+            "kuwahara",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        vec![self.radius as f32, self.mode as i32 as f32, self.sharpness]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn get_schedule(&self, dimensions: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        let passes = match self.mode {
+            KuwaharaMode::Classic => 1,
+            KuwaharaMode::Anisotropic => 3,
+        };
+        Some((passes, dimensions))
+    }
+
+    // Gradient products and tensors, three values per pixel each
+    fn scratch_size(&self, dimensions: (u32, u32)) -> usize {
+        match self.mode {
+            KuwaharaMode::Classic => 0,
+            KuwaharaMode::Anisotropic => 6 * (dimensions.0 * dimensions.1) as usize,
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let radius = options[0] as isize;
+        let colors: Vec<[f32; 3]> = pixels
+            .iter()
+            .map(|&pixel| unpack_color(pixel).map(|c| c / 255.0))
+            .collect();
+
+        let output = if options[1] == 0.0 {
+            classic_kuwahara(&colors, dimensions, radius)
+        } else {
+            anisotropic_kuwahara(&colors, dimensions, radius, options[2])
+        };
+        output
+            .into_iter()
+            .map(|color| pack_color(color.map(|c| c.clamp(0.0, 1.0) * 255.0)))
+            .collect()
+    }
+}
+
+fn unpack_color(packed: f32) -> [f32; 3] {
+    let pixel = packed as u32;
+    [16, 8, 0].map(|shift| ((pixel >> shift) & 0xFF) as f32)
+}
+
+// Rounds 0..255 channels into a packed pixel
+fn pack_color(color: [f32; 3]) -> f32 {
+    let [r, g, b] = color.map(|c| (c + 0.5) as u32);
+    ((r << 16) | (g << 8) | b) as f32
+}
+
+fn luma(color: [f32; 3]) -> f32 {
+    0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2]
+}
+
+fn classic_kuwahara(colors: &[[f32; 3]], dimensions: (u32, u32), radius: isize) -> Vec<[f32; 3]> {
+    let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+    let count = ((radius + 1) * (radius + 1)) as f32;
+
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut best = [0.0; 3];
+            let mut lowest = f32::INFINITY;
+            for (sx, sy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let mut sum = [0.0; 3];
+                let (mut luma_sum, mut luma_squares) = (0.0, 0.0);
+                for i in 0..=radius {
+                    for j in 0..=radius {
+                        let nx = (x + sx * j).clamp(0, width - 1);
+                        let ny = (y + sy * i).clamp(0, height - 1);
+                        let color = colors[(ny * width + nx) as usize];
+                        let value = luma(color);
+                        sum.iter_mut().zip(color).for_each(|(sum, c)| *sum += c);
+                        luma_sum += value;
+                        luma_squares += value * value;
+                    }
+                }
+
+                let variance = luma_squares / count - (luma_sum / count).powi(2);
+                if variance < lowest {
+                    lowest = variance;
+                    best = sum.map(|sum| sum / count);
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+// Kyprianidis et al. anisotropic Kuwahara filter with polynomial sector weights
+fn anisotropic_kuwahara(
+    colors: &[[f32; 3]],
+    dimensions: (u32, u32),
+    radius: isize,
+    sharpness: f32,
+) -> Vec<[f32; 3]> {
+    let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+    let at = |x: isize, y: isize| (y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize;
+    let luminance: Vec<f32> = colors.iter().map(|&color| luma(color)).collect();
+
+    // Sobel gradients and their products, smoothed with a Gaussian of sigma two
+    let products: Vec<[f32; 3]> = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let l = |dx: isize, dy: isize| luminance[at(x + dx, y + dy)];
+            let gx = l(1, -1) + 2.0 * l(1, 0) + l(1, 1) - l(-1, -1) - 2.0 * l(-1, 0) - l(-1, 1);
+            let gy = l(-1, 1) + 2.0 * l(0, 1) + l(1, 1) - l(-1, -1) - 2.0 * l(0, -1) - l(1, -1);
+            [gx * gx, gx * gy, gy * gy]
+        })
+        .collect();
+    let tensors: Vec<[f32; 3]> = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut tensor = [0.0; 3];
+            let mut weights = 0.0;
+            for dy in -6..=6isize {
+                for dx in -6..=6isize {
+                    let weight = (-((dx * dx + dy * dy) as f32) / 8.0).exp();
+                    let product = products[at(x + dx, y + dy)];
+                    tensor
+                        .iter_mut()
+                        .zip(product)
+                        .for_each(|(t, p)| *t += weight * p);
+                    weights += weight;
+                }
+            }
+            tensor.map(|t| t / weights)
+        })
+        .collect();
+
+    let zeta = 2.0 / radius as f32;
+    let sine = (PI / 8.0).sin();
+    let eta = (zeta + (PI / 8.0).cos()) / (sine * sine);
+
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let [e, f, g] = tensors[i as usize];
+            let root = ((e - g).powi(2) + 4.0 * f * f).sqrt();
+            let (major, minor) = ((e + g + root) / 2.0, (e + g - root) / 2.0);
+
+            let length = (major - e).hypot(f);
+            let (cos_phi, sin_phi) = if length > 0.0 {
+                ((major - e) / length, -f / length)
+            } else {
+                (0.0, 1.0)
+            };
+            let anisotropy = if major + minor > 0.0 {
+                (major - minor) / (major + minor)
+            } else {
+                0.0
+            };
+
+            let a = radius as f32 * (1.0 + anisotropy).clamp(0.1, 2.0);
+            let b = radius as f32 * (1.0 / (1.0 + anisotropy)).clamp(0.1, 2.0);
+            let max_x = (a * a * cos_phi * cos_phi + b * b * sin_phi * sin_phi).sqrt() as isize;
+            let max_y = (a * a * sin_phi * sin_phi + b * b * cos_phi * cos_phi).sqrt() as isize;
+
+            let mut means = [[0.0f32; 4]; 8];
+            let mut squares = [[0.0f32; 3]; 8];
+            for dy in -max_y..=max_y {
+                for dx in -max_x..=max_x {
+                    let (dx_f, dy_f) = (dx as f32, dy as f32);
+                    let v = (
+                        0.5 / a * (cos_phi * dx_f + sin_phi * dy_f),
+                        0.5 / b * (-sin_phi * dx_f + cos_phi * dy_f),
+                    );
+                    let distance = v.0 * v.0 + v.1 * v.1;
+                    if distance > 0.25 {
+                        continue;
+                    }
+
+                    // Polynomial sector weights, the odd sectors from v rotated by 45 degrees
+                    let mut w = [0.0f32; 8];
+                    let r = (FRAC_1_SQRT_2 * (v.0 - v.1), FRAC_1_SQRT_2 * (v.0 + v.1));
+                    for (half, u) in [v, r].into_iter().enumerate() {
+                        let uxx = zeta - eta * u.0 * u.0;
+                        let uyy = zeta - eta * u.1 * u.1;
+                        w[half] = (u.1 + uxx).max(0.0).powi(2);
+                        w[2 + half] = (-u.0 + uyy).max(0.0).powi(2);
+                        w[4 + half] = (-u.1 + uxx).max(0.0).powi(2);
+                        w[6 + half] = (u.0 + uyy).max(0.0).powi(2);
+                    }
+                    let sum: f32 = w.iter().sum();
+                    if sum <= 0.0 {
+                        continue;
+                    }
+
+                    let color = colors[at(x + dx, y + dy)];
+                    let gaussian = (-3.125 * distance).exp() / sum;
+                    for k in 0..8 {
+                        let weight = w[k] * gaussian;
+                        for c in 0..3 {
+                            means[k][c] += color[c] * weight;
+                            squares[k][c] += color[c] * color[c] * weight;
+                        }
+                        means[k][3] += weight;
+                    }
+                }
+            }
+
+            let mut output = [0.0f32; 4];
+            for (mean, squares) in means.iter().zip(&squares) {
+                if mean[3] <= 0.0 {
+                    continue;
+                }
+                let average = [mean[0] / mean[3], mean[1] / mean[3], mean[2] / mean[3]];
+                let variance: f32 = (0..3)
+                    .map(|c| (squares[c] / mean[3] - average[c] * average[c]).abs())
+                    .sum();
+                let weight = 1.0 / (1.0 + (255.0 * variance).powf(0.5 * sharpness));
+                for c in 0..3 {
+                    output[c] += average[c] * weight;
+                }
+                output[3] += weight;
+            }
+
+            if output[3] > 0.0 {
+                [output[0], output[1], output[2]].map(|c| c / output[3])
+            } else {
+                colors[i as usize]
+            }
+        })
+        .collect()
+}
+
+const ZERO_CROSSING_KERNEL: (&str, &str) = (
+    r#"
+    float convolveAt(
+        __global const float* image,
+        __global const float* options,
+        int x, int y, int width, int height) {
+
+        int kernelSize = (int)options[0];
+        int halfKernel = kernelSize / 2;
+
+        float sum = 0.0;
+        for (int ky = -halfKernel; ky <= halfKernel; ky++) {
+            for (int kx = -halfKernel; kx <= halfKernel; kx++) {
+                // Replicate the border pixels
+                int nx = clamp(x + kx, 0, width - 1);
+                int ny = clamp(y + ky, 0, height - 1);
+                int kernelIndex = (ky + halfKernel) * kernelSize + (kx + halfKernel);
+                sum += options[3 + kernelIndex] * image[ny * width + nx];
+            }
+        }
+        return sum;
+    }
+
+    __kernel void zeroCrossing(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        if (x >= width || y >= height)
+            return;
+
+        float response = convolveAt(inputImage, options, x, y, width, height);
+        if (options[1] == 0.0f) {
+            outputImage[y * width + x] = response;
+            return;
+        }
+
+        // Mark sign changes towards the right and bottom neighbours
+        float threshold = options[2];
+        float edge = 0.0f;
+        if (x + 1 < width) {
+            float right = convolveAt(inputImage, options, x + 1, y, width, height);
+            if (response * right < 0.0f && fabs(response - right) > threshold)
+                edge = 1.0f;
+        }
+        if (y + 1 < height) {
+            float bottom = convolveAt(inputImage, options, x, y + 1, width, height);
+            if (response * bottom < 0.0f && fabs(response - bottom) > threshold)
+                edge = 1.0f;
+        }
+        outputImage[y * width + x] = edge;
+    }
+    "#,
+    "zeroCrossing",
+);
+
+fn gaussian_kernel_size(sigma: f32) -> usize {
+    2 * (3.0 * sigma).ceil().max(1.0) as usize + 1
+}
+
+fn gaussian_kernel(sigma: f32, size: usize) -> Vec<f32> {
+    let half = (size / 2) as isize;
+
+    let mut kernel = vec![0.0; size * size];
+    for y in -half..=half {
+        for x in -half..=half {
+            let value = (-(x * x + y * y) as f32 / (2.0 * sigma * sigma)).exp();
+            kernel[((y + half) as usize) * size + (x + half) as usize] = value;
+        }
     }
 
-    fn compute_options(&self, _: &[f32]) -> Vec<f32> {
-        // Define a 4x4 Bayer Matrix
-        let mut threshold_matrix = vec![
-            vec![0.0, 8.0, 2.0, 10.0],
-            vec![12.0, 4.0, 14.0, 6.0],
-            vec![3.0, 11.0, 1.0, 9.0],
-            vec![15.0, 7.0, 13.0, 5.0],
-        ];
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|v| *v /= sum);
+    kernel
+}
+
+fn zero_crossing_options(size: usize, zero_crossing: Option<f32>, kernel: Vec<f32>) -> Vec<f32> {
+    let mut options = vec![
+        size as f32,
+        zero_crossing.is_some() as i32 as f32,
+        zero_crossing.unwrap_or(0.0),
+    ];
+    options.extend(kernel);
+    options
+}
+
+fn convolve_zero_crossing(pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+    let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+    let kernel_size = options[0] as isize;
+    let half_kernel = kernel_size / 2;
 
-        let matrix_size = 4;
-        for row in &mut threshold_matrix {
-            for value in row.iter_mut() {
-                *value /= 16.0;
+    let mut response = vec![0.0; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for ky in -half_kernel..=half_kernel {
+                for kx in -half_kernel..=half_kernel {
+                    let nx = (x + kx).clamp(0, width - 1);
+                    let ny = (y + ky).clamp(0, height - 1);
+                    let kernel_index = (ky + half_kernel) * kernel_size + kx + half_kernel;
+                    sum += options[3 + kernel_index as usize] * pixels[(ny * width + nx) as usize];
+                }
             }
+            response[(y * width + x) as usize] = sum;
         }
+    }
+
+    if options[1] == 0.0 {
+        return response;
+    }
+
+    let threshold = options[2];
+    let crosses = |a: f32, b: f32| a * b < 0.0 && (a - b).abs() > threshold;
+    (0..response.len())
+        .map(|i| {
+            let (x, y) = (i as isize % width, i as isize / width);
+            let right = x + 1 < width && crosses(response[i], response[i + 1]);
+            let bottom = y + 1 < height && crosses(response[i], response[i + width as usize]);
+            if right || bottom {
+                1.0
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+pub fn convolve_3x3(pixels: &[f32], weights: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
 
-        let mut options = vec![matrix_size as f32];
-        for row in threshold_matrix {
-            options.extend(row);
+    let mut output = vec![0.0; pixels.len()];
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let mut value = 0.0;
+            for i in 0..3 {
+                for j in 0..3 {
+                    value += weights[i * 3 + j] * pixels[(y + i - 1) * width + (x + j - 1)];
+                }
+            }
+            output[y * width + x] = value;
         }
-        options
+    }
+    output
+}
+
+// Rotates the outer ring of a 3x3 kernel, listed clockwise from the top left corner
+fn compass_kernels(ring: [f32; 8]) -> Vec<[f32; 9]> {
+    let positions = [0, 1, 2, 5, 8, 7, 6, 3];
+
+    (0..8)
+        .map(|rotation| {
+            let mut kernel = [0.0; 9];
+            for (i, &position) in positions.iter().enumerate() {
+                kernel[position] = ring[(i + rotation) % 8];
+            }
+            kernel
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    // Brightness rising by `step` per column
+    fn ramp(step: f32) -> Vec<f32> {
+        (0..25).map(|i| (i % 5) as f32 * step).collect()
+    }
+
+    fn gradient(operator: GradientOperator, output: GradientOutput, pixels: &[f32]) -> Vec<f32> {
+        let filter = GradientFilter {
+            output,
+            ..GradientFilter::new(operator)
+        };
+        run(&filter, pixels, (5, 5))
+    }
+
+    #[test]
+    fn gradient_kernels_ignore_flat_regions() {
+        for operator in [
+            GradientOperator::Sobel,
+            GradientOperator::Prewitt,
+            GradientOperator::Scharr,
+            GradientOperator::Roberts,
+            GradientOperator::Kirsch,
+            GradientOperator::Robinson,
+        ] {
+            for kernel in operator.kernels() {
+                assert_eq!(kernel.iter().sum::<f32>(), 0.0, "{operator:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn horizontal_ramp_only_has_an_x_gradient() {
+        let pixels = ramp(0.25);
+        for (operator, scale) in [
+            (GradientOperator::Sobel, 8.0),
+            (GradientOperator::Prewitt, 6.0),
+            (GradientOperator::Scharr, 32.0),
+        ] {
+            let x = gradient(operator, GradientOutput::X, &pixels);
+            let y = gradient(operator, GradientOutput::Y, &pixels);
+            let magnitude = gradient(operator, GradientOutput::Magnitude, &pixels);
+
+            assert!((x[12] - 0.25 * scale).abs() < 1e-5, "{operator:?}");
+            assert_eq!(y[12], 0.0);
+            assert!((magnitude[12] - x[12]).abs() < 1e-6);
+            // Borders are left at zero
+            assert_eq!(magnitude[0], 0.0);
+        }
+    }
+
+    #[test]
+    fn orientation_maps_the_angle_to_the_unit_range() {
+        let rising = gradient(
+            GradientOperator::Sobel,
+            GradientOutput::Orientation,
+            &ramp(0.25),
+        );
+        let falling = gradient(
+            GradientOperator::Sobel,
+            GradientOutput::Orientation,
+            &ramp(-0.25),
+        );
+        assert_eq!(rising[12], 0.5);
+        assert!(falling[12] == 0.0 || falling[12] == 1.0);
+    }
+
+    #[test]
+    fn compass_operators_pick_the_strongest_direction() {
+        let pixels = ramp(0.25);
+        let orientation = gradient(
+            GradientOperator::Kirsch,
+            GradientOutput::Orientation,
+            &pixels,
+        );
+        let magnitude = gradient(GradientOperator::Kirsch, GradientOutput::Magnitude, &pixels);
+
+        // East is the first kernel
+        assert_eq!(orientation[12], 0.0);
+        assert!((magnitude[12] - 6.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sobel_and_prewitt_match_the_generic_operator() {
+        let pixels: Vec<f32> = (0..25).map(|i| ((i * 7) % 5) as f32 / 4.0).collect();
+        assert_eq!(
+            run(&SobelFilter, &pixels, (5, 5)),
+            gradient(GradientOperator::Sobel, GradientOutput::Magnitude, &pixels)
+        );
+        assert_eq!(
+            run(&PrewittFilter, &pixels, (5, 5)),
+            gradient(
+                GradientOperator::Prewitt,
+                GradientOutput::Magnitude,
+                &pixels
+            )
+        );
+    }
+
+    #[test]
+    fn vectors_keep_the_gradient_sign() {
+        let filter = GradientFilter::new(GradientOperator::Sobel);
+        let vectors = filter.vectors(&ramp(-0.25), (5, 5), Backend::Cpu);
+        assert_eq!(vectors.len(), 25);
+        assert_eq!(vectors[12], [-2.0, 0.0]);
+        assert_eq!(vectors[0], [0.0, 0.0]);
+
+        let interleaved = GradientFilter {
+            output: GradientOutput::Vectors,
+            ..filter
+        };
+        assert_eq!(interleaved.output_dimensions((5, 5)), (10, 5));
+        assert_eq!(run(&interleaved, &ramp(-0.25), (5, 5))[24..26], [-2.0, 0.0]);
+    }
+
+    #[test]
+    fn max_magnitude_bounds_each_operator() {
+        assert!((GradientOperator::Sobel.max_magnitude() - 32f32.sqrt()).abs() < 1e-5);
+        assert_eq!(GradientOperator::Kirsch.max_magnitude(), 15.0);
+        assert_eq!(GradientOperator::Robinson.max_magnitude(), 4.0);
+    }
+
+    #[test]
+    fn colour_orientation_brightness_does_not_depend_on_other_edges() {
+        let packed = |values: &[f32]| -> Vec<f32> {
+            values
+                .iter()
+                .map(|value| ((value * 255.0) as u32 * 0x010101) as f32)
+                .collect()
+        };
+        let filter = GradientFilter {
+            output: GradientOutput::ColorOrientation,
+            ..GradientFilter::new(GradientOperator::Sobel)
+        };
+
+        let gentle = ramp(0.1);
+        let mut steep = gentle.clone();
+        steep[4] = 1.0;
+        let (a, b) = (
+            run(&filter, &packed(&gentle), (5, 5)),
+            run(&filter, &packed(&steep), (5, 5)),
+        );
+        assert_eq!(a[12], b[12]);
+
+        // A smaller scale saturates the same edge
+        let scaled = GradientFilter {
+            scale: Some(0.5),
+            ..filter
+        };
+        assert!(run(&scaled, &packed(&gentle), (5, 5))[12] > a[12]);
+    }
+
+    // Bright disc of radius three in the middle of a dark 15x15 image
+    fn blob() -> Vec<f32> {
+        (0..225)
+            .map(|i| {
+                let (x, y) = (i % 15 - 7, i / 15 - 7);
+                if x * x + y * y <= 9 {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blob_detectors_peak_on_bright_blobs() {
+        let log = LaplacianOfGaussian::new(2.0).unwrap();
+        let dog = DifferenceOfGaussians::new(1.5, 3.0).unwrap();
+        for response in [run(&log, &blob(), (15, 15)), run(&dog, &blob(), (15, 15))] {
+            let peak = (0..225)
+                .max_by(|&a, &b| response[a].total_cmp(&response[b]))
+                .unwrap();
+            assert_eq!(peak, 112);
+            assert!(response[112] > 0.0);
+        }
+    }
+
+    #[test]
+    fn blob_detectors_ignore_flat_images() {
+        let log = LaplacianOfGaussian::new(1.5).unwrap();
+        let response = run(&log, &[0.6; 81], (9, 9));
+        assert!(response.iter().all(|value| value.abs() < 1e-5));
+    }
+
+    #[test]
+    fn zero_crossings_ring_the_blob() {
+        let log = LaplacianOfGaussian {
+            zero_crossing: Some(0.0),
+            ..LaplacianOfGaussian::new(1.0).unwrap()
+        };
+        let edges = run(&log, &blob(), (15, 15));
+
+        assert!(edges.iter().all(|&value| value == 0.0 || value == 1.0));
+        assert_eq!(edges[112], 0.0);
+        assert_eq!(edges[0], 0.0);
+        // Each row through the blob crosses zero on both sides of it
+        let row: Vec<usize> = (0..15).filter(|&x| edges[7 * 15 + x] == 1.0).collect();
+        assert!(row.first() < Some(&7) && row.last() >= Some(&7));
+    }
+
+    #[test]
+    fn blob_detectors_reject_non_positive_sigma() {
+        let invalid = |name| FilterError::InvalidParameter {
+            name,
+            reason: "must be positive",
+        };
+        assert_eq!(LaplacianOfGaussian::new(0.0).err(), Some(invalid("Sigma")));
+        assert_eq!(
+            LaplacianOfGaussian::new(f32::NAN).err(),
+            Some(invalid("Sigma"))
+        );
+        assert_eq!(
+            DifferenceOfGaussians::new(1.0, -2.0).err(),
+            Some(invalid("Wide sigma"))
+        );
+    }
+
+    #[test]
+    fn difference_of_gaussians_needs_a_wider_second_sigma() {
+        let swapped = Some(FilterError::InvalidParameter {
+            name: "Narrow sigma",
+            reason: "must be smaller than the wide sigma",
+        });
+        assert_eq!(DifferenceOfGaussians::new(2.0, 2.0).err(), swapped);
+        assert_eq!(DifferenceOfGaussians::new(3.0, 1.0).err(), swapped);
+        assert!(DifferenceOfGaussians::new(1.0, 1.6).is_ok());
+    }
+
+    // Dark left half and bright right half, split between columns 3 and 4
+    fn halves() -> Vec<f32> {
+        (0..64)
+            .map(|i| {
+                if i % 8 < 4 {
+                    0x202020 as f32
+                } else {
+                    0xE0E0E0 as f32
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn emboss_is_mid_grey_on_flat_regions() {
+        let output = run(&Emboss::new(0.0), &[0.3; 25], (5, 5));
+        assert!(output.iter().all(|value| (value - 0.5).abs() < 1e-6));
+
+        // Lit from the right, so brightness rising to the right stands out
+        let output = run(&Emboss::new(0.0), &ramp(0.1), (5, 5));
+        assert!(output[12] > 0.5);
+        assert!(run(&Emboss::new(180.0), &ramp(0.1), (5, 5))[12] < 0.5);
+    }
+
+    #[test]
+    fn pixelate_averages_each_block() {
+        let pixels: Vec<f32> = (0..16).map(|i| i as f32).collect();
+        let output = run(&Pixelate::new(2).unwrap(), &pixels, (4, 4));
+        assert_eq!(&output[..4], &[2.5, 2.5, 4.5, 4.5]);
+        assert_eq!(output[4], 2.5);
+        assert_eq!(output[15], 12.5);
+
+        // Partial blocks at the border average what they cover
+        let output = run(&Pixelate::new(3).unwrap(), &pixels, (4, 4));
+        assert_eq!(output[15], 15.0);
+    }
+
+    #[test]
+    fn posterize_rounds_to_the_nearest_level() {
+        let output = run(
+            &Posterize::new(3).unwrap(),
+            &[0.0, 0.2, 0.3, 0.7, 1.0, 1.5],
+            (6, 1),
+        );
+        assert_eq!(output, vec![0.0, 0.0, 0.5, 0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn painterly_filters_keep_flat_images() {
+        let pixels = vec![0x336699 as f32; 49];
+        assert_eq!(run(&OilPaint::new(2, 20).unwrap(), &pixels, (7, 7)), pixels);
+        for mode in [KuwaharaMode::Classic, KuwaharaMode::Anisotropic] {
+            assert_eq!(
+                run(&Kuwahara::new(2, mode).unwrap(), &pixels, (7, 7)),
+                pixels
+            );
+        }
+    }
+
+    #[test]
+    fn painterly_filters_keep_edges() {
+        let pixels = halves();
+        assert_eq!(run(&OilPaint::new(1, 20).unwrap(), &pixels, (8, 8)), pixels);
+        assert_eq!(
+            run(
+                &Kuwahara::new(2, KuwaharaMode::Classic).unwrap(),
+                &pixels,
+                (8, 8)
+            ),
+            pixels
+        );
+
+        let output = run(
+            &Kuwahara::new(2, KuwaharaMode::Anisotropic).unwrap(),
+            &pixels,
+            (8, 8),
+        );
+        for row in output.chunks(8) {
+            assert!(row[1] < 0x808080 as f32 && row[6] > 0x808080 as f32);
+        }
+    }
+
+    #[test]
+    fn artistic_filters_reject_invalid_parameters() {
+        let invalid = |name, reason| Some(FilterError::InvalidParameter { name, reason });
+        assert_eq!(
+            Pixelate::new(0).err(),
+            invalid("Block size", "must be at least one")
+        );
+        assert_eq!(
+            Posterize::new(1).err(),
+            invalid("Levels", "must be at least two")
+        );
+        for levels in [0, 65] {
+            assert_eq!(
+                OilPaint::new(2, levels).err(),
+                invalid("Intensity levels", "must be between 1 and 64")
+            );
+        }
+        assert_eq!(
+            Kuwahara::new(0, KuwaharaMode::Anisotropic).err(),
+            invalid("Radius", "must be at least one")
+        );
+
+        assert!(Pixelate::new(1).is_ok() && Posterize::new(2).is_ok());
+        assert!(OilPaint::new(0, 1).is_ok() && OilPaint::new(3, 64).is_ok());
+    }
+
+    #[test]
+    fn anisotropic_kuwahara_reserves_tensor_space() {
+        let classic = Kuwahara::new(2, KuwaharaMode::Classic).unwrap();
+        let anisotropic = Kuwahara::new(2, KuwaharaMode::Anisotropic).unwrap();
+        assert_eq!(anisotropic.compute_options(&[], (4, 3)).len(), 3);
+        assert_eq!(classic.scratch_size((4, 3)), 0);
+        assert_eq!(anisotropic.scratch_size((4, 3)), 6 * 12);
+        assert_eq!(classic.get_schedule((4, 3)), Some((1, (4, 3))));
+        assert_eq!(anisotropic.get_schedule((4, 3)), Some((3, (4, 3))));
     }
 }
@@ -0,0 +1,602 @@
+use std::f32::consts::PI;
+use std::ops::{Add, Mul, Sub};
+
+use super::error::{check_size, FilterError};
+use super::filters::{ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpectrumComponent {
+    // Log magnitude, normalised to the strongest frequency
+    Magnitude,
+    Phase,
+}
+
+// Shows the spectrum of the luminance with the zero frequency in the centre
+pub struct Spectrum {
+    pub component: SpectrumComponent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterShape {
+    Ideal,
+    // Order of the filter, higher is closer to ideal
+    Butterworth(u32),
+    Gaussian,
+}
+
+// Frequencies are in cycles per pixel, from 0 up to 0.5 at Nyquist
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrequencyBand {
+    LowPass(f32),
+    HighPass(f32),
+    BandReject {
+        center: f32,
+        width: f32,
+    },
+    // Rejects each (fx, fy) frequency together with its mirror image
+    Notch {
+        frequencies: Vec<(f32, f32)>,
+        radius: f32,
+    },
+}
+
+pub struct FrequencyFilter {
+    pub shape: FilterShape,
+    pub band: FrequencyBand,
+}
+
+// Correlates the image with a large kernel through the FFT, replicating the borders
+pub struct FftConvolution {
+    // Row-major weights, anchored at their centre and checked against their size in `new`
+    kernel: Vec<f32>,
+    kernel_dimensions: (u32, u32),
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn from_polar(magnitude: f32, angle: f32) -> Self {
+        Self::new(magnitude * angle.cos(), magnitude * angle.sin())
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn norm(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f32 {
+        self.im.atan2(self.re)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Mul<f32> for Complex {
+    type Output = Complex;
+
+    fn mul(self, scale: f32) -> Complex {
+        Complex::new(self.re * scale, self.im * scale)
+    }
+}
+
+// Discrete Fourier transform of any length, the inverse is scaled by 1/n
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    if n <= 1 {
+        return;
+    }
+
+    // The inverse is the conjugate of the forward transform of the conjugate
+    if inverse {
+        data.iter_mut().for_each(|value| *value = value.conj());
+    }
+    if n.is_power_of_two() {
+        radix2(data);
+    } else {
+        bluestein(data);
+    }
+    if inverse {
+        let scale = 1.0 / n as f32;
+        data.iter_mut()
+            .for_each(|value| *value = value.conj() * scale);
+    }
+}
+
+// Transforms the rows and then the columns of a row-major image
+pub fn fft_2d(data: &mut [Complex], dimensions: (u32, u32), inverse: bool) {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    for row in data.chunks_mut(width) {
+        fft(row, inverse);
+    }
+
+    let mut column = vec![Complex::default(); height];
+    for x in 0..width {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = data[y * width + x];
+        }
+        fft(&mut column, inverse);
+        for (y, value) in column.iter().enumerate() {
+            data[y * width + x] = *value;
+        }
+    }
+}
+
+// Swaps the quadrants so that the zero frequency moves to the centre
+pub fn fft_shift<T: Copy>(data: &[T], dimensions: (u32, u32)) -> Vec<T> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    (0..width * height)
+        .map(|i| {
+            let x = (i % width + width.div_ceil(2)) % width;
+            let y = (i / width + height.div_ceil(2)) % height;
+            data[y * width + x]
+        })
+        .collect()
+}
+
+// Signed frequency of every sample along an axis, in cycles per pixel
+fn frequencies(length: usize) -> Vec<f32> {
+    (0..length)
+        .map(|i| {
+            let i = if i > length / 2 {
+                i as f32 - length as f32
+            } else {
+                i as f32
+            };
+            i / length as f32
+        })
+        .collect()
+}
+
+fn radix2(data: &mut [Complex]) {
+    let n = data.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let twiddles: Vec<Complex> = (0..length / 2)
+            .map(|k| Complex::from_polar(1.0, -2.0 * PI * k as f32 / length as f32))
+            .collect();
+        for block in data.chunks_mut(length) {
+            let (lower, upper) = block.split_at_mut(length / 2);
+            for ((a, b), &twiddle) in lower.iter_mut().zip(upper.iter_mut()).zip(&twiddles) {
+                let product = *b * twiddle;
+                (*a, *b) = (*a + product, *a - product);
+            }
+        }
+        length *= 2;
+    }
+}
+
+// Arbitrary lengths as a chirp convolution evaluated with power of two transforms
+fn bluestein(data: &mut [Complex]) {
+    let n = data.len();
+    let m = (2 * n - 1).next_power_of_two();
+
+    // k^2 is reduced modulo 2n first to keep the angle accurate for long inputs
+    let chirp: Vec<Complex> = (0..n)
+        .map(|k| {
+            let square = (k * k) % (2 * n);
+            Complex::from_polar(1.0, -PI * square as f32 / n as f32)
+        })
+        .collect();
+
+    let mut a = vec![Complex::default(); m];
+    for ((a, value), chirp) in a.iter_mut().zip(data.iter()).zip(&chirp) {
+        *a = *value * *chirp;
+    }
+    let mut b = vec![Complex::default(); m];
+    b[0] = chirp[0].conj();
+    for k in 1..n {
+        b[k] = chirp[k].conj();
+        b[m - k] = chirp[k].conj();
+    }
+
+    fft(&mut a, false);
+    fft(&mut b, false);
+    a.iter_mut().zip(&b).for_each(|(a, b)| *a = *a * *b);
+    fft(&mut a, true);
+
+    for ((value, a), chirp) in data.iter_mut().zip(&a).zip(&chirp) {
+        *value = *a * *chirp;
+    }
+}
+
+fn to_complex(pixels: &[f32]) -> Vec<Complex> {
+    pixels
+        .iter()
+        .map(|&pixel| Complex::new(pixel, 0.0))
+        .collect()
+}
+
+// Runs a single channel operation on each colour channel of packed pixels
+fn per_channel(pixels: &[f32], operation: impl Fn(&[f32]) -> Vec<f32>) -> Vec<f32> {
+    let (r, g, b) = ImageConverter::decompose_rgb(&ImageConverter::convert_packed_to_rgb(pixels));
+    let (r, g, b) = (operation(&r), operation(&g), operation(&b));
+
+    // Rounded rather than truncated, as the round trip leaves values just below whole levels
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+    (0..pixels.len())
+        .map(|i| ((to_byte(r[i]) << 16) | (to_byte(g[i]) << 8) | to_byte(b[i])) as f32)
+        .collect()
+}
+
+impl Spectrum {
+    pub fn new(component: SpectrumComponent) -> Self {
+        Self { component }
+    }
+}
+
+// The FFT only runs on the CPU, so none of the frequency stages have a kernel
+impl ImageFilter for Spectrum {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Grayscale
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let mut spectrum = to_complex(pixels);
+        fft_2d(&mut spectrum, dimensions, false);
+
+        let values: Vec<f32> = match self.component {
+            SpectrumComponent::Magnitude => {
+                let magnitudes: Vec<f32> =
+                    spectrum.iter().map(|value| value.norm().ln_1p()).collect();
+                let strongest = magnitudes.iter().copied().fold(f32::EPSILON, f32::max);
+                magnitudes.iter().map(|value| value / strongest).collect()
+            }
+            SpectrumComponent::Phase => spectrum
+                .iter()
+                .map(|value| (value.arg() + PI) / (2.0 * PI))
+                .collect(),
+        };
+        fft_shift(&values, dimensions)
+    }
+}
+
+impl FrequencyFilter {
+    pub fn new(shape: FilterShape, band: FrequencyBand) -> Self {
+        Self { shape, band }
+    }
+
+    // Low-pass transfer function at a distance from the pass band's centre
+    fn low_pass(&self, distance: f32, cutoff: f32) -> f32 {
+        let cutoff = cutoff.max(f32::EPSILON);
+        match self.shape {
+            FilterShape::Ideal => (distance <= cutoff) as i32 as f32,
+            FilterShape::Butterworth(order) => {
+                1.0 / (1.0 + (distance / cutoff).powi(2 * order.max(1) as i32))
+            }
+            FilterShape::Gaussian => (-distance * distance / (2.0 * cutoff * cutoff)).exp(),
+        }
+    }
+
+    // Gain for every frequency of an image, in unshifted order
+    pub fn response(&self, dimensions: (u32, u32)) -> Vec<f32> {
+        let (fx, fy) = (
+            frequencies(dimensions.0 as usize),
+            frequencies(dimensions.1 as usize),
+        );
+
+        fy.iter()
+            .flat_map(|&v| fx.iter().map(move |&u| (u, v)))
+            .map(|(u, v)| {
+                let distance = u.hypot(v);
+                match &self.band {
+                    FrequencyBand::LowPass(cutoff) => self.low_pass(distance, *cutoff),
+                    FrequencyBand::HighPass(cutoff) => 1.0 - self.low_pass(distance, *cutoff),
+                    FrequencyBand::BandReject { center, width } => {
+                        let width = width.max(f32::EPSILON);
+                        match self.shape {
+                            FilterShape::Ideal => {
+                                ((distance - center).abs() > width / 2.0) as i32 as f32
+                            }
+                            FilterShape::Butterworth(order) => {
+                                let ratio = distance * width / (distance.powi(2) - center.powi(2));
+                                1.0 / (1.0 + ratio.powi(2 * order.max(1) as i32))
+                            }
+                            FilterShape::Gaussian => {
+                                let ratio = (distance.powi(2) - center.powi(2))
+                                    / (distance * width).max(f32::EPSILON);
+                                1.0 - (-ratio * ratio).exp()
+                            }
+                        }
+                    }
+                    FrequencyBand::Notch {
+                        frequencies,
+                        radius,
+                    } => frequencies
+                        .iter()
+                        .map(|&(nu, nv)| {
+                            (1.0 - self.low_pass((u - nu).hypot(v - nv), *radius))
+                                * (1.0 - self.low_pass((u + nu).hypot(v + nv), *radius))
+                        })
+                        .product(),
+                }
+            })
+            .map(|gain| if gain.is_nan() { 1.0 } else { gain })
+            .collect()
+    }
+
+    pub fn apply(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let response = self.response(dimensions);
+        let mut spectrum = to_complex(pixels);
+        fft_2d(&mut spectrum, dimensions, false);
+        spectrum
+            .iter_mut()
+            .zip(&response)
+            .for_each(|(value, &gain)| *value = *value * gain);
+        fft_2d(&mut spectrum, dimensions, true);
+        spectrum.iter().map(|value| value.re).collect()
+    }
+}
+
+impl ImageFilter for FrequencyFilter {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        per_channel(pixels, |channel| self.apply(channel, dimensions))
+    }
+}
+
+impl FftConvolution {
+    pub fn new(kernel: Vec<f32>, kernel_dimensions: (u32, u32)) -> Result<Self, FilterError> {
+        check_size("Convolution kernel", kernel.len(), kernel_dimensions)?;
+        Ok(Self {
+            kernel,
+            kernel_dimensions,
+        })
+    }
+
+    pub fn kernel(&self) -> (&[f32], (u32, u32)) {
+        (&self.kernel, self.kernel_dimensions)
+    }
+
+    pub fn apply(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            return Vec::new();
+        }
+
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let (kernel_width, kernel_height) = (
+            self.kernel_dimensions.0 as isize,
+            self.kernel_dimensions.1 as isize,
+        );
+        let (half_x, half_y) = (kernel_width / 2, kernel_height / 2);
+
+        // Padding by the kernel size on every side keeps the circular wrap out of the image
+        let padded = (
+            (width + kernel_width).max(1) as usize,
+            (height + kernel_height).max(1) as usize,
+        );
+        let size = (padded.0.next_power_of_two(), padded.1.next_power_of_two());
+
+        let mut image = vec![Complex::default(); size.0 * size.1];
+        for y in 0..size.1 as isize {
+            for x in 0..size.0 as isize {
+                let (sx, sy) = (
+                    (x - half_x).clamp(0, width - 1),
+                    (y - half_y).clamp(0, height - 1),
+                );
+                image[y as usize * size.0 + x as usize].re = pixels[(sy * width + sx) as usize];
+            }
+        }
+
+        // Correlation is convolution with the kernel mirrored through its anchor
+        let mut kernel = vec![Complex::default(); size.0 * size.1];
+        for ky in 0..kernel_height {
+            for kx in 0..kernel_width {
+                let x = (half_x - (kx - half_x)).rem_euclid(size.0 as isize) as usize;
+                let y = (half_y - (ky - half_y)).rem_euclid(size.1 as isize) as usize;
+                kernel[y * size.0 + x].re = self.kernel[(ky * kernel_width + kx) as usize];
+            }
+        }
+
+        let size_u32 = (size.0 as u32, size.1 as u32);
+        fft_2d(&mut image, size_u32, false);
+        fft_2d(&mut kernel, size_u32, false);
+        image
+            .iter_mut()
+            .zip(&kernel)
+            .for_each(|(value, weight)| *value = *value * *weight);
+        fft_2d(&mut image, size_u32, true);
+
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| image[(y + 2 * half_y) as usize * size.0 + (x + 2 * half_x) as usize].re)
+            .collect()
+    }
+}
+
+impl ImageFilter for FftConvolution {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        per_channel(pixels, |channel| self.apply(channel, dimensions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn ramp(count: usize) -> Vec<f32> {
+        (0..count).map(|i| ((i * 37) % 11) as f32 / 10.0).collect()
+    }
+
+    #[test]
+    fn fft_round_trips() {
+        // Powers of two use radix-2, anything else goes through Bluestein
+        for length in [8, 12, 7] {
+            let original = to_complex(&ramp(length));
+            let mut data = original.clone();
+            fft(&mut data, false);
+            fft(&mut data, true);
+            for (a, b) in data.iter().zip(&original) {
+                assert!((a.re - b.re).abs() < 1e-4 && a.im.abs() < 1e-4, "{length}");
+            }
+        }
+    }
+
+    #[test]
+    fn impulse_has_a_flat_spectrum() {
+        for length in [8, 6] {
+            let mut data = vec![Complex::default(); length];
+            data[0].re = 1.0;
+            fft(&mut data, false);
+            for value in data {
+                assert!((value.re - 1.0).abs() < 1e-5 && value.im.abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn shift_moves_the_zero_frequency_to_the_centre() {
+        let data: Vec<u32> = (0..12).collect();
+        let shifted = fft_shift(&data, (4, 3));
+        assert_eq!(shifted[4 + 2], 0);
+    }
+
+    #[test]
+    fn spectrum_of_a_flat_image_is_a_single_peak() {
+        let output = run(
+            &Spectrum::new(SpectrumComponent::Magnitude),
+            &[0.5; 16],
+            (4, 4),
+        );
+        assert_eq!(output[2 * 4 + 2], 1.0);
+        assert_eq!(output.iter().filter(|&&value| value > 1e-4).count(), 1);
+    }
+
+    #[test]
+    fn high_pass_removes_the_mean_and_low_pass_keeps_it() {
+        let pixels = ramp(48);
+        let mean = pixels.iter().sum::<f32>() / 48.0;
+
+        let high = FrequencyFilter::new(FilterShape::Gaussian, FrequencyBand::HighPass(0.1));
+        let filtered = high.apply(&pixels, (8, 6));
+        assert!((filtered.iter().sum::<f32>() / 48.0).abs() < 1e-4);
+
+        // Only the zero frequency is below the cutoff
+        let low = FrequencyFilter::new(FilterShape::Ideal, FrequencyBand::LowPass(0.01));
+        for value in low.apply(&pixels, (8, 6)) {
+            assert!((value - mean).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn cutoff_beyond_nyquist_passes_everything() {
+        let pixels = ramp(48);
+        let low = FrequencyFilter::new(FilterShape::Ideal, FrequencyBand::LowPass(1.0));
+        for (a, b) in low.apply(&pixels, (8, 6)).iter().zip(&pixels) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn fft_convolution_matches_direct_correlation() {
+        let (width, height) = (7usize, 5usize);
+        let pixels = ramp(width * height);
+        let weights = vec![0.0, 1.0, 0.5, 2.0, -1.0, 0.25];
+        let convolution = FftConvolution::new(weights.clone(), (3, 2)).unwrap();
+        let output = convolution.apply(&pixels, (7, 5));
+
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut expected = 0.0;
+                for ky in 0..2 {
+                    for kx in 0..3 {
+                        let sx = (x + kx - 1).clamp(0, width as isize - 1);
+                        let sy = (y + ky - 1).clamp(0, height as isize - 1);
+                        expected += weights[(ky * 3 + kx) as usize]
+                            * pixels[sy as usize * width + sx as usize];
+                    }
+                }
+                let actual = output[y as usize * width + x as usize];
+                assert!((actual - expected).abs() < 1e-4, "{x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn convolution_kernel_must_match_its_size() {
+        assert!(FftConvolution::new(vec![1.0; 8], (3, 3)).is_err());
+    }
+
+    #[test]
+    fn empty_images_stay_empty() {
+        let convolution = FftConvolution::new(vec![1.0 / 9.0; 9], (3, 3)).unwrap();
+        let filter = FrequencyFilter::new(FilterShape::Gaussian, FrequencyBand::LowPass(0.1));
+        for dimensions in [(0, 0), (0, 4), (5, 0)] {
+            assert!(convolution.apply(&[], dimensions).is_empty());
+            assert!(filter.apply(&[], dimensions).is_empty());
+            assert!(run(&Spectrum::new(SpectrumComponent::Phase), &[], dimensions).is_empty());
+        }
+    }
+}
@@ -0,0 +1,1238 @@
+use std::f32::consts::PI;
+
+use super::error::FilterError;
+use super::filters::{ColorMode, ImageFilter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResizeMode {
+    Exact(u32, u32),
+    // Largest size inside the box that keeps the aspect ratio
+    Fit(u32, u32),
+    // Covers the box keeping the aspect ratio, cropping the overflow evenly
+    Fill(u32, u32),
+}
+
+pub struct Resize {
+    pub mode: ResizeMode,
+    pub interpolation: Interpolation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BorderMode {
+    // 0xRRGGBB
+    Constant(u32),
+    Replicate,
+    // Mirrored without repeating the edge pixel
+    Reflect,
+    Wrap,
+}
+
+// Rotations are clockwise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    Transpose,
+}
+
+// Lossless pixel rearrangement
+pub struct Reorient {
+    pub orientation: Orientation,
+}
+
+// Matrices map source to destination coordinates, with pixel centres at half-integers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    // Row-major 2x3
+    Affine([f32; 6]),
+    // Row-major 3x3 homography
+    Perspective([f32; 9]),
+}
+
+pub struct Warp {
+    // Set through `Warp::new`, which checks that the transform can be inverted
+    transform: Transform,
+    inverse: [f32; 9],
+    pub interpolation: Interpolation,
+    pub border: BorderMode,
+    // Output size, the source size when not set
+    pub size: Option<(u32, u32)>,
+}
+
+// Rectangle in source pixels, clipped to the image
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct Rotate {
+    // Clockwise, in degrees
+    pub angle: f32,
+    pub interpolation: Interpolation,
+    // 0xRRGGBB for the area not covered by the source
+    pub background: u32,
+    // Grows the output to hold the whole rotated image instead of keeping the source size
+    pub expand: bool,
+}
+
+// Shared by every geometric kernel, each filter selects its entry point by name
+const GEOMETRY_KERNELS: &str = r#"
+    float filterWeight(int interpolation, float t) {
+        t = fabs(t);
+        switch (interpolation) {
+            case 1:
+                return max(1.0f - t, 0.0f);
+            case 2:
+                // Keys cubic with a = -0.5
+                if (t < 1.0f)
+                    return (1.5f * t - 2.5f) * t * t + 1.0f;
+                if (t < 2.0f)
+                    return ((-0.5f * t + 2.5f) * t - 4.0f) * t + 2.0f;
+                return 0.0f;
+            default: {
+                // Three lobe Lanczos
+                if (t < 1e-5f)
+                    return 1.0f;
+                if (t >= 3.0f)
+                    return 0.0f;
+                float px = M_PI_F * t;
+                return 3.0f * sin(px) * sin(px / 3.0f) / (px * px);
+            }
+        }
+    }
+
+    // Border 0 fills with a constant, 1 replicates the edge, 2 mirrors without repeating
+    // the edge and 3 wraps around. Returns -1 where the constant fill applies
+    int borderIndex(int i, int len, int border) {
+        if (i >= 0 && i < len)
+            return i;
+        if (len == 0)
+            return -1;
+
+        switch (border) {
+            case 0:
+                return -1;
+            case 1:
+                return clamp(i, 0, len - 1);
+            case 2: {
+                if (len == 1)
+                    return 0;
+                int period = 2 * (len - 1);
+                i = (i < 0 ? -i : i) % period;
+                return i < len ? i : period - i;
+            }
+            default:
+                return (i % len + len) % len;
+        }
+    }
+
+    // Channel -1 reads the value as is, 0..2 extract red, green or blue from a packed pixel
+    float fetchPixel(
+        __global const float* image,
+        int width, int height,
+        int x, int y,
+        int channel, int border, float fill) {
+
+        int bx = borderIndex(x, width, border);
+        int by = borderIndex(y, height, border);
+        if (bx < 0 || by < 0)
+            return fill;
+
+        float value = image[by * width + bx];
+        if (channel < 0)
+            return value;
+        return (((uint)value >> (16 - 8 * channel)) & 0xFF) / 255.0f;
+    }
+
+    // Scales above one widen the filter so that downsampling averages instead of aliasing
+    float sampleImage(
+        __global const float* image,
+        int width, int height,
+        float sx, float sy,
+        float scaleX, float scaleY,
+        int interpolation,
+        int channel, int border, float fill) {
+
+        if (interpolation == 0)
+            return fetchPixel(image, width, height,
+                (int)floor(sx + 0.5f), (int)floor(sy + 0.5f), channel, border, fill);
+
+        float radius = (float)interpolation;
+        float supportX = radius * scaleX;
+        float supportY = radius * scaleY;
+
+        float sum = 0.0f, weights = 0.0f;
+        for (int ny = (int)ceil(sy - supportY); ny <= (int)floor(sy + supportY); ny++) {
+            float wy = filterWeight(interpolation, (ny - sy) / scaleY);
+            if (wy == 0.0f)
+                continue;
+
+            for (int nx = (int)ceil(sx - supportX); nx <= (int)floor(sx + supportX); nx++) {
+                float weight = wy * filterWeight(interpolation, (nx - sx) / scaleX);
+                sum += weight * fetchPixel(image, width, height, nx, ny, channel, border, fill);
+                weights += weight;
+            }
+        }
+        return weights != 0.0f ? sum / weights : 0.0f;
+    }
+
+    uint toByte(float value) {
+        return (uint)(clamp(value, 0.0f, 1.0f) * 255.0f + 0.5f);
+    }
+
+    // Samples each channel of a packed image, with the fill given as 0xRRGGBB
+    float samplePacked(
+        __global const float* image,
+        int width, int height,
+        float sx, float sy,
+        int interpolation, int border, uint fill) {
+
+        uint pixel = 0;
+        for (int channel = 0; channel < 3; channel++) {
+            int shift = 16 - 8 * channel;
+            float value = sampleImage(image, width, height, sx, sy, 1.0f, 1.0f,
+                interpolation, channel, border, ((fill >> shift) & 0xFF) / 255.0f);
+            pixel |= toByte(value) << shift;
+        }
+        return (float)pixel;
+    }
+
+    __kernel void resize(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        int outWidth = (int)options[0];
+        int outHeight = (int)options[1];
+        if (x >= outWidth || y >= outHeight)
+            return;
+
+        int interpolation = (int)options[2];
+        float scaleX = options[5] / outWidth;
+        float scaleY = options[6] / outHeight;
+        float sx = options[3] + (x + 0.5f) * scaleX - 0.5f;
+        float sy = options[4] + (y + 0.5f) * scaleY - 0.5f;
+
+        outputImage[y * outWidth + x] = sampleImage(
+            inputImage, width, height, sx, sy,
+            max(scaleX, 1.0f), max(scaleY, 1.0f), interpolation, -1, 1, 0.0f);
+    }
+
+    __kernel void reorient(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        int outWidth = (int)options[1];
+        int outHeight = (int)options[2];
+        if (x >= outWidth || y >= outHeight)
+            return;
+
+        int sx, sy;
+        switch ((int)options[0]) {
+            case 0: sx = y; sy = outWidth - 1 - x; break;
+            case 1: sx = width - 1 - x; sy = height - 1 - y; break;
+            case 2: sx = outHeight - 1 - y; sy = x; break;
+            case 3: sx = width - 1 - x; sy = y; break;
+            case 4: sx = x; sy = height - 1 - y; break;
+            default: sx = y; sy = x; break;
+        }
+        outputImage[y * outWidth + x] = inputImage[sy * width + sx];
+    }
+
+    __kernel void crop(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        int outWidth = (int)options[2];
+        int outHeight = (int)options[3];
+        if (x >= outWidth || y >= outHeight)
+            return;
+
+        int sx = (int)options[0] + x;
+        int sy = (int)options[1] + y;
+        outputImage[y * outWidth + x] = inputImage[sy * width + sx];
+    }
+
+    __kernel void rotate(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        int outWidth = (int)options[0];
+        int outHeight = (int)options[1];
+        if (x >= outWidth || y >= outHeight)
+            return;
+
+        // Inverse rotation about the centres of both images
+        int interpolation = (int)options[2];
+        float c = options[3];
+        float s = options[4];
+        float dx = x + 0.5f - outWidth * 0.5f;
+        float dy = y + 0.5f - outHeight * 0.5f;
+        float sx = c * dx + s * dy + width * 0.5f - 0.5f;
+        float sy = -s * dx + c * dy + height * 0.5f - 0.5f;
+
+        outputImage[y * outWidth + x] = samplePacked(
+            inputImage, width, height, sx, sy, interpolation, 0, (uint)options[5]);
+    }
+
+    __kernel void warp(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        int outWidth = (int)options[0];
+        int outHeight = (int)options[1];
+        if (x >= outWidth || y >= outHeight)
+            return;
+
+        int interpolation = (int)options[2];
+        int border = (int)options[3];
+        uint fill = (uint)options[4];
+        __global const float* m = options + 5;
+
+        // Destination to source through the inverse matrix
+        float px = x + 0.5f;
+        float py = y + 0.5f;
+        float w = m[6] * px + m[7] * py + m[8];
+        int idx = y * outWidth + x;
+        if (w <= 1e-8f) {
+            // Behind the horizon of a perspective transform
+            outputImage[idx] = (float)fill;
+            return;
+        }
+
+        float sx = (m[0] * px + m[1] * py + m[2]) / w - 0.5f;
+        float sy = (m[3] * px + m[4] * py + m[5]) / w - 0.5f;
+        outputImage[idx] = samplePacked(
+            inputImage, width, height, sx, sy, interpolation, border, fill);
+    }
+"#;
+
+impl Interpolation {
+    fn radius(&self) -> f32 {
+        *self as i32 as f32
+    }
+
+    fn weight(&self, t: f32) -> f32 {
+        let t = t.abs();
+        match self {
+            Interpolation::Nearest => (t < 0.5) as i32 as f32,
+            Interpolation::Bilinear => (1.0 - t).max(0.0),
+            Interpolation::Bicubic if t < 1.0 => (1.5 * t - 2.5) * t * t + 1.0,
+            Interpolation::Bicubic if t < 2.0 => ((-0.5 * t + 2.5) * t - 4.0) * t + 2.0,
+            Interpolation::Bicubic => 0.0,
+            Interpolation::Lanczos if t < 1e-5 => 1.0,
+            Interpolation::Lanczos if t >= 3.0 => 0.0,
+            Interpolation::Lanczos => {
+                let px = PI * t;
+                3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+            }
+        }
+    }
+
+    // Source indices and normalised weights contributing to position `s` along one axis,
+    // left unclamped so the caller decides how to treat positions outside the image
+    fn taps(&self, s: f32, scale: f32) -> Vec<(isize, f32)> {
+        if *self == Interpolation::Nearest {
+            return vec![((s + 0.5).floor() as isize, 1.0)];
+        }
+
+        let support = self.radius() * scale;
+        let taps: Vec<(isize, f32)> = ((s - support).ceil() as isize
+            ..=(s + support).floor() as isize)
+            .map(|i| (i, self.weight((i as f32 - s) / scale)))
+            .filter(|(_, weight)| *weight != 0.0)
+            .collect();
+
+        let total: f32 = taps.iter().map(|(_, weight)| weight).sum();
+        taps.into_iter()
+            .map(|(i, weight)| (i, weight / total))
+            .collect()
+    }
+
+    // Point sample at unit scale, the CPU counterpart of `sampleImage`
+    pub fn sample(&self, sx: f32, sy: f32, fetch: impl Fn(isize, isize) -> f32) -> f32 {
+        let columns = self.taps(sx, 1.0);
+        self.taps(sy, 1.0)
+            .iter()
+            .map(|&(y, wy)| wy * columns.iter().map(|&(x, wx)| wx * fetch(x, y)).sum::<f32>())
+            .sum()
+    }
+
+    // Samples the unpacked channels of a packed image and packs the result again
+    fn sample_packed(
+        &self,
+        channels: &[Vec<f32>],
+        dimensions: (u32, u32),
+        sx: f32,
+        sy: f32,
+        border: BorderMode,
+    ) -> f32 {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let fill = border.fill();
+
+        channels
+            .iter()
+            .enumerate()
+            .fold(0, |pixel, (channel, values)| {
+                let shift = 16 - 8 * channel;
+                let fill = ((fill >> shift) & 0xFF) as f32 / 255.0;
+                let value = self.sample(sx, sy, |x, y| {
+                    match (border.index(x, width), border.index(y, height)) {
+                        (Some(x), Some(y)) => values[y * width + x],
+                        _ => fill,
+                    }
+                });
+                pixel | ((value.clamp(0.0, 1.0) * 255.0 + 0.5) as u32) << shift
+            }) as f32
+    }
+}
+
+impl BorderMode {
+    fn code(&self) -> f32 {
+        match self {
+            BorderMode::Constant(_) => 0.0,
+            BorderMode::Replicate => 1.0,
+            BorderMode::Reflect => 2.0,
+            BorderMode::Wrap => 3.0,
+        }
+    }
+
+    fn fill(&self) -> u32 {
+        match self {
+            BorderMode::Constant(color) => color & 0xFFFFFF,
+            _ => 0x000000,
+        }
+    }
+
+    // Index read for position `i` along an axis of `len` pixels, None where the fill applies
+    fn index(&self, i: isize, len: usize) -> Option<usize> {
+        let len = len as isize;
+        if (0..len).contains(&i) {
+            return Some(i as usize);
+        }
+        // An empty axis has nothing to repeat, so only the fill is left
+        if len == 0 {
+            return None;
+        }
+
+        let index = match self {
+            BorderMode::Constant(_) => return None,
+            BorderMode::Replicate => i.clamp(0, len - 1),
+            BorderMode::Reflect if len == 1 => 0,
+            BorderMode::Reflect => {
+                let period = 2 * (len - 1);
+                let i = i.abs() % period;
+                if i < len {
+                    i
+                } else {
+                    period - i
+                }
+            }
+            BorderMode::Wrap => i.rem_euclid(len),
+        };
+        Some(index as usize)
+    }
+}
+
+impl Resize {
+    pub fn new(mode: ResizeMode, interpolation: Interpolation) -> Self {
+        Self {
+            mode,
+            interpolation,
+        }
+    }
+
+    // Part of the source mapped onto the output as (x, y, width, height)
+    fn source_region(&self, dimensions: (u32, u32)) -> (f32, f32, f32, f32) {
+        let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
+        match self.mode {
+            ResizeMode::Fill(target_width, target_height) => {
+                let scale = (target_width as f32 / width).max(target_height as f32 / height);
+                let (region_width, region_height) =
+                    (target_width as f32 / scale, target_height as f32 / scale);
+                (
+                    (width - region_width) / 2.0,
+                    (height - region_height) / 2.0,
+                    region_width,
+                    region_height,
+                )
+            }
+            _ => (0.0, 0.0, width, height),
+        }
+    }
+}
+
+impl ImageFilter for Resize {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((GEOMETRY_KERNELS, "resize"))
+    }
+
+    fn compute_options(&self, _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = self.output_dimensions(dimensions);
+        let (x, y, region_width, region_height) = self.source_region(dimensions);
+        vec![
+            width as f32,
+            height as f32,
+            self.interpolation as i32 as f32,
+            x,
+            y,
+            region_width,
+            region_height,
+        ]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Rgb
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        // There is nothing to sample, as with a crop of an empty image
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            return (0, 0);
+        }
+
+        match self.mode {
+            ResizeMode::Exact(width, height) | ResizeMode::Fill(width, height) => {
+                (width.max(1), height.max(1))
+            }
+            ResizeMode::Fit(width, height) => {
+                let scale =
+                    (width as f32 / dimensions.0 as f32).min(height as f32 / dimensions.1 as f32);
+                (
+                    ((dimensions.0 as f32 * scale).round() as u32).max(1),
+                    ((dimensions.1 as f32 * scale).round() as u32).max(1),
+                )
+            }
+        }
+    }
+
+    // Separable on the CPU, which gives the same weights as the 2D kernel
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let (out_width, out_height) = (options[0] as usize, options[1] as usize);
+        let (x, y, region_width, region_height) = (options[3], options[4], options[5], options[6]);
+        let (scale_x, scale_y) = (
+            region_width / out_width as f32,
+            region_height / out_height as f32,
+        );
+
+        let clamped = |taps: Vec<(isize, f32)>, len: usize| -> Vec<(usize, f32)> {
+            taps.into_iter()
+                .map(|(i, weight)| (i.clamp(0, len as isize - 1) as usize, weight))
+                .collect()
+        };
+        let columns: Vec<Vec<(usize, f32)>> = (0..out_width)
+            .map(|i| {
+                let sx = x + (i as f32 + 0.5) * scale_x - 0.5;
+                clamped(self.interpolation.taps(sx, scale_x.max(1.0)), width)
+            })
+            .collect();
+        let rows: Vec<Vec<(usize, f32)>> = (0..out_height)
+            .map(|i| {
+                let sy = y + (i as f32 + 0.5) * scale_y - 0.5;
+                clamped(self.interpolation.taps(sy, scale_y.max(1.0)), height)
+            })
+            .collect();
+
+        let mut horizontal = vec![0.0; out_width * height];
+        for row in 0..height {
+            for (column, taps) in columns.iter().enumerate() {
+                horizontal[row * out_width + column] = taps
+                    .iter()
+                    .map(|&(i, weight)| weight * pixels[row * width + i])
+                    .sum();
+            }
+        }
+
+        let mut output = vec![0.0; out_width * out_height];
+        for (row, taps) in rows.iter().enumerate() {
+            for column in 0..out_width {
+                output[row * out_width + column] = taps
+                    .iter()
+                    .map(|&(i, weight)| weight * horizontal[i * out_width + column])
+                    .sum();
+            }
+        }
+        output
+    }
+}
+
+impl Orientation {
+    // Source pixel shown at output position (x, y)
+    fn source(&self, x: usize, y: usize, dimensions: (usize, usize)) -> (usize, usize) {
+        let (width, height) = dimensions;
+        match self {
+            Orientation::Rotate90 => (y, height - 1 - x),
+            Orientation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Orientation::Rotate270 => (width - 1 - y, x),
+            Orientation::FlipHorizontal => (width - 1 - x, y),
+            Orientation::FlipVertical => (x, height - 1 - y),
+            Orientation::Transpose => (y, x),
+        }
+    }
+
+    fn swaps_axes(&self) -> bool {
+        matches!(
+            self,
+            Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Transpose
+        )
+    }
+}
+
+impl Reorient {
+    pub fn new(orientation: Orientation) -> Self {
+        Self { orientation }
+    }
+}
+
+impl ImageFilter for Reorient {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((GEOMETRY_KERNELS, "reorient"))
+    }
+
+    fn compute_options(&self, _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = self.output_dimensions(dimensions);
+        vec![self.orientation as i32 as f32, width as f32, height as f32]
+    }
+
+    // Packed so that every channel moves in a single pass
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        if self.orientation.swaps_axes() {
+            (dimensions.1, dimensions.0)
+        } else {
+            dimensions
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let source = (dimensions.0 as usize, dimensions.1 as usize);
+        let (out_width, out_height) = (options[1] as usize, options[2] as usize);
+
+        (0..out_width * out_height)
+            .map(|i| {
+                let (x, y) = self
+                    .orientation
+                    .source(i % out_width, i / out_width, source);
+                pixels[y * source.0 + x]
+            })
+            .collect()
+    }
+}
+
+impl Crop {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // Keeps at least one pixel of a non-empty image so the stages after it always have
+    // an image to work on
+    fn region(&self, dimensions: (u32, u32)) -> (u32, u32, u32, u32) {
+        let x = self.x.min(dimensions.0.saturating_sub(1));
+        let y = self.y.min(dimensions.1.saturating_sub(1));
+        (
+            x,
+            y,
+            self.width.max(1).min(dimensions.0 - x),
+            self.height.max(1).min(dimensions.1 - y),
+        )
+    }
+}
+
+impl ImageFilter for Crop {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((GEOMETRY_KERNELS, "crop"))
+    }
+
+    fn compute_options(&self, _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (x, y, width, height) = self.region(dimensions);
+        vec![x as f32, y as f32, width as f32, height as f32]
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        let (_, _, width, height) = self.region(dimensions);
+        (width, height)
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (x, y) = (options[0] as usize, options[1] as usize);
+        let (width, height) = (options[2] as usize, options[3] as usize);
+        let stride = dimensions.0 as usize;
+
+        (y..y + height)
+            .flat_map(|row| {
+                pixels[row * stride + x..row * stride + x + width]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+}
+
+impl Rotate {
+    pub fn new(angle: f32, interpolation: Interpolation) -> Self {
+        Self {
+            angle,
+            interpolation,
+            background: 0x000000,
+            expand: true,
+        }
+    }
+}
+
+impl ImageFilter for Rotate {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((GEOMETRY_KERNELS, "rotate"))
+    }
+
+    fn compute_options(&self, _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = self.output_dimensions(dimensions);
+        let radians = self.angle.to_radians();
+        vec![
+            width as f32,
+            height as f32,
+            self.interpolation as i32 as f32,
+            radians.cos(),
+            radians.sin(),
+            (self.background & 0xFFFFFF) as f32,
+        ]
+    }
+
+    // Packed so that the background colour can be filled per channel
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        if !self.expand || dimensions.0 == 0 || dimensions.1 == 0 {
+            return dimensions;
+        }
+
+        let radians = self.angle.to_radians();
+        let (c, s) = (radians.cos().abs(), radians.sin().abs());
+        let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
+        // Rounding off float noise first keeps right angles from gaining a pixel
+        let size = |value: f32| ((value * 1e3).round() / 1e3).ceil().max(1.0) as u32;
+        (size(width * c + height * s), size(width * s + height * c))
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
+        let (out_width, out_height) = (options[0] as usize, options[1] as usize);
+        let (c, s) = (options[3], options[4]);
+        let border = BorderMode::Constant(options[5] as u32);
+        let channels = unpack_channels(pixels);
+
+        (0..out_width * out_height)
+            .map(|i| {
+                let dx = (i % out_width) as f32 + 0.5 - out_width as f32 * 0.5;
+                let dy = (i / out_width) as f32 + 0.5 - out_height as f32 * 0.5;
+                let sx = c * dx + s * dy + width * 0.5 - 0.5;
+                let sy = -s * dx + c * dy + height * 0.5 - 0.5;
+                self.interpolation
+                    .sample_packed(&channels, dimensions, sx, sy, border)
+            })
+            .collect()
+    }
+}
+
+impl Transform {
+    pub fn affine_from_points(
+        source: [(f32, f32); 3],
+        destination: [(f32, f32); 3],
+    ) -> Option<Self> {
+        let mut rows = Vec::new();
+        for ((x, y), (u, v)) in source.iter().zip(destination) {
+            let (x, y) = (*x as f64, *y as f64);
+            rows.push(vec![x, y, 1.0, 0.0, 0.0, 0.0, u as f64]);
+            rows.push(vec![0.0, 0.0, 0.0, x, y, 1.0, v as f64]);
+        }
+
+        let solution = solve_linear(rows)?;
+        let mut matrix = [0.0; 6];
+        for (value, solved) in matrix.iter_mut().zip(solution) {
+            *value = solved as f32;
+        }
+        Some(Transform::Affine(matrix))
+    }
+
+    // Fails when three of the points are collinear
+    pub fn perspective_from_points(
+        source: [(f32, f32); 4],
+        destination: [(f32, f32); 4],
+    ) -> Option<Self> {
+        let mut rows = Vec::new();
+        for ((x, y), (u, v)) in source.iter().zip(destination) {
+            let (x, y, u, v) = (*x as f64, *y as f64, u as f64, v as f64);
+            rows.push(vec![x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u]);
+            rows.push(vec![0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v]);
+        }
+
+        let solution = solve_linear(rows)?;
+        let mut matrix = [1.0; 9];
+        for (value, solved) in matrix.iter_mut().zip(solution) {
+            *value = solved as f32;
+        }
+        Some(Transform::Perspective(matrix))
+    }
+
+    pub fn matrix(&self) -> [f32; 9] {
+        match *self {
+            Transform::Affine([a, b, c, d, e, f]) => [a, b, c, d, e, f, 0.0, 0.0, 1.0],
+            Transform::Perspective(matrix) => matrix,
+        }
+    }
+
+    pub fn inverse(&self) -> Option<[f32; 9]> {
+        let m = self.matrix().map(|value| value as f64);
+        let cofactors = [
+            m[4] * m[8] - m[5] * m[7],
+            m[2] * m[7] - m[1] * m[8],
+            m[1] * m[5] - m[2] * m[4],
+            m[5] * m[6] - m[3] * m[8],
+            m[0] * m[8] - m[2] * m[6],
+            m[2] * m[3] - m[0] * m[5],
+            m[3] * m[7] - m[4] * m[6],
+            m[1] * m[6] - m[0] * m[7],
+            m[0] * m[4] - m[1] * m[3],
+        ];
+        let determinant = m[0] * cofactors[0] + m[1] * cofactors[3] + m[2] * cofactors[6];
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        Some(cofactors.map(|value| (value / determinant) as f32))
+    }
+}
+
+impl Warp {
+    pub fn new(transform: Transform, interpolation: Interpolation) -> Result<Self, FilterError> {
+        let inverse = transform.inverse().ok_or(FilterError::InvalidParameter {
+            name: "Transform",
+            reason: "is not invertible",
+        })?;
+
+        Ok(Self {
+            transform,
+            inverse,
+            interpolation,
+            border: BorderMode::Constant(0x000000),
+            size: None,
+        })
+    }
+
+    // Maps the quadrilateral with corners top-left, top-right, bottom-right, bottom-left
+    // onto an upright rectangle of the given size
+    pub fn rectify(corners: [(f32, f32); 4], size: (u32, u32)) -> Result<Self, FilterError> {
+        let (width, height) = (size.0 as f32, size.1 as f32);
+        let rectangle = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+        let transform = Transform::perspective_from_points(corners, rectangle).ok_or(
+            FilterError::InvalidParameter {
+                name: "Corners",
+                reason: "must not have three collinear points",
+            },
+        )?;
+
+        Ok(Self {
+            size: Some(size),
+            ..Self::new(transform, Interpolation::Bilinear)?
+        })
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    // A homography is only defined up to scale, so the inverse may put the whole source
+    // behind the horizon. Flipping its sign where the source centre lands at a negative w
+    // keeps the visible side at positive w, which the kernels rely on
+    fn oriented_inverse(&self, dimensions: (u32, u32)) -> [f32; 9] {
+        let m = self.transform.matrix();
+        let (cx, cy) = (dimensions.0 as f32 * 0.5, dimensions.1 as f32 * 0.5);
+        if m[6] * cx + m[7] * cy + m[8] < 0.0 {
+            self.inverse.map(|value| -value)
+        } else {
+            self.inverse
+        }
+    }
+}
+
+impl ImageFilter for Warp {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((GEOMETRY_KERNELS, "warp"))
+    }
+
+    fn compute_options(&self, _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = self.output_dimensions(dimensions);
+        let mut options = vec![
+            width as f32,
+            height as f32,
+            self.interpolation as i32 as f32,
+            self.border.code(),
+            self.border.fill() as f32,
+        ];
+        options.extend(self.oriented_inverse(dimensions));
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    // An empty image stays empty unless an explicit size asks for the border fill
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        match self.size {
+            Some((width, height)) => (width.max(1), height.max(1)),
+            None => dimensions,
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (out_width, out_height) = (options[0] as usize, options[1] as usize);
+        let m = &options[5..14];
+        let channels = unpack_channels(pixels);
+
+        (0..out_width * out_height)
+            .map(|i| {
+                let px = (i % out_width) as f32 + 0.5;
+                let py = (i / out_width) as f32 + 0.5;
+                let w = m[6] * px + m[7] * py + m[8];
+                if w <= 1e-8 {
+                    return self.border.fill() as f32;
+                }
+
+                let sx = (m[0] * px + m[1] * py + m[2]) / w - 0.5;
+                let sy = (m[3] * px + m[4] * py + m[5]) / w - 0.5;
+                self.interpolation
+                    .sample_packed(&channels, dimensions, sx, sy, self.border)
+            })
+            .collect()
+    }
+}
+
+fn unpack_channels(pixels: &[f32]) -> Vec<Vec<f32>> {
+    (0..3)
+        .map(|channel| {
+            let shift = 16 - 8 * channel;
+            pixels
+                .iter()
+                .map(|&pixel| ((pixel as u32 >> shift) & 0xFF) as f32 / 255.0)
+                .collect()
+        })
+        .collect()
+}
+
+// Gaussian elimination with partial pivoting on an augmented matrix
+fn solve_linear(mut rows: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let n = rows.len();
+    for column in 0..n {
+        let pivot =
+            (column..n).max_by(|&a, &b| rows[a][column].abs().total_cmp(&rows[b][column].abs()))?;
+        if rows[pivot][column].abs() < 1e-10 {
+            return None;
+        }
+        rows.swap(column, pivot);
+
+        let pivot_row = rows[column].clone();
+        for (index, row) in rows.iter_mut().enumerate() {
+            if index == column {
+                continue;
+            }
+            let factor = row[column] / pivot_row[column];
+            for (value, pivot_value) in row.iter_mut().zip(&pivot_row).skip(column) {
+                *value -= factor * pivot_value;
+            }
+        }
+    }
+
+    Some((0..n).map(|i| rows[i][n] / rows[i][i]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processing::image_processor::{Backend, ImageProcessor};
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    #[test]
+    fn fit_and_fill_keep_the_aspect_ratio() {
+        let fit = Resize::new(ResizeMode::Fit(100, 100), Interpolation::Bilinear);
+        let fill = Resize::new(ResizeMode::Fill(100, 100), Interpolation::Bilinear);
+        assert_eq!(fit.output_dimensions((400, 200)), (100, 50));
+        assert_eq!(fill.output_dimensions((400, 200)), (100, 100));
+        assert_eq!(fill.source_region((400, 200)), (100.0, 0.0, 200.0, 200.0));
+    }
+
+    #[test]
+    fn nearest_upscaling_repeats_pixels() {
+        let filter = Resize::new(ResizeMode::Exact(4, 2), Interpolation::Nearest);
+        let output = run(&filter, &[0.25, 0.75], (2, 1));
+        assert_eq!(output, vec![0.25, 0.25, 0.75, 0.75, 0.25, 0.25, 0.75, 0.75]);
+    }
+
+    #[test]
+    fn downscaling_averages_instead_of_aliasing() {
+        // Single pixel stripes would alias to one of the two values under point sampling
+        let pixels: Vec<f32> = (0..64).map(|i| (i % 2) as f32).collect();
+        for interpolation in [
+            Interpolation::Bilinear,
+            Interpolation::Bicubic,
+            Interpolation::Lanczos,
+        ] {
+            let filter = Resize::new(ResizeMode::Exact(2, 2), interpolation);
+            for value in run(&filter, &pixels, (8, 8)) {
+                assert!((value - 0.5).abs() < 0.1, "{interpolation:?} {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn resizing_keeps_a_flat_image_flat() {
+        for interpolation in [Interpolation::Bicubic, Interpolation::Lanczos] {
+            let filter = Resize::new(ResizeMode::Exact(7, 5), interpolation);
+            for value in run(&filter, &[0.4; 9], (3, 3)) {
+                assert!((value - 0.4).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn resizing_an_empty_image_gives_an_empty_image() {
+        let filters: Vec<Box<dyn ImageFilter>> = vec![
+            Box::new(Resize::new(
+                ResizeMode::Exact(4, 4),
+                Interpolation::Bilinear,
+            )),
+            Box::new(Resize::new(ResizeMode::Fit(4, 4), Interpolation::Bilinear)),
+            Box::new(Resize::new(ResizeMode::Fill(4, 4), Interpolation::Bilinear)),
+        ];
+        for filter in &filters {
+            assert_eq!(filter.output_dimensions((0, 0)), (0, 0));
+            assert_eq!(filter.output_dimensions((0, 3)), (0, 0));
+        }
+
+        let processor = ImageProcessor::new(&[], (0, 0), &filters).with_backend(Backend::Cpu);
+        assert!(processor.process_image().iter().all(Vec::is_empty));
+        assert_eq!(processor.process_chain(), (Vec::new(), (0, 0)));
+    }
+
+    // Distinct grey levels packed as 0xRRGGBB
+    fn packed(count: u32) -> Vec<f32> {
+        (0..count).map(|i| (i * 0x101010) as f32).collect()
+    }
+
+    #[test]
+    fn rotate90_moves_the_left_column_to_the_top_row() {
+        let filter = Reorient::new(Orientation::Rotate90);
+        let output = run(&filter, &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0], (3, 2));
+        assert_eq!(filter.output_dimensions((3, 2)), (2, 3));
+        assert_eq!(output, vec![3.0, 0.0, 4.0, 1.0, 5.0, 2.0]);
+    }
+
+    #[test]
+    fn opposite_orientations_cancel() {
+        let pixels: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        for (forward, backward) in [
+            (Orientation::Rotate90, Orientation::Rotate270),
+            (Orientation::Rotate180, Orientation::Rotate180),
+            (Orientation::Transpose, Orientation::Transpose),
+        ] {
+            let turned = run(&Reorient::new(forward), &pixels, (4, 3));
+            let dimensions = Reorient::new(forward).output_dimensions((4, 3));
+            assert_eq!(run(&Reorient::new(backward), &turned, dimensions), pixels);
+        }
+    }
+
+    #[test]
+    fn crop_is_clipped_to_the_image() {
+        let pixels: Vec<f32> = (0..16).map(|i| i as f32).collect();
+        let filter = Crop::new(2, 1, 10, 2);
+        assert_eq!(filter.output_dimensions((4, 4)), (2, 2));
+        assert_eq!(run(&filter, &pixels, (4, 4)), vec![6.0, 7.0, 10.0, 11.0]);
+
+        let outside = Crop::new(9, 9, 0, 0);
+        assert_eq!(run(&outside, &pixels, (4, 4)), vec![15.0]);
+    }
+
+    #[test]
+    fn cropping_an_empty_image_gives_an_empty_image() {
+        let filter = Crop::new(1, 1, 2, 2);
+        assert_eq!(filter.output_dimensions((0, 3)), (0, 2));
+        assert!(run(&filter, &[], (0, 3)).is_empty());
+    }
+
+    #[test]
+    fn right_angle_rotation_matches_the_lossless_one() {
+        let pixels = packed(6);
+        let rotated = run(&Rotate::new(90.0, Interpolation::Nearest), &pixels, (3, 2));
+        let reoriented = run(&Reorient::new(Orientation::Rotate90), &pixels, (3, 2));
+        assert_eq!(rotated, reoriented);
+    }
+
+    #[test]
+    fn expanded_rotation_holds_the_whole_image() {
+        let filter = Rotate::new(45.0, Interpolation::Bilinear);
+        assert_eq!(filter.output_dimensions((10, 10)), (15, 15));
+        let fixed = Rotate {
+            expand: false,
+            ..Rotate::new(45.0, Interpolation::Bilinear)
+        };
+        assert_eq!(fixed.output_dimensions((10, 10)), (10, 10));
+    }
+
+    #[test]
+    fn warp_translates_by_whole_pixels() {
+        let pixels = packed(12);
+        let filter = Warp::new(
+            Transform::Affine([1.0, 0.0, 1.0, 0.0, 1.0, 0.0]),
+            Interpolation::Bilinear,
+        )
+        .unwrap();
+        let output = run(&filter, &pixels, (4, 3));
+        for y in 0..3 {
+            assert_eq!(output[y * 4], 0.0);
+            assert_eq!(&output[y * 4 + 1..y * 4 + 4], &pixels[y * 4..y * 4 + 3]);
+        }
+    }
+
+    #[test]
+    fn singular_transforms_are_rejected() {
+        let collapse = Transform::Affine([1.0, 1.0, 0.0, 1.0, 1.0, 0.0]);
+        assert!(Warp::new(collapse, Interpolation::Bilinear).is_err());
+
+        let collinear = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (0.0, 3.0)];
+        assert!(Warp::rectify(collinear, (4, 4)).is_err());
+    }
+
+    const BORDERS: [BorderMode; 4] = [
+        BorderMode::Constant(0x336699),
+        BorderMode::Replicate,
+        BorderMode::Reflect,
+        BorderMode::Wrap,
+    ];
+
+    #[test]
+    fn border_modes_have_nothing_to_read_on_an_empty_axis() {
+        for border in BORDERS {
+            for i in [-2, 0, 3] {
+                assert_eq!(border.index(i, 0), None, "{border:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn warping_an_empty_image() {
+        let transform = Transform::Affine([1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+        for border in BORDERS {
+            let mut warp = Warp::new(transform, Interpolation::Bilinear).unwrap();
+            warp.border = border;
+            assert_eq!(warp.output_dimensions((0, 0)), (0, 0));
+            assert!(run(&warp, &[], (0, 0)).is_empty());
+
+            // An explicit size is all border
+            warp.size = Some((2, 1));
+            let fill = border.fill() as f32;
+            assert_eq!(run(&warp, &[], (0, 0)), vec![fill; 2], "{border:?}");
+        }
+
+        let rotate = Rotate::new(30.0, Interpolation::Bilinear);
+        assert_eq!(rotate.output_dimensions((0, 4)), (0, 4));
+        assert!(run(&rotate, &[], (0, 4)).is_empty());
+    }
+
+    #[test]
+    fn homography_scale_does_not_change_the_warp() {
+        let pixels = packed(16);
+        let source = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)];
+        let destination = [(0.5, 0.0), (4.0, 0.5), (3.5, 4.0), (0.0, 3.5)];
+        let Transform::Perspective(matrix) =
+            Transform::perspective_from_points(source, destination).unwrap()
+        else {
+            unreachable!()
+        };
+
+        let warp = |matrix| {
+            let filter = Warp::new(Transform::Perspective(matrix), Interpolation::Nearest);
+            run(&filter.unwrap(), &pixels, (4, 4))
+        };
+        let output = warp(matrix);
+        assert!(output.iter().any(|&value| value != 0.0));
+        assert_eq!(warp(matrix.map(|value| -2.0 * value)), output);
+    }
+
+    #[test]
+    fn rectify_maps_the_corners_to_the_rectangle() {
+        let corners = [(1.0, 1.0), (7.0, 2.0), (6.0, 7.0), (2.0, 6.0)];
+        let filter = Warp::rectify(corners, (10, 10)).unwrap();
+        let m = filter.transform().matrix();
+        let project = |(x, y): (f32, f32)| {
+            let w = m[6] * x + m[7] * y + m[8];
+            (
+                (m[0] * x + m[1] * y + m[2]) / w,
+                (m[3] * x + m[4] * y + m[5]) / w,
+            )
+        };
+        let rectangle = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        for (corner, expected) in corners.into_iter().zip(rectangle) {
+            let (x, y) = project(corner);
+            assert!((x - expected.0).abs() < 1e-3 && (y - expected.1).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use super::filters::{ColorMode, ImageFilter};
+
+const BINS: usize = 256;
+
+pub struct HistogramEqualization;
+
+pub struct Clahe {
+    pub tiles: (u32, u32),
+    // Maximum bin height as a multiple of the average bin height
+    pub clip_limit: f32,
+}
+
+impl ImageFilter for HistogramEqualization {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void histogramEqualization(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                int bin = clamp((int)(inputImage[idx] * 255.0f + 0.5f), 0, 255);
+                outputImage[idx] = options[bin];
+            }
+            "#,
+            "histogramEqualization",
+        ))
+    }
+
+    fn compute_options(&self, pixels: &[f32], _: (u32, u32)) -> Vec<f32> {
+        equalization_lut(&histogram(pixels.iter().copied()))
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Luminance
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        pixels.iter().map(|&pixel| options[bin(pixel)]).collect()
+    }
+}
+
+impl Clahe {
+    pub fn new(tiles: (u32, u32), clip_limit: f32) -> Self {
+        Self { tiles, clip_limit }
+    }
+
+    fn tile_bounds(size: u32, tiles: u32, tile: u32) -> (usize, usize) {
+        let start = (tile as u64 * size as u64 / tiles as u64) as usize;
+        let end = ((tile as u64 + 1) * size as u64 / tiles as u64) as usize;
+        (start, end)
+    }
+}
+
+impl ImageFilter for Clahe {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void clahe(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int tilesX = (int)options[0];
+                int tilesY = (int)options[1];
+                __global const float* luts = options + 2;
+
+                // Position relative to the tile centres
+                float tx = (x + 0.5f) * tilesX / width - 0.5f;
+                float ty = (y + 0.5f) * tilesY / height - 0.5f;
+                int x0 = clamp((int)floor(tx), 0, tilesX - 1);
+                int y0 = clamp((int)floor(ty), 0, tilesY - 1);
+                int x1 = min(x0 + 1, tilesX - 1);
+                int y1 = min(y0 + 1, tilesY - 1);
+                float fx = clamp(tx - x0, 0.0f, 1.0f);
+                float fy = clamp(ty - y0, 0.0f, 1.0f);
+
+                int idx = y * width + x;
+                int bin = clamp((int)(inputImage[idx] * 255.0f + 0.5f), 0, 255);
+                float top = mix(luts[(y0 * tilesX + x0) * 256 + bin], luts[(y0 * tilesX + x1) * 256 + bin], fx);
+                float bottom = mix(luts[(y1 * tilesX + x0) * 256 + bin], luts[(y1 * tilesX + x1) * 256 + bin], fx);
+                outputImage[idx] = mix(top, bottom, fy);
+            }
+            "#,
+            "clahe",
+        ))
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let width = dimensions.0 as usize;
+        let tiles_x = self.tiles.0.clamp(1, dimensions.0.max(1));
+        let tiles_y = self.tiles.1.clamp(1, dimensions.1.max(1));
+
+        let mut options = vec![tiles_x as f32, tiles_y as f32];
+        for tile_y in 0..tiles_y {
+            let (top, bottom) = Self::tile_bounds(dimensions.1, tiles_y, tile_y);
+            for tile_x in 0..tiles_x {
+                let (left, right) = Self::tile_bounds(dimensions.0, tiles_x, tile_x);
+
+                let tile = (top..bottom)
+                    .flat_map(|y| pixels[y * width + left..y * width + right].iter().copied());
+                let mut histogram = histogram(tile);
+
+                // Clip the histogram and spread the excess evenly over all bins
+                let total: f32 = histogram.iter().sum();
+                let limit = (self.clip_limit * total / BINS as f32).max(1.0);
+                let excess: f32 = histogram
+                    .iter()
+                    .map(|&count| (count - limit).max(0.0))
+                    .sum();
+                histogram
+                    .iter_mut()
+                    .for_each(|count| *count = count.min(limit) + excess / BINS as f32);
+
+                options.extend(equalization_lut(&histogram));
+            }
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Luminance
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
+        let (tiles_x, tiles_y) = (options[0] as usize, options[1] as usize);
+        let luts = &options[2..];
+        let lut = |tile_x: usize, tile_y: usize, bin: usize| {
+            luts[(tile_y * tiles_x + tile_x) * BINS + bin]
+        };
+
+        pixels
+            .iter()
+            .enumerate()
+            .map(|(index, &pixel)| {
+                let x = (index % dimensions.0 as usize) as f32;
+                let y = (index / dimensions.0 as usize) as f32;
+
+                let tx = (x + 0.5) * tiles_x as f32 / width - 0.5;
+                let ty = (y + 0.5) * tiles_y as f32 / height - 0.5;
+                let x0 = (tx.floor().max(0.0) as usize).min(tiles_x - 1);
+                let y0 = (ty.floor().max(0.0) as usize).min(tiles_y - 1);
+                let x1 = (x0 + 1).min(tiles_x - 1);
+                let y1 = (y0 + 1).min(tiles_y - 1);
+                let fx = (tx - x0 as f32).clamp(0.0, 1.0);
+                let fy = (ty - y0 as f32).clamp(0.0, 1.0);
+
+                let bin = bin(pixel);
+                let top = lut(x0, y0, bin) * (1.0 - fx) + lut(x1, y0, bin) * fx;
+                let bottom = lut(x0, y1, bin) * (1.0 - fx) + lut(x1, y1, bin) * fx;
+                top * (1.0 - fy) + bottom * fy
+            })
+            .collect()
+    }
+}
+
+pub fn bin(pixel: f32) -> usize {
+    ((pixel * 255.0 + 0.5) as isize).clamp(0, BINS as isize - 1) as usize
+}
+
+pub fn histogram(pixels: impl Iterator<Item = f32>) -> Vec<f32> {
+    let mut histogram = vec![0.0; BINS];
+    for pixel in pixels {
+        histogram[bin(pixel)] += 1.0;
+    }
+    histogram
+}
+
+// Maps each bin through the normalised cumulative histogram, stretched to the full range
+fn equalization_lut(histogram: &[f32]) -> Vec<f32> {
+    let total: f32 = histogram.iter().sum();
+    let first = histogram
+        .iter()
+        .copied()
+        .find(|&count| count > 0.0)
+        .unwrap_or(0.0);
+
+    let mut cumulative = 0.0;
+    histogram
+        .iter()
+        .map(|&count| {
+            cumulative += count;
+            if total > first {
+                ((cumulative - first) / (total - first)).max(0.0)
+            } else {
+                cumulative / total.max(1.0)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    // Low contrast ramp between 0.4 and 0.6
+    fn narrow_ramp() -> Vec<f32> {
+        (0..64).map(|i| 0.4 + 0.2 * i as f32 / 63.0).collect()
+    }
+
+    #[test]
+    fn equalization_stretches_to_the_full_range() {
+        let output = run(&HistogramEqualization, &narrow_ramp(), (8, 8));
+
+        assert_eq!(output[0], 0.0);
+        assert_eq!(output[63], 1.0);
+        assert!(output.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn equalization_keeps_a_flat_image_flat() {
+        let output = run(&HistogramEqualization, &[0.3; 16], (4, 4));
+        assert!(output.iter().all(|&value| value == output[0]));
+    }
+
+    #[test]
+    fn single_unclipped_tile_is_global_equalization() {
+        let pixels: Vec<f32> = (0..64).map(|i| ((i * 29) % 64) as f32 / 63.0).collect();
+        let clahe = run(&Clahe::new((1, 1), 1000.0), &pixels, (8, 8));
+        let global = run(&HistogramEqualization, &pixels, (8, 8));
+
+        for (a, b) in clahe.iter().zip(&global) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn clip_limit_bounds_the_contrast_gain() {
+        let pixels = narrow_ramp();
+        let range = |output: Vec<f32>| output[63] - output[0];
+        let unclipped = range(run(&Clahe::new((2, 2), 1000.0), &pixels, (8, 8)));
+        let clipped = range(run(&Clahe::new((2, 2), 2.0), &pixels, (8, 8)));
+
+        assert!(clipped < unclipped);
+        assert!(clipped > 0.2);
+    }
+
+    #[test]
+    fn tile_bounds_cover_the_image() {
+        let bounds: Vec<(usize, usize)> =
+            (0..3).map(|tile| Clahe::tile_bounds(10, 3, tile)).collect();
+        assert_eq!(bounds, vec![(0, 3), (3, 6), (6, 10)]);
+    }
+}
@@ -0,0 +1,669 @@
+use std::f32::consts::PI;
+
+use super::filters::{CannyFilter, ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+use super::morphology::Thinning;
+
+// Canny marks strong edges with 1 and weak ones with 0.5, only strong edges vote
+const EDGE_THRESHOLD: f32 = 0.5;
+
+// Normal form x * cos(theta) + y * sin(theta) = rho, theta in 0..PI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HoughLine {
+    pub rho: f32,
+    pub theta: f32,
+    pub votes: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineSegment {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HoughCircle {
+    pub center: (f32, f32),
+    pub radius: f32,
+    // Fraction of the circle's pixels that lie on an edge, so at most one
+    pub score: f32,
+}
+
+pub struct HoughLines {
+    pub rho_step: f32,
+    // In radians
+    pub theta_step: f32,
+    pub threshold: u32,
+    pub max_lines: usize,
+}
+
+pub struct ProbabilisticHoughLines {
+    pub rho_step: f32,
+    pub theta_step: f32,
+    pub threshold: u32,
+    pub min_length: f32,
+    // Largest run of missing pixels bridged inside a segment
+    pub max_gap: u32,
+}
+
+pub struct HoughCircles {
+    pub min_radius: u32,
+    pub max_radius: u32,
+    pub threshold: f32,
+    // Smallest distance between the centres of two reported circles
+    pub min_distance: f32,
+    pub max_circles: usize,
+}
+
+pub enum HoughDetector {
+    Lines(HoughLines),
+    Segments(ProbabilisticHoughLines),
+    Circles(HoughCircles),
+}
+
+// Runs Canny and a detector on the image and draws what it finds onto it
+pub struct HoughOverlay {
+    pub detector: HoughDetector,
+    // 0xRRGGBB
+    pub color: u32,
+    pub thickness: f32,
+}
+
+// Accumulator over (theta, rho) shared by both line detectors
+struct LineAccumulator {
+    thetas: Vec<(f32, f32)>,
+    rho_step: f32,
+    max_rho: f32,
+    rho_bins: usize,
+    votes: Vec<u32>,
+}
+
+impl LineAccumulator {
+    fn new(rho_step: f32, theta_step: f32, dimensions: (u32, u32)) -> Self {
+        let theta_count = ((PI / theta_step.max(1e-4)).round() as usize).max(1);
+        let thetas = (0..theta_count)
+            .map(|i| (i as f32 * PI / theta_count as f32).sin_cos())
+            .map(|(sin, cos)| (cos, sin))
+            .collect();
+        let rho_step = rho_step.max(1e-3);
+        // Whole number of bins either side of zero so bin centres land on multiples of the step
+        let half_bins = ((dimensions.0 as f32).hypot(dimensions.1 as f32) / rho_step).ceil();
+        let max_rho = half_bins * rho_step;
+        let rho_bins = 2 * half_bins as usize + 1;
+
+        Self {
+            thetas,
+            rho_step,
+            max_rho,
+            rho_bins,
+            votes: vec![0; theta_count * rho_bins],
+        }
+    }
+
+    fn bin(&self, (x, y): (usize, usize), theta: usize) -> usize {
+        let (cos, sin) = self.thetas[theta];
+        let rho = x as f32 * cos + y as f32 * sin;
+        theta * self.rho_bins + ((rho + self.max_rho) / self.rho_step).round() as usize
+    }
+
+    fn theta(&self, bin: usize) -> f32 {
+        (bin / self.rho_bins) as f32 * PI / self.thetas.len() as f32
+    }
+
+    fn rho(&self, bin: usize) -> f32 {
+        (bin % self.rho_bins) as f32 * self.rho_step - self.max_rho
+    }
+}
+
+impl HoughLines {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            rho_step: 1.0,
+            theta_step: PI / 180.0,
+            threshold,
+            max_lines: 16,
+        }
+    }
+
+    pub fn detect(&self, edges: &[f32], dimensions: (u32, u32)) -> Vec<HoughLine> {
+        let mut accumulator = LineAccumulator::new(self.rho_step, self.theta_step, dimensions);
+        for point in edge_points(edges, dimensions) {
+            for theta in 0..accumulator.thetas.len() {
+                let bin = accumulator.bin(point, theta);
+                accumulator.votes[bin] += 1;
+            }
+        }
+
+        // Peaks that no neighbouring bin beats
+        let (rows, columns) = (accumulator.thetas.len(), accumulator.rho_bins);
+        let votes = &accumulator.votes;
+        let mut peaks: Vec<usize> = (0..votes.len())
+            .filter(|&bin| {
+                let count = votes[bin];
+                if count < self.threshold.max(1) {
+                    return false;
+                }
+                let (theta, rho) = (bin / columns, bin % columns);
+                (theta.saturating_sub(1)..(theta + 2).min(rows)).all(|t| {
+                    (rho.saturating_sub(1)..(rho + 2).min(columns)).all(|r| {
+                        let neighbour = t * columns + r;
+                        votes[neighbour] < count || (votes[neighbour] == count && neighbour >= bin)
+                    })
+                })
+            })
+            .collect();
+        peaks.sort_by(|&a, &b| votes[b].cmp(&votes[a]));
+        peaks.truncate(self.max_lines);
+
+        peaks
+            .into_iter()
+            .map(|bin| HoughLine {
+                rho: accumulator.rho(bin),
+                theta: accumulator.theta(bin),
+                votes: votes[bin],
+            })
+            .collect()
+    }
+}
+
+impl HoughLine {
+    // Part of the line inside the image, None when it misses it
+    pub fn clip(&self, dimensions: (u32, u32)) -> Option<LineSegment> {
+        let (width, height) = (dimensions.0 as f32 - 1.0, dimensions.1 as f32 - 1.0);
+        let (sin, cos) = self.theta.sin_cos();
+        let (x0, y0) = (self.rho * cos, self.rho * sin);
+        let direction = (-sin, cos);
+
+        // Liang-Barsky against the image rectangle
+        let (mut low, mut high) = (f32::MIN, f32::MAX);
+        for (position, delta, max) in [(x0, direction.0, width), (y0, direction.1, height)] {
+            if delta.abs() < 1e-6 {
+                if position < 0.0 || position > max {
+                    return None;
+                }
+                continue;
+            }
+            let (a, b) = (-position / delta, (max - position) / delta);
+            low = low.max(a.min(b));
+            high = high.min(a.max(b));
+        }
+        if low > high {
+            return None;
+        }
+
+        let point = |t: f32| (x0 + t * direction.0, y0 + t * direction.1);
+        Some(LineSegment {
+            start: point(low),
+            end: point(high),
+        })
+    }
+}
+
+impl ProbabilisticHoughLines {
+    pub fn new(threshold: u32, min_length: f32, max_gap: u32) -> Self {
+        Self {
+            rho_step: 1.0,
+            theta_step: PI / 180.0,
+            threshold,
+            min_length,
+            max_gap,
+        }
+    }
+
+    // Progressive probabilistic Hough transform: points vote in random order and every
+    // accepted line removes its pixels, and their votes, from further consideration
+    pub fn detect(&self, edges: &[f32], dimensions: (u32, u32)) -> Vec<LineSegment> {
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let mut accumulator = LineAccumulator::new(self.rho_step, self.theta_step, dimensions);
+        let mut points = edge_points(edges, dimensions);
+
+        // Fixed seed so repeated runs find the same segments
+        let mut state = 0x2545F491u32;
+        for i in (1..points.len()).rev() {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            points.swap(i, (state as usize) % (i + 1));
+        }
+
+        let mut remaining = vec![false; width * height];
+        for &(x, y) in &points {
+            remaining[y * width + x] = true;
+        }
+        let mut voted = vec![false; width * height];
+
+        let mut segments = Vec::new();
+        for &point in &points {
+            let index = point.1 * width + point.0;
+            if !remaining[index] {
+                continue;
+            }
+
+            let mut best = (0, 0);
+            for theta in 0..accumulator.thetas.len() {
+                let bin = accumulator.bin(point, theta);
+                accumulator.votes[bin] += 1;
+                if accumulator.votes[bin] > best.1 {
+                    best = (bin, accumulator.votes[bin]);
+                }
+            }
+            voted[index] = true;
+            if best.1 < self.threshold.max(1) {
+                continue;
+            }
+
+            // Walk along the line through this point in both directions
+            let (sin, cos) = accumulator.theta(best.0).sin_cos();
+            let scale = sin.abs().max(cos.abs());
+            let step = (-sin / scale, cos / scale);
+            let walk = |sign: f32, remaining: &[bool]| {
+                let mut end = point;
+                let mut gap = 0;
+                for k in 1.. {
+                    let x = (point.0 as f32 + sign * k as f32 * step.0).round();
+                    let y = (point.1 as f32 + sign * k as f32 * step.1).round();
+                    if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+                        break;
+                    }
+                    if remaining[y as usize * width + x as usize] {
+                        end = (x as usize, y as usize);
+                        gap = 0;
+                    } else {
+                        gap += 1;
+                        if gap > self.max_gap {
+                            break;
+                        }
+                    }
+                }
+                end
+            };
+            let (start, end) = (walk(-1.0, &remaining), walk(1.0, &remaining));
+            let length = (end.0 as f32 - start.0 as f32).hypot(end.1 as f32 - start.1 as f32);
+
+            // Consume the pixels of the segment, withdrawing the votes already cast
+            let steps = (end.0 as isize - start.0 as isize)
+                .abs()
+                .max((end.1 as isize - start.1 as isize).abs());
+            for k in 0..=steps {
+                let t = if steps == 0 {
+                    0.0
+                } else {
+                    k as f32 / steps as f32
+                };
+                let x = (start.0 as f32 + t * (end.0 as f32 - start.0 as f32)).round() as usize;
+                let y = (start.1 as f32 + t * (end.1 as f32 - start.1 as f32)).round() as usize;
+                let index = y * width + x;
+                if !remaining[index] {
+                    continue;
+                }
+                remaining[index] = false;
+                if voted[index] {
+                    for theta in 0..accumulator.thetas.len() {
+                        let bin = accumulator.bin((x, y), theta);
+                        accumulator.votes[bin] -= 1;
+                    }
+                    voted[index] = false;
+                }
+            }
+
+            if length >= self.min_length {
+                segments.push(LineSegment {
+                    start: (start.0 as f32, start.1 as f32),
+                    end: (end.0 as f32, end.1 as f32),
+                });
+            }
+        }
+        segments
+    }
+}
+
+impl HoughCircles {
+    pub fn new(min_radius: u32, max_radius: u32) -> Self {
+        Self {
+            min_radius,
+            max_radius,
+            threshold: 0.5,
+            min_distance: min_radius.max(1) as f32,
+            max_circles: 16,
+        }
+    }
+
+    // One accumulator plane per radius, votes normalised by the circumference so that
+    // small and large circles are compared fairly. Expects one pixel wide edges, thicker
+    // ones also score highly for the neighbouring radii and centres
+    pub fn detect(&self, edges: &[f32], dimensions: (u32, u32)) -> Vec<HoughCircle> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let points = edge_points(edges, dimensions);
+        let mut votes = vec![0u32; (width * height) as usize];
+
+        let mut candidates = Vec::new();
+        for radius in self.min_radius.max(1)..=self.max_radius.max(self.min_radius.max(1)) {
+            let offsets = circle_offsets(radius as f32);
+            votes.iter_mut().for_each(|count| *count = 0);
+            for &(x, y) in &points {
+                for &(dx, dy) in &offsets {
+                    let (cx, cy) = (x as isize + dx, y as isize + dy);
+                    if cx >= 0 && cy >= 0 && cx < width && cy < height {
+                        votes[(cy * width + cx) as usize] += 1;
+                    }
+                }
+            }
+
+            // The offsets are distinct, so every pixel of a circle adds at most one vote
+            for (index, &count) in votes.iter().enumerate() {
+                let score = count as f32 / offsets.len() as f32;
+                if score >= self.threshold {
+                    let (x, y) = (index as isize % width, index as isize / width);
+                    candidates.push(HoughCircle {
+                        center: (x as f32, y as f32),
+                        radius: radius as f32,
+                        score,
+                    });
+                }
+            }
+        }
+
+        // Strongest first, dropping anything centred too close to a circle already kept
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut circles: Vec<HoughCircle> = Vec::new();
+        for candidate in candidates {
+            if circles.len() >= self.max_circles {
+                break;
+            }
+            let isolated = circles.iter().all(|circle| {
+                (circle.center.0 - candidate.center.0).hypot(circle.center.1 - candidate.center.1)
+                    >= self.min_distance
+            });
+            if isolated {
+                circles.push(candidate);
+            }
+        }
+        circles
+    }
+}
+
+impl HoughOverlay {
+    pub fn new(detector: HoughDetector) -> Self {
+        Self {
+            detector,
+            color: 0xFF0000,
+            thickness: 2.0,
+        }
+    }
+
+    pub fn detect(
+        &self,
+        edges: &[f32],
+        dimensions: (u32, u32),
+    ) -> (Vec<LineSegment>, Vec<HoughCircle>) {
+        match &self.detector {
+            HoughDetector::Lines(detector) => (
+                detector
+                    .detect(edges, dimensions)
+                    .iter()
+                    .filter_map(|line| line.clip(dimensions))
+                    .collect(),
+                vec![],
+            ),
+            HoughDetector::Segments(detector) => (detector.detect(edges, dimensions), vec![]),
+            HoughDetector::Circles(detector) => (vec![], detector.detect(edges, dimensions)),
+        }
+    }
+}
+
+impl ImageFilter for HoughOverlay {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void houghOverlay(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                float halfThickness = options[1] * 0.5f;
+                int segmentCount = (int)options[2];
+                int circleCount = (int)options[3];
+                __global const float* segments = options + 4;
+                __global const float* circles = segments + segmentCount * 4;
+                float2 point = (float2)(x, y);
+
+                for (int i = 0; i < segmentCount; i++) {
+                    float2 start = (float2)(segments[i * 4], segments[i * 4 + 1]);
+                    float2 direction = (float2)(segments[i * 4 + 2], segments[i * 4 + 3]) - start;
+                    float length = dot(direction, direction);
+                    float t = length > 0.0f ? clamp(dot(point - start, direction) / length, 0.0f, 1.0f) : 0.0f;
+                    if (distance(point, start + t * direction) <= halfThickness) {
+                        outputImage[idx] = options[0];
+                        return;
+                    }
+                }
+
+                for (int i = 0; i < circleCount; i++) {
+                    float2 center = (float2)(circles[i * 3], circles[i * 3 + 1]);
+                    if (fabs(distance(point, center) - circles[i * 3 + 2]) <= halfThickness) {
+                        outputImage[idx] = options[0];
+                        return;
+                    }
+                }
+
+                outputImage[idx] = inputImage[idx];
+            }
+            "#,
+            "houghOverlay",
+        ))
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let grayscale = ImageConverter::convert_rgb_to_grayscale(
+            &ImageConverter::convert_packed_to_rgb(pixels),
+        );
+        let canny = CannyFilter.compute_options(&grayscale, dimensions);
+        let strong: Vec<f32> = CannyFilter
+            .process_cpu(&grayscale, &canny, dimensions)
+            .iter()
+            .map(|&edge| (edge > EDGE_THRESHOLD) as i32 as f32)
+            .collect();
+        // Sobel edges are a few pixels wide, thinning them keeps each edge to one vote
+        let edges = Thinning::new().process_cpu(&strong, &[], dimensions);
+        let (segments, circles) = self.detect(&edges, dimensions);
+
+        let mut options = vec![
+            (self.color & 0xFFFFFF) as f32,
+            self.thickness.max(1.0),
+            segments.len() as f32,
+            circles.len() as f32,
+        ];
+        for segment in segments {
+            options.extend([
+                segment.start.0,
+                segment.start.1,
+                segment.end.0,
+                segment.end.1,
+            ]);
+        }
+        for circle in circles {
+            options.extend([circle.center.0, circle.center.1, circle.radius]);
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let width = dimensions.0 as usize;
+        let half_thickness = options[1] * 0.5;
+        let (segment_count, circle_count) = (options[2] as usize, options[3] as usize);
+        let segments = &options[4..4 + segment_count * 4];
+        let circles = &options[4 + segment_count * 4..][..circle_count * 3];
+
+        pixels
+            .iter()
+            .enumerate()
+            .map(|(index, &pixel)| {
+                let (x, y) = ((index % width) as f32, (index / width) as f32);
+
+                let on_segment = segments.chunks(4).any(|segment| {
+                    let (dx, dy) = (segment[2] - segment[0], segment[3] - segment[1]);
+                    let length = dx * dx + dy * dy;
+                    let t = if length > 0.0 {
+                        (((x - segment[0]) * dx + (y - segment[1]) * dy) / length).clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    };
+                    (x - segment[0] - t * dx).hypot(y - segment[1] - t * dy) <= half_thickness
+                });
+                let on_circle = circles.chunks(3).any(|circle| {
+                    ((x - circle[0]).hypot(y - circle[1]) - circle[2]).abs() <= half_thickness
+                });
+
+                if on_segment || on_circle {
+                    options[0]
+                } else {
+                    pixel
+                }
+            })
+            .collect()
+    }
+}
+
+fn edge_points(edges: &[f32], dimensions: (u32, u32)) -> Vec<(usize, usize)> {
+    let width = dimensions.0 as usize;
+    edges
+        .iter()
+        .enumerate()
+        .filter(|(_, &edge)| edge > EDGE_THRESHOLD)
+        .map(|(index, _)| (index % width, index / width))
+        .collect()
+}
+
+// Distinct pixel offsets on a circle of the given radius
+fn circle_offsets(radius: f32) -> Vec<(isize, isize)> {
+    let steps = (2.0 * PI * radius).ceil().max(8.0) as usize * 2;
+    let mut offsets: Vec<(isize, isize)> = (0..steps)
+        .map(|i| {
+            let (sin, cos) = (i as f32 * 2.0 * PI / steps as f32).sin_cos();
+            (
+                (radius * cos).round() as isize,
+                (radius * sin).round() as isize,
+            )
+        })
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn circle_edges(center: (isize, isize), radius: f32, dimensions: (u32, u32)) -> Vec<f32> {
+        let width = dimensions.0 as isize;
+        let mut edges = vec![0.0; (dimensions.0 * dimensions.1) as usize];
+        for (dx, dy) in circle_offsets(radius) {
+            edges[((center.1 + dy) * width + center.0 + dx) as usize] = 1.0;
+        }
+        edges
+    }
+
+    #[test]
+    fn vertical_line_is_found() {
+        let edges: Vec<f32> = (0..400).map(|i| (i % 20 == 7) as i32 as f32).collect();
+        let lines = HoughLines::new(10).detect(&edges, (20, 20));
+
+        assert_eq!(lines[0].votes, 20);
+        assert!((lines[0].rho - 7.0).abs() < 1e-4);
+        assert!(lines[0].theta.abs() < 1e-4);
+
+        let segment = lines[0].clip((20, 20)).unwrap();
+        assert_eq!(segment.start.0.round(), 7.0);
+        assert_eq!((segment.start.1 - segment.end.1).abs().round(), 19.0);
+    }
+
+    #[test]
+    fn lines_outside_the_image_are_not_clipped() {
+        let line = HoughLine {
+            rho: 50.0,
+            theta: 0.0,
+            votes: 0,
+        };
+        assert_eq!(line.clip((20, 20)), None);
+    }
+
+    #[test]
+    fn segments_stop_at_gaps() {
+        // Two pieces of the same horizontal line with a wide gap between them
+        let edges: Vec<f32> = (0..30 * 10)
+            .map(|i| (i / 30 == 4 && (i % 30 < 10 || i % 30 >= 20)) as i32 as f32)
+            .collect();
+        let mut segments = ProbabilisticHoughLines::new(5, 5.0, 2).detect(&edges, (30, 10));
+        segments.sort_by(|a, b| a.start.0.min(a.end.0).total_cmp(&b.start.0.min(b.end.0)));
+
+        assert_eq!(segments.len(), 2);
+        let span = |segment: &LineSegment| {
+            (
+                segment.start.0.min(segment.end.0),
+                segment.start.0.max(segment.end.0),
+            )
+        };
+        assert_eq!(span(&segments[0]), (0.0, 9.0));
+        assert_eq!(span(&segments[1]), (20.0, 29.0));
+    }
+
+    #[test]
+    fn circle_is_found_with_a_full_score() {
+        let edges = circle_edges((15, 14), 8.0, (32, 32));
+        let circles = HoughCircles::new(5, 10).detect(&edges, (32, 32));
+
+        assert_eq!(circles[0].center, (15.0, 14.0));
+        assert_eq!(circles[0].radius, 8.0);
+        assert_eq!(circles[0].score, 1.0);
+        assert!(circles.iter().all(|circle| circle.score <= 1.0));
+    }
+
+    #[test]
+    fn overlay_draws_the_detected_circle() {
+        // White disc on black, whose thinned edge is a circle of about the same radius
+        let pixels: Vec<f32> = (0..40 * 40)
+            .map(|i| {
+                let (x, y) = ((i % 40) as f32 - 20.0, (i / 40) as f32 - 20.0);
+                if x.hypot(y) <= 10.0 {
+                    0xFFFFFF as f32
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let filter = HoughOverlay {
+            color: 0x00FF00,
+            ..HoughOverlay::new(HoughDetector::Circles(HoughCircles {
+                max_circles: 1,
+                ..HoughCircles::new(8, 12)
+            }))
+        };
+
+        let options = filter.compute_options(&pixels, (40, 40));
+        assert_eq!(options[3], 1.0);
+        let (x, y, radius) = (options[4], options[5], options[6]);
+        assert!((x - 20.0).abs() <= 1.0 && (y - 20.0).abs() <= 1.0);
+        assert!((radius - 10.0).abs() <= 1.0);
+
+        let output = run(&filter, &pixels, (40, 40));
+        assert_eq!(output[20 * 40 + 20], 0xFFFFFF as f32);
+        assert!(output.contains(&(0x00FF00 as f32)));
+    }
+}
@@ -46,9 +46,9 @@ impl ImageConverter {
         let mut output = Vec::with_capacity(r_channel.len());
 
         for i in 0..r_channel.len() {
-            let r = (r_channel[i].min(1.0).max(0.0) * 255.0) as u32;
-            let g = (g_channel[i].min(1.0).max(0.0) * 255.0) as u32;
-            let b = (b_channel[i].min(1.0).max(0.0) * 255.0) as u32;
+            let r = (r_channel[i].clamp(0.0, 1.0) * 255.0) as u32;
+            let g = (g_channel[i].clamp(0.0, 1.0) * 255.0) as u32;
+            let b = (b_channel[i].clamp(0.0, 1.0) * 255.0) as u32;
             let a = 255; // Fully opaque
 
             output.push((a << 24) | (r << 16) | (g << 8) | b);
@@ -70,9 +70,9 @@ impl ImageConverter {
             let orig_g = ((original[i] >> 8) & 0xFF) as f32 / 255.0;
             let orig_b = (original[i] & 0xFF) as f32 / 255.0;
 
-            let r = ((r_channel[i] + orig_r).min(1.0).max(0.0) * 255.0) as u32;
-            let g = ((g_channel[i] + orig_g).min(1.0).max(0.0) * 255.0) as u32;
-            let b = ((b_channel[i] + orig_b).min(1.0).max(0.0) * 255.0) as u32;
+            let r = ((r_channel[i] + orig_r).clamp(0.0, 1.0) * 255.0) as u32;
+            let g = ((g_channel[i] + orig_g).clamp(0.0, 1.0) * 255.0) as u32;
+            let b = ((b_channel[i] + orig_b).clamp(0.0, 1.0) * 255.0) as u32;
             let a = 255; // Fully opaque
 
             output.push((a << 24) | (r << 16) | (g << 8) | b);
@@ -80,4 +80,54 @@ impl ImageConverter {
 
         output
     }
+
+    // 24-bit colours are exactly representable as floats, so packed pixels can travel
+    // through the float pipeline unchanged
+    pub fn convert_rgb_to_packed(pixels: &[u32]) -> Vec<f32> {
+        pixels
+            .iter()
+            .map(|&pixel| (pixel & 0xFFFFFF) as f32)
+            .collect()
+    }
+
+    pub fn convert_packed_to_rgb(pixels: &[f32]) -> Vec<u32> {
+        pixels
+            .iter()
+            .map(|&value| 0xFF000000 | (value.clamp(0.0, 16777215.0) as u32))
+            .collect()
+    }
+
+    // BT.601 luma with colour-difference chroma
+    pub fn convert_rgb_to_ycbcr(pixels: &[u32]) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        let (r_channel, g_channel, b_channel) = Self::decompose_rgb(pixels);
+
+        let mut y_channel = Vec::with_capacity(pixels.len());
+        let mut cb_channel = Vec::with_capacity(pixels.len());
+        let mut cr_channel = Vec::with_capacity(pixels.len());
+        for i in 0..pixels.len() {
+            let y = 0.299 * r_channel[i] + 0.587 * g_channel[i] + 0.114 * b_channel[i];
+            y_channel.push(y);
+            cb_channel.push(0.564 * (b_channel[i] - y));
+            cr_channel.push(0.713 * (r_channel[i] - y));
+        }
+
+        (y_channel, cb_channel, cr_channel)
+    }
+
+    pub fn convert_ycbcr_to_rgb(
+        y_channel: &[f32],
+        cb_channel: &[f32],
+        cr_channel: &[f32],
+    ) -> Vec<u32> {
+        let mut r_channel = Vec::with_capacity(y_channel.len());
+        let mut g_channel = Vec::with_capacity(y_channel.len());
+        let mut b_channel = Vec::with_capacity(y_channel.len());
+        for i in 0..y_channel.len() {
+            r_channel.push(y_channel[i] + 1.403 * cr_channel[i]);
+            g_channel.push(y_channel[i] - 0.344 * cb_channel[i] - 0.714 * cr_channel[i]);
+            b_channel.push(y_channel[i] + 1.773 * cb_channel[i]);
+        }
+
+        Self::recompose_rgb(&r_channel, &g_channel, &b_channel)
+    }
 }
@@ -1,11 +1,60 @@
-use super::filters::ImageFilter;
+use super::filters::{ColorMode, ImageFilter};
 use super::image_converter::ImageConverter;
 use super::opencl_processor::OpenCLProcessor;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    OpenCL,
+    Cpu,
+}
+
+impl Backend {
+    // OpenCL when a platform is present, the CPU otherwise
+    pub fn available() -> Self {
+        if OpenCLProcessor::is_available() {
+            Backend::OpenCL
+        } else {
+            Backend::Cpu
+        }
+    }
+
+    // Runs a filter on one plane, on the CPU for stages without a kernel
+    pub fn process(
+        &self,
+        filter: &dyn ImageFilter,
+        channel: &[f32],
+        options: &[f32],
+        dimensions: (u32, u32),
+    ) -> Vec<f32> {
+        let output_dimensions = filter.output_dimensions(dimensions);
+        if output_dimensions.0 == 0 || output_dimensions.1 == 0 {
+            return Vec::new();
+        }
+
+        // OpenCL cannot create buffers for an empty input, so stages that still give an output,
+        // like a warp to a fixed size, run on the CPU
+        match (self, filter.get_kernel()) {
+            (Backend::OpenCL, Some(kernel)) if !channel.is_empty() => {
+                let processor = OpenCLProcessor::new(channel, options, dimensions)
+                    .with_output_dimensions(output_dimensions)
+                    .with_scratch(filter.scratch_size(dimensions));
+                match filter.get_schedule(dimensions) {
+                    Some((passes, work_size)) => {
+                        processor.process_scheduled(kernel, passes, work_size)
+                    }
+                    None => processor.process(kernel),
+                }
+            }
+            _ => filter.process_cpu(channel, options, dimensions),
+        }
+    }
+}
+
 pub struct ImageProcessor<'a, 'b> {
     input: &'a [u32],
     dimensions: (u32, u32),
     filters: &'b [Box<dyn ImageFilter>],
+    backend: Backend,
 }
 
 impl<'a, 'b> ImageProcessor<'a, 'b> {
@@ -18,66 +67,133 @@ impl<'a, 'b> ImageProcessor<'a, 'b> {
             input,
             dimensions,
             filters,
+            backend: Backend::available(),
         }
     }
 
-    pub fn preprocess_image(
-        &self,
-        filter: &Box<dyn ImageFilter>,
-    ) -> ((Vec<f32>, Vec<f32>, Vec<f32>), Vec<f32>) {
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn preprocess_image(&self, filter: &dyn ImageFilter) -> (Vec<Vec<f32>>, Vec<f32>) {
         let grayscale = ImageConverter::convert_rgb_to_grayscale(self.input);
-        let options = filter.compute_options(&grayscale);
-        let kernel = filter.get_kernel();
-        match kernel.1 {
-            "gaussianBlur" | "laplacianSharpening" | "bayerOrderedDithering" => {
-                let channels = ImageConverter::decompose_rgb(self.input);
-                (channels, options)
+        match filter.color_mode() {
+            ColorMode::Rgb | ColorMode::RgbResidual => {
+                let (r, g, b) = ImageConverter::decompose_rgb(self.input);
+                let options = filter.compute_options(&grayscale, self.dimensions);
+                (vec![r, g, b], options)
+            }
+            ColorMode::Grayscale => {
+                let options = filter.compute_options(&grayscale, self.dimensions);
+                (vec![grayscale], options)
+            }
+            ColorMode::Luminance => {
+                // Statistics come from the luma plane the filter will actually see
+                let (luma, _, _) = ImageConverter::convert_rgb_to_ycbcr(self.input);
+                let options = filter.compute_options(&luma, self.dimensions);
+                (vec![luma], options)
+            }
+            ColorMode::Packed => {
+                // Packed filters see whole colours when computing their options too
+                let packed = ImageConverter::convert_rgb_to_packed(self.input);
+                let options = filter.compute_options(&packed, self.dimensions);
+                (vec![packed], options)
             }
-            _ => ((grayscale, vec![], vec![]), options),
         }
     }
 
     pub fn process_image(&self) -> Vec<Vec<u32>> {
         self.filters
             .iter()
-            .map(|filter| {
-                let kernel = filter.get_kernel();
-                let (channels, options) = self.preprocess_image(filter);
-
-                let channels: Vec<Vec<f32>> = match kernel.1 {
-                    "gaussianBlur" | "laplacianSharpening" | "bayerOrderedDithering" => {
-                        vec![
-                            OpenCLProcessor::new(&channels.0, &options, self.dimensions)
-                                .process(kernel),
-                            OpenCLProcessor::new(&channels.1, &options, self.dimensions)
-                                .process(kernel),
-                            OpenCLProcessor::new(&channels.2, &options, self.dimensions)
-                                .process(kernel),
-                        ]
-                    }
-                    _ => {
-                        vec![OpenCLProcessor::new(&channels.0, &options, self.dimensions)
-                            .process(kernel)]
-                    }
-                };
+            .map(|filter| self.process_filter(filter.as_ref()))
+            .collect()
+    }
+
+    // Runs the filters one after another, each on the output of the previous one
+    pub fn process_chain(&self) -> (Vec<u32>, (u32, u32)) {
+        self.filters.iter().fold(
+            (self.input.to_vec(), self.dimensions),
+            |(image, dimensions), filter| {
+                let output = ImageProcessor {
+                    input: &image,
+                    dimensions,
+                    filters: self.filters,
+                    backend: self.backend,
+                }
+                .process_filter(filter.as_ref());
+                (output, filter.output_dimensions(dimensions))
+            },
+        )
+    }
 
-                self.postprocess_image(&channels, kernel)
+    pub fn process_filter(&self, filter: &dyn ImageFilter) -> Vec<u32> {
+        let (channels, options) = self.preprocess_image(filter);
+
+        let channels: Vec<Vec<f32>> = channels
+            .iter()
+            .map(|channel| {
+                self.backend
+                    .process(filter, channel, &options, self.dimensions)
             })
-            .collect()
+            .collect();
+
+        self.postprocess_image(&channels, filter)
     }
 
-    pub fn postprocess_image(&self, channels: &[Vec<f32>], kernel: (&str, &str)) -> Vec<u32> {
-        match kernel.1 {
-            "gaussianBlur" | "bayerOrderedDithering" => {
+    pub fn postprocess_image(&self, channels: &[Vec<f32>], filter: &dyn ImageFilter) -> Vec<u32> {
+        match filter.color_mode() {
+            ColorMode::Rgb => {
                 ImageConverter::recompose_rgb(&channels[0], &channels[1], &channels[2])
             }
-            "laplacianSharpening" => ImageConverter::recompose_rgb_with_original(
+            ColorMode::RgbResidual => ImageConverter::recompose_rgb_with_original(
                 &channels[0],
                 &channels[1],
                 &channels[2],
                 self.input,
             ),
-            _ => ImageConverter::convert_grayscale_to_rgb(&channels[0]),
+            ColorMode::Grayscale => ImageConverter::convert_grayscale_to_rgb(&channels[0]),
+            ColorMode::Packed => ImageConverter::convert_packed_to_rgb(&channels[0]),
+            ColorMode::Luminance => {
+                let (_, cb, cr) = ImageConverter::convert_rgb_to_ycbcr(self.input);
+                ImageConverter::convert_ycbcr_to_rgb(&channels[0], &cb, &cr)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processing::filters::{GaussianBlur, LaplacianSharpening};
+
+    #[test]
+    fn chain_runs_filters_in_sequence() {
+        let input: Vec<u32> = (0..64).map(|i| (i * 0x030201) & 0xFFFFFF).collect();
+        let filters: Vec<Box<dyn ImageFilter>> =
+            vec![Box::new(GaussianBlur), Box::new(LaplacianSharpening)];
+        let processor = ImageProcessor::new(&input, (8, 8), &filters).with_backend(Backend::Cpu);
+
+        let blurred = processor.process_filter(filters[0].as_ref());
+        let expected = ImageProcessor::new(&blurred, (8, 8), &filters)
+            .with_backend(Backend::Cpu)
+            .process_filter(filters[1].as_ref());
+        assert_eq!(processor.process_chain(), (expected, (8, 8)));
+    }
+
+    #[test]
+    fn blur_keeps_a_flat_image() {
+        let input = vec![0xFF336699; 25];
+        let filters: Vec<Box<dyn ImageFilter>> = vec![Box::new(GaussianBlur)];
+        let output = ImageProcessor::new(&input, (5, 5), &filters)
+            .with_backend(Backend::Cpu)
+            .process_image();
+
+        for pixel in &output[0] {
+            for shift in [16, 8, 0] {
+                let (a, b) = ((pixel >> shift) & 0xFF, (input[0] >> shift) & 0xFF);
+                assert!(a.abs_diff(b) <= 1);
+            }
         }
     }
 }
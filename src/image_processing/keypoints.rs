@@ -0,0 +1,413 @@
+use super::filters::{convolve_3x3, ColorMode, GradientOperator, ImageFilter};
+use super::image_converter::ImageConverter;
+
+// Bresenham circle of radius 3 used by FAST, clockwise from the top
+const FAST_CIRCLE: [(isize, isize); 16] = [
+    (0, -3),
+    (1, -3),
+    (2, -2),
+    (3, -1),
+    (3, 0),
+    (3, 1),
+    (2, 2),
+    (1, 3),
+    (0, 3),
+    (-1, 3),
+    (-2, 2),
+    (-3, 1),
+    (-3, 0),
+    (-3, -1),
+    (-2, -2),
+    (-1, -3),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keypoint {
+    pub position: (f32, f32),
+    pub score: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CornerMethod {
+    // det(M) - k * trace(M)^2
+    Harris { k: f32 },
+    // Smaller eigenvalue of M
+    ShiTomasi,
+}
+
+pub struct CornerDetector {
+    pub method: CornerMethod,
+    // Odd side length of the window the structure tensor is summed over
+    pub window: u32,
+    // Responses below this fraction of the strongest one are ignored
+    pub quality: f32,
+    // Corners closer than this to a stronger one are suppressed
+    pub min_distance: f32,
+    pub max_corners: usize,
+}
+
+pub struct Fast {
+    pub threshold: f32,
+    // Contiguous circle pixels that must all be brighter or all darker, 9 to 12
+    pub arc_length: u32,
+    pub non_max_suppression: bool,
+}
+
+pub enum KeypointDetector {
+    Corners(CornerDetector),
+    Fast(Fast),
+}
+
+// Detects keypoints on the image and circles them
+pub struct KeypointOverlay {
+    pub detector: KeypointDetector,
+    // 0xRRGGBB
+    pub color: u32,
+    pub radius: f32,
+}
+
+impl CornerDetector {
+    pub fn new(method: CornerMethod) -> Self {
+        Self {
+            method,
+            window: 3,
+            quality: 0.01,
+            min_distance: 5.0,
+            max_corners: 256,
+        }
+    }
+
+    // Corner response for every pixel, from Sobel gradients summed over the window
+    pub fn response(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let kernels = GradientOperator::Sobel.kernels();
+        let gx = convolve_3x3(pixels, &kernels[0], dimensions);
+        let gy = convolve_3x3(pixels, &kernels[1], dimensions);
+        let half = (self.window.max(1) | 1) as isize / 2;
+
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+                for ny in (y - half).max(0)..=(y + half).min(height - 1) {
+                    for nx in (x - half).max(0)..=(x + half).min(width - 1) {
+                        // Sobel weights sum to eight on each side
+                        let index = (ny * width + nx) as usize;
+                        let (dx, dy) = (gx[index] / 8.0, gy[index] / 8.0);
+                        xx += dx * dx;
+                        yy += dy * dy;
+                        xy += dx * dy;
+                    }
+                }
+
+                match self.method {
+                    CornerMethod::Harris { k } => xx * yy - xy * xy - k * (xx + yy).powi(2),
+                    CornerMethod::ShiTomasi => {
+                        (xx + yy) / 2.0 - (((xx - yy) / 2.0).powi(2) + xy * xy).sqrt()
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn detect(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<Keypoint> {
+        let response = self.response(pixels, dimensions);
+        let strongest = response.iter().copied().fold(0.0, f32::max);
+        if strongest <= 0.0 {
+            return vec![];
+        }
+
+        let candidates = local_maxima(&response, dimensions, self.quality * strongest);
+        suppress(candidates, self.min_distance, self.max_corners)
+    }
+}
+
+impl Fast {
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            arc_length: 9,
+            non_max_suppression: true,
+        }
+    }
+
+    // Sum of how far the arc pixels exceed the threshold, zero for non-corners
+    fn score(&self, pixels: &[f32], width: isize, x: isize, y: isize) -> f32 {
+        let centre = pixels[(y * width + x) as usize];
+        let ring: Vec<f32> = FAST_CIRCLE
+            .iter()
+            .map(|&(dx, dy)| pixels[((y + dy) * width + x + dx) as usize] - centre)
+            .collect();
+        let arc = self.arc_length.clamp(9, 12) as usize;
+
+        let mut best: f32 = 0.0;
+        for sign in [1.0, -1.0] {
+            // Walk the ring twice so that arcs may wrap around the start
+            let (mut run, mut sum) = (0, 0.0);
+            for i in 0..32 {
+                let difference = sign * ring[i % 16];
+                if difference > self.threshold {
+                    run += 1;
+                    sum += difference - self.threshold;
+                    if run >= arc {
+                        best = best.max(sum);
+                    }
+                    if run == 16 {
+                        break;
+                    }
+                } else {
+                    (run, sum) = (0, 0.0);
+                }
+            }
+        }
+        best
+    }
+
+    pub fn detect(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<Keypoint> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let mut scores = vec![0.0; pixels.len()];
+        for y in 3..height - 3 {
+            for x in 3..width - 3 {
+                scores[(y * width + x) as usize] = self.score(pixels, width, x, y);
+            }
+        }
+
+        let mut keypoints = if self.non_max_suppression {
+            local_maxima(&scores, dimensions, f32::MIN_POSITIVE)
+        } else {
+            scores
+                .iter()
+                .enumerate()
+                .filter(|(_, &score)| score > 0.0)
+                .map(|(index, &score)| Keypoint {
+                    position: (
+                        (index as isize % width) as f32,
+                        (index as isize / width) as f32,
+                    ),
+                    score,
+                })
+                .collect()
+        };
+        keypoints.sort_by(|a, b| b.score.total_cmp(&a.score));
+        keypoints
+    }
+}
+
+impl KeypointOverlay {
+    pub fn new(detector: KeypointDetector) -> Self {
+        Self {
+            detector,
+            color: 0x00FF00,
+            radius: 3.0,
+        }
+    }
+
+    pub fn detect(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<Keypoint> {
+        match &self.detector {
+            KeypointDetector::Corners(detector) => detector.detect(pixels, dimensions),
+            KeypointDetector::Fast(detector) => detector.detect(pixels, dimensions),
+        }
+    }
+}
+
+impl ImageFilter for KeypointOverlay {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void keypointOverlay(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                float radius = options[1];
+                int count = (int)options[2];
+                float2 point = (float2)(x, y);
+
+                for (int i = 0; i < count; i++) {
+                    float2 keypoint = (float2)(options[3 + i * 2], options[4 + i * 2]);
+                    if (fabs(distance(point, keypoint) - radius) <= 0.5f) {
+                        outputImage[idx] = options[0];
+                        return;
+                    }
+                }
+                outputImage[idx] = inputImage[idx];
+            }
+            "#,
+            "keypointOverlay",
+        ))
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let grayscale = ImageConverter::convert_rgb_to_grayscale(
+            &ImageConverter::convert_packed_to_rgb(pixels),
+        );
+        let keypoints = self.detect(&grayscale, dimensions);
+
+        let mut options = vec![
+            (self.color & 0xFFFFFF) as f32,
+            self.radius.max(1.0),
+            keypoints.len() as f32,
+        ];
+        for keypoint in keypoints {
+            options.extend([keypoint.position.0, keypoint.position.1]);
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let width = dimensions.0 as usize;
+        let radius = options[1];
+        let keypoints = &options[3..3 + options[2] as usize * 2];
+
+        pixels
+            .iter()
+            .enumerate()
+            .map(|(index, &pixel)| {
+                let (x, y) = ((index % width) as f32, (index / width) as f32);
+                let circled = keypoints.chunks(2).any(|keypoint| {
+                    ((x - keypoint[0]).hypot(y - keypoint[1]) - radius).abs() <= 0.5
+                });
+                if circled {
+                    options[0]
+                } else {
+                    pixel
+                }
+            })
+            .collect()
+    }
+}
+
+// Pixels at or above the threshold that no 8-neighbour beats, ties going to the first in scan order
+fn local_maxima(values: &[f32], dimensions: (u32, u32), threshold: f32) -> Vec<Keypoint> {
+    let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+    (0..width * height)
+        .filter_map(|i| {
+            let value = values[i as usize];
+            if value < threshold {
+                return None;
+            }
+
+            let (x, y) = (i % width, i / width);
+            let maximum = (-1..=1).all(|dy| {
+                (-1..=1).all(|dx| {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        return true;
+                    }
+                    let neighbour = ny * width + nx;
+                    let other = values[neighbour as usize];
+                    other < value || (other == value && neighbour >= i)
+                })
+            });
+            maximum.then_some(Keypoint {
+                position: (x as f32, y as f32),
+                score: value,
+            })
+        })
+        .collect()
+}
+
+// Greedy suppression, strongest first
+fn suppress(mut candidates: Vec<Keypoint>, min_distance: f32, limit: usize) -> Vec<Keypoint> {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut kept: Vec<Keypoint> = Vec::new();
+    for candidate in candidates {
+        if kept.len() >= limit {
+            break;
+        }
+        let isolated = kept.iter().all(|keypoint| {
+            (keypoint.position.0 - candidate.position.0)
+                .hypot(keypoint.position.1 - candidate.position.1)
+                >= min_distance
+        });
+        if isolated {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bright 8x8 square with corners at (6, 6) and (13, 13)
+    fn square() -> Vec<f32> {
+        (0..400)
+            .map(|i| {
+                let (x, y) = (i % 20, i / 20);
+                ((6..14).contains(&x) && (6..14).contains(&y)) as i32 as f32
+            })
+            .collect()
+    }
+
+    fn near_square_corners(keypoints: &[Keypoint]) -> bool {
+        keypoints.iter().all(|keypoint| {
+            let (x, y) = keypoint.position;
+            [5.5, 13.5].iter().any(|c| (x - c).abs() <= 1.5)
+                && [5.5, 13.5].iter().any(|c| (y - c).abs() <= 1.5)
+        })
+    }
+
+    #[test]
+    fn corner_detectors_find_the_square_corners() {
+        for method in [CornerMethod::Harris { k: 0.04 }, CornerMethod::ShiTomasi] {
+            let keypoints = CornerDetector::new(method).detect(&square(), (20, 20));
+            assert_eq!(keypoints.len(), 4, "{method:?}");
+            assert!(near_square_corners(&keypoints), "{method:?} {keypoints:?}");
+        }
+    }
+
+    #[test]
+    fn straight_edges_are_not_corners() {
+        let detector = CornerDetector::new(CornerMethod::Harris { k: 0.04 });
+        let response = detector.response(&square(), (20, 20));
+        // Middle of the left edge responds negatively, the corner positively
+        assert!(response[10 * 20 + 6] < 0.0);
+        assert!(response[6 * 20 + 6] > 0.0);
+    }
+
+    #[test]
+    fn flat_images_have_no_keypoints() {
+        let flat = vec![0.5; 400];
+        assert!(CornerDetector::new(CornerMethod::ShiTomasi)
+            .detect(&flat, (20, 20))
+            .is_empty());
+        assert!(Fast::new(0.1).detect(&flat, (20, 20)).is_empty());
+    }
+
+    #[test]
+    fn fast_finds_the_square_corners() {
+        let keypoints = Fast::new(0.2).detect(&square(), (20, 20));
+        assert_eq!(keypoints.len(), 4);
+        assert!(near_square_corners(&keypoints), "{keypoints:?}");
+    }
+
+    #[test]
+    fn suppression_keeps_the_strongest_of_close_points() {
+        let keypoint = |x: f32, score: f32| Keypoint {
+            position: (x, 0.0),
+            score,
+        };
+        let kept = suppress(
+            vec![keypoint(0.0, 1.0), keypoint(2.0, 3.0), keypoint(10.0, 2.0)],
+            5.0,
+            8,
+        );
+        assert_eq!(kept, vec![keypoint(2.0, 3.0), keypoint(10.0, 2.0)]);
+    }
+}
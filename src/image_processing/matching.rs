@@ -0,0 +1,435 @@
+use super::error::{check_size, FilterError};
+use super::filters::{ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+use super::image_processor::Backend;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchMethod {
+    // Sum of squared differences, lower is better
+    SquaredDifference,
+    // Normalised cross-correlation, 0 to 1 for non-negative images
+    CrossCorrelation,
+    // Zero-mean normalised cross-correlation, -1 to 1 and unaffected by brightness and contrast
+    ZeroMeanCrossCorrelation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    // Top left corner of the template in the image
+    pub position: (u32, u32),
+    pub score: f32,
+}
+
+// Slides the template over the image, the stage shows the response with the best matches brightest
+pub struct TemplateMatcher {
+    // Grayscale template, checked against its dimensions in `new`
+    template: Vec<f32>,
+    template_dimensions: (u32, u32),
+    pub method: MatchMethod,
+    // None keeps only the best match, otherwise every local optimum at least this good
+    pub threshold: Option<f32>,
+    // Matches overlapping a better one by more than this fraction (intersection over union) are dropped
+    pub max_overlap: f32,
+    pub max_matches: usize,
+}
+
+impl MatchMethod {
+    pub fn is_better(&self, score: f32, other: f32) -> bool {
+        match self {
+            MatchMethod::SquaredDifference => score < other,
+            _ => score > other,
+        }
+    }
+}
+
+impl TemplateMatcher {
+    pub fn new(
+        template: &[u32],
+        template_dimensions: (u32, u32),
+        method: MatchMethod,
+    ) -> Result<Self, FilterError> {
+        check_size("Template", template.len(), template_dimensions)?;
+        if template.is_empty() {
+            return Err(FilterError::InvalidParameter {
+                name: "Template",
+                reason: "must not be empty",
+            });
+        }
+
+        Ok(Self {
+            template: ImageConverter::convert_rgb_to_grayscale(template),
+            template_dimensions,
+            method,
+            threshold: None,
+            max_overlap: 0.3,
+            max_matches: 16,
+        })
+    }
+
+    pub fn template(&self) -> (&[f32], (u32, u32)) {
+        (&self.template, self.template_dimensions)
+    }
+
+    // Positions the template fits at without leaving the image, none if it is larger
+    pub fn response_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        if self.template_dimensions.0 > dimensions.0 || self.template_dimensions.1 > dimensions.1 {
+            return (0, 0);
+        }
+        (
+            dimensions.0 - self.template_dimensions.0 + 1,
+            dimensions.1 - self.template_dimensions.1 + 1,
+        )
+    }
+
+    // Raw score of the method for every template position
+    pub fn response(&self, pixels: &[f32], dimensions: (u32, u32), backend: Backend) -> Vec<f32> {
+        backend.process(self, pixels, &self.options(true), dimensions)
+    }
+
+    // Scores are mapped to 0..1 for display unless `raw` is set
+    fn options(&self, raw: bool) -> Vec<f32> {
+        let count = self.template.len() as f32;
+        let mean = self.template.iter().sum::<f32>() / count;
+        // Zero-mean correlation compares against the template's deviations instead
+        let energy = match self.method {
+            MatchMethod::ZeroMeanCrossCorrelation => self
+                .template
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum(),
+            _ => self.template.iter().map(|value| value * value).sum(),
+        };
+
+        let mut options = vec![
+            self.method as i32 as f32,
+            self.template_dimensions.0 as f32,
+            self.template_dimensions.1 as f32,
+            mean,
+            energy,
+            raw as i32 as f32,
+        ];
+        options.extend(&self.template);
+        options
+    }
+
+    fn scores(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (response_width, response_height) = self.response_dimensions(dimensions);
+        let (template_width, template_height) = (
+            self.template_dimensions.0 as usize,
+            self.template_dimensions.1 as usize,
+        );
+        let width = dimensions.0 as usize;
+        let count = (template_width * template_height) as f32;
+        let (template_mean, template_energy) = (options[3], options[4]);
+
+        (0..(response_width * response_height) as usize)
+            .map(|i| {
+                let (x, y) = (i % response_width as usize, i / response_width as usize);
+                let (mut sum, mut squares, mut products) = (0.0, 0.0, 0.0);
+                for ty in 0..template_height {
+                    let row = &pixels[(y + ty) * width + x..(y + ty) * width + x + template_width];
+                    let template = &self.template[ty * template_width..(ty + 1) * template_width];
+                    for (&pixel, &weight) in row.iter().zip(template) {
+                        sum += pixel;
+                        squares += pixel * pixel;
+                        products += pixel * weight;
+                    }
+                }
+
+                match self.method {
+                    // Expanded as I^2 - 2IT + T^2 with the template energy precomputed
+                    MatchMethod::SquaredDifference => {
+                        (squares - 2.0 * products + template_energy).max(0.0)
+                    }
+                    MatchMethod::CrossCorrelation => {
+                        let norm = (squares * template_energy).sqrt();
+                        if norm > f32::EPSILON {
+                            products / norm
+                        } else {
+                            0.0
+                        }
+                    }
+                    MatchMethod::ZeroMeanCrossCorrelation => {
+                        let variance = squares - sum * sum / count;
+                        let norm = (variance.max(0.0) * template_energy).sqrt();
+                        if norm > f32::EPSILON {
+                            (products - sum * template_mean) / norm
+                        } else {
+                            0.0
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Best match first
+    pub fn find(&self, pixels: &[f32], dimensions: (u32, u32), backend: Backend) -> Vec<Match> {
+        let response = self.response(pixels, dimensions, backend);
+        let (width, height) = self.response_dimensions(dimensions);
+        let at = |x: u32, y: u32| Match {
+            position: (x, y),
+            score: response[(y * width + x) as usize],
+        };
+
+        let Some(threshold) = self.threshold else {
+            let best = (0..width * height)
+                .map(|i| at(i % width, i / width))
+                .reduce(|best, candidate| {
+                    if self.method.is_better(candidate.score, best.score) {
+                        candidate
+                    } else {
+                        best
+                    }
+                });
+            return best.into_iter().collect();
+        };
+
+        // Local optima over the 8-neighbourhood that pass the threshold
+        let mut candidates: Vec<Match> = (0..width * height)
+            .map(|i| at(i % width, i / width))
+            .filter(|candidate| !self.method.is_better(threshold, candidate.score))
+            .filter(|candidate| {
+                let (x, y) = (candidate.position.0 as i64, candidate.position.1 as i64);
+                (-1..=1).all(|dy| {
+                    (-1..=1).all(|dx| {
+                        let (nx, ny) = (x + dx, y + dy);
+                        nx < 0
+                            || ny < 0
+                            || nx >= width as i64
+                            || ny >= height as i64
+                            || !self
+                                .method
+                                .is_better(at(nx as u32, ny as u32).score, candidate.score)
+                    })
+                })
+            })
+            .collect();
+        candidates.sort_by(|a, b| match self.method {
+            MatchMethod::SquaredDifference => a.score.total_cmp(&b.score),
+            _ => b.score.total_cmp(&a.score),
+        });
+
+        let (template_width, template_height) = (
+            self.template_dimensions.0 as f32,
+            self.template_dimensions.1 as f32,
+        );
+        let area = template_width * template_height;
+        let mut matches: Vec<Match> = Vec::new();
+        for candidate in candidates {
+            if matches.len() >= self.max_matches {
+                break;
+            }
+            let isolated = matches.iter().all(|kept| {
+                let dx = (kept.position.0 as f32 - candidate.position.0 as f32).abs();
+                let dy = (kept.position.1 as f32 - candidate.position.1 as f32).abs();
+                let intersection = (template_width - dx).max(0.0) * (template_height - dy).max(0.0);
+                intersection / (2.0 * area - intersection) <= self.max_overlap
+            });
+            if isolated {
+                matches.push(candidate);
+            }
+        }
+        matches
+    }
+}
+
+impl ImageFilter for TemplateMatcher {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void templateMatching(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                int method = (int)options[0];
+                int templateWidth = (int)options[1];
+                int templateHeight = (int)options[2];
+                float templateMean = options[3];
+                float templateEnergy = options[4];
+                int raw = (int)options[5];
+                int responseWidth = width - templateWidth + 1;
+
+                if (x >= responseWidth || y >= height - templateHeight + 1)
+                    return;
+
+                float sum = 0.0f, squares = 0.0f, products = 0.0f;
+                for (int ty = 0; ty < templateHeight; ty++) {
+                    for (int tx = 0; tx < templateWidth; tx++) {
+                        float pixel = inputImage[(y + ty) * width + x + tx];
+                        sum += pixel;
+                        squares += pixel * pixel;
+                        products += pixel * options[6 + ty * templateWidth + tx];
+                    }
+                }
+
+                float count = templateWidth * templateHeight;
+                float score;
+                if (method == 0) {
+                    score = max(squares - 2.0f * products + templateEnergy, 0.0f);
+                } else if (method == 1) {
+                    float norm = sqrt(squares * templateEnergy);
+                    score = norm > FLT_EPSILON ? products / norm : 0.0f;
+                } else {
+                    float norm = sqrt(max(squares - sum * sum / count, 0.0f) * templateEnergy);
+                    score = norm > FLT_EPSILON ? (products - sum * templateMean) / norm : 0.0f;
+                }
+
+                // Otherwise scores are mapped to 0..1 with the best matches brightest
+                float value = score;
+                if (!raw) {
+                    if (method == 0)
+                        value = 1.0f - score / count;
+                    else if (method == 2)
+                        value = (score + 1.0f) / 2.0f;
+                    value = clamp(value, 0.0f, 1.0f);
+                }
+                outputImage[y * responseWidth + x] = value;
+            }
+            "#,
+            "templateMatching",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        self.options(false)
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Grayscale
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        self.response_dimensions(dimensions)
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let scores = self.scores(pixels, options, dimensions);
+        if options[5] != 0.0 {
+            return scores;
+        }
+
+        let count = options[1] * options[2];
+        scores
+            .iter()
+            .map(|&score| {
+                let value = match self.method {
+                    MatchMethod::SquaredDifference => 1.0 - score / count,
+                    MatchMethod::CrossCorrelation => score,
+                    MatchMethod::ZeroMeanCrossCorrelation => (score + 1.0) / 2.0,
+                };
+                value.clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Dark 12x10 image with a bright cross whose top left corner is at (6, 3)
+    fn scene() -> Vec<f32> {
+        let mut pixels: Vec<f32> = (0..120).map(|i| ((i * 7) % 5) as f32 * 0.02).collect();
+        for (x, y) in [(7, 3), (6, 4), (7, 4), (8, 4), (7, 5)] {
+            pixels[y * 12 + x] = 1.0;
+        }
+        pixels
+    }
+
+    fn cross() -> Vec<u32> {
+        [0, 1, 0, 1, 1, 1, 0, 1, 0]
+            .iter()
+            .map(|&on| if on == 1 { 0xFFFFFFFF } else { 0xFF000000 })
+            .collect()
+    }
+
+    #[test]
+    fn every_method_finds_the_template() {
+        for method in [
+            MatchMethod::SquaredDifference,
+            MatchMethod::CrossCorrelation,
+            MatchMethod::ZeroMeanCrossCorrelation,
+        ] {
+            let matcher = TemplateMatcher::new(&cross(), (3, 3), method).unwrap();
+            let matches = matcher.find(&scene(), (12, 10), Backend::Cpu);
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].position, (6, 3));
+        }
+    }
+
+    #[test]
+    fn response_covers_every_position() {
+        let matcher =
+            TemplateMatcher::new(&cross(), (3, 3), MatchMethod::CrossCorrelation).unwrap();
+        assert_eq!(matcher.response_dimensions((12, 10)), (10, 8));
+        assert_eq!(matcher.response(&scene(), (12, 10), Backend::Cpu).len(), 80);
+    }
+
+    #[test]
+    fn exact_match_has_a_perfect_score() {
+        let pixels = ImageConverter::convert_rgb_to_grayscale(&cross());
+        for (method, perfect) in [
+            (MatchMethod::SquaredDifference, 0.0),
+            (MatchMethod::CrossCorrelation, 1.0),
+            (MatchMethod::ZeroMeanCrossCorrelation, 1.0),
+        ] {
+            let matcher = TemplateMatcher::new(&cross(), (3, 3), method).unwrap();
+            let response = matcher.response(&pixels, (3, 3), Backend::Cpu);
+            assert!((response[0] - perfect).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn threshold_keeps_separate_matches() {
+        let mut pixels = scene();
+        for (x, y) in [(2, 6), (1, 7), (2, 7), (3, 7), (2, 8)] {
+            pixels[y * 12 + x] = 1.0;
+        }
+        let mut matcher =
+            TemplateMatcher::new(&cross(), (3, 3), MatchMethod::ZeroMeanCrossCorrelation).unwrap();
+        matcher.threshold = Some(0.9);
+
+        let mut positions: Vec<(u32, u32)> = matcher
+            .find(&pixels, (12, 10), Backend::Cpu)
+            .iter()
+            .map(|found| found.position)
+            .collect();
+        positions.sort();
+        assert_eq!(positions, vec![(1, 6), (6, 3)]);
+    }
+
+    #[test]
+    fn display_output_is_in_range() {
+        let matcher =
+            TemplateMatcher::new(&cross(), (3, 3), MatchMethod::SquaredDifference).unwrap();
+        let options = matcher.compute_options(&scene(), (12, 10));
+        let output = matcher.process_cpu(&scene(), &options, (12, 10));
+        assert!(output.iter().all(|value| (0.0..=1.0).contains(value)));
+        assert_eq!(
+            output[3 * 10 + 6],
+            output.iter().cloned().fold(0.0, f32::max)
+        );
+    }
+
+    #[test]
+    fn template_must_match_its_dimensions() {
+        let method = MatchMethod::CrossCorrelation;
+        assert!(TemplateMatcher::new(&cross(), (3, 2), method).is_err());
+        assert!(TemplateMatcher::new(&[], (0, 0), method).is_err());
+    }
+
+    #[test]
+    fn larger_template_finds_nothing() {
+        let matcher =
+            TemplateMatcher::new(&cross(), (3, 3), MatchMethod::CrossCorrelation).unwrap();
+        assert_eq!(matcher.response_dimensions((2, 5)), (0, 0));
+        assert!(matcher.find(&[0.5; 10], (2, 5), Backend::Cpu).is_empty());
+    }
+}
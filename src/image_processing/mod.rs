@@ -1,4 +1,23 @@
+pub mod color_matrix;
+pub mod components;
+pub mod compositing;
+pub mod denoising;
+pub mod distance;
+pub mod dithering;
+pub mod error;
 pub mod filters;
+pub mod frequency;
+pub mod geometry;
+pub mod histogram;
+pub mod hough;
 pub mod image_converter;
 pub mod image_processor;
+pub mod keypoints;
+pub mod matching;
+pub mod morphology;
 pub mod opencl_processor;
+pub mod pyramid;
+pub mod quantization;
+pub mod seam_carving;
+pub mod thresholding;
+pub mod tone;
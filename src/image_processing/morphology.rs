@@ -0,0 +1,652 @@
+use super::error::{check_size, FilterError};
+use super::filters::{ColorMode, ImageFilter};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StructuringElement {
+    Rectangle(u32, u32),
+    Cross(u32),
+    Ellipse(u32, u32),
+    // Built with StructuringElement::custom so the mask always matches its size
+    Custom(CustomElement),
+}
+
+// Row-major mask, anchored at its centre
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomElement {
+    width: u32,
+    height: u32,
+    mask: Vec<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MorphologyOperation {
+    Erode,
+    Dilate,
+    Open,
+    Close,
+    Gradient,
+    TopHat,
+    BlackHat,
+}
+
+pub struct Morphology {
+    pub operation: MorphologyOperation,
+    pub element: StructuringElement,
+    pub iterations: u32,
+    // Threshold the input at 0.5 before applying the operation
+    pub binary: bool,
+}
+
+impl StructuringElement {
+    pub fn custom(width: u32, height: u32, mask: Vec<bool>) -> Result<Self, FilterError> {
+        check_size("Structuring element mask", mask.len(), (width, height))?;
+        Ok(StructuringElement::Custom(CustomElement {
+            width,
+            height,
+            mask,
+        }))
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            StructuringElement::Rectangle(width, height) => (*width, *height),
+            StructuringElement::Cross(size) => (*size, *size),
+            StructuringElement::Ellipse(width, height) => (*width, *height),
+            StructuringElement::Custom(element) => (element.width, element.height),
+        }
+    }
+
+    pub fn mask(&self) -> Vec<bool> {
+        let (width, height) = self.dimensions();
+        let (center_x, center_y) = (width / 2, height / 2);
+
+        match self {
+            StructuringElement::Rectangle(..) => vec![true; (width * height) as usize],
+            StructuringElement::Cross(_) => (0..width * height)
+                .map(|i| i % width == center_x || i / width == center_y)
+                .collect(),
+            StructuringElement::Ellipse(..) => {
+                let radius_x = width as f32 / 2.0;
+                let radius_y = height as f32 / 2.0;
+                (0..width * height)
+                    .map(|i| {
+                        let dx = (i % width) as f32 + 0.5 - radius_x;
+                        let dy = (i / width) as f32 + 0.5 - radius_y;
+                        (dx / radius_x).powi(2) + (dy / radius_y).powi(2) <= 1.0
+                    })
+                    .collect()
+            }
+            StructuringElement::Custom(element) => element.mask.clone(),
+        }
+    }
+
+    // Offsets of the active mask cells relative to the anchor
+    pub fn offsets(&self) -> Vec<(i32, i32)> {
+        let (width, height) = self.dimensions();
+        let (center_x, center_y) = ((width / 2) as i32, (height / 2) as i32);
+
+        self.mask()
+            .iter()
+            .enumerate()
+            .filter(|(_, &active)| active)
+            .map(|(i, _)| {
+                let x = (i as u32 % width) as i32;
+                let y = (i as u32 / width) as i32;
+                (x - center_x, y - center_y)
+            })
+            .collect()
+    }
+}
+
+impl Morphology {
+    pub fn new(operation: MorphologyOperation, element: StructuringElement) -> Self {
+        Self {
+            operation,
+            element,
+            iterations: 1,
+            binary: false,
+        }
+    }
+
+    // Applying an element n times is equivalent to applying its n-fold Minkowski sum once
+    fn effective_offsets(&self) -> Vec<(i32, i32)> {
+        let element = self.element.offsets();
+        let mut offsets = element.clone();
+
+        for _ in 1..self.iterations.max(1) {
+            let mut sum: Vec<(i32, i32)> = offsets
+                .iter()
+                .flat_map(|&(x, y)| element.iter().map(move |&(dx, dy)| (x + dx, y + dy)))
+                .collect();
+            sum.sort_unstable();
+            sum.dedup();
+            offsets = sum;
+        }
+        offsets
+    }
+}
+
+impl ImageFilter for Morphology {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float readPixel(__global const float* image, int index, int binary) {
+                float pixel = image[index];
+                return binary ? (pixel >= 0.5f ? 1.0f : 0.0f) : pixel;
+            }
+
+            float erodeAt(
+                __global const float* image,
+                __global const float* options,
+                int x, int y, int width, int height, int binary) {
+
+                int count = (int)options[2];
+                float value = FLT_MAX;
+                for (int i = 0; i < count; i++) {
+                    int nx = x + (int)options[3 + i * 2];
+                    int ny = y + (int)options[4 + i * 2];
+                    if (nx >= 0 && ny >= 0 && nx < width && ny < height)
+                        value = min(value, readPixel(image, ny * width + nx, binary));
+                }
+                return value;
+            }
+
+            float dilateAt(
+                __global const float* image,
+                __global const float* options,
+                int x, int y, int width, int height, int binary) {
+
+                int count = (int)options[2];
+                float value = -FLT_MAX;
+                for (int i = 0; i < count; i++) {
+                    int nx = x - (int)options[3 + i * 2];
+                    int ny = y - (int)options[4 + i * 2];
+                    if (nx >= 0 && ny >= 0 && nx < width && ny < height)
+                        value = max(value, readPixel(image, ny * width + nx, binary));
+                }
+                return value;
+            }
+
+            // Elements without the origin can leave a pixel with no neighbours
+            float morphAt(
+                __global const float* image,
+                __global const float* options,
+                int x, int y, int width, int height, int binary, int dilate) {
+
+                float value = dilate
+                    ? dilateAt(image, options, x, y, width, height, binary)
+                    : erodeAt(image, options, x, y, width, height, binary);
+                return fabs(value) == FLT_MAX ? readPixel(image, y * width + x, binary) : value;
+            }
+
+            // Open, close and the hats run the first operation into the output, combine the second
+            // one with the original pixel in place of the input and copy that back to the output
+            __kernel void morphology(
+                __global float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height,
+                const int pass) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int operation = (int)options[0];
+                int binary = (int)options[1];
+                int idx = y * width + x;
+
+                if (operation == 0 || operation == 1) {
+                    outputImage[idx] = morphAt(inputImage, options, x, y, width, height, binary, operation);
+                } else if (operation == 4) {
+                    outputImage[idx] = morphAt(inputImage, options, x, y, width, height, binary, 1)
+                        - morphAt(inputImage, options, x, y, width, height, binary, 0);
+                } else if (pass == 0) {
+                    // Opening and the top-hat erode first, closing and the black-hat dilate first
+                    int dilateFirst = operation == 3 || operation == 6;
+                    outputImage[idx] = morphAt(inputImage, options, x, y, width, height, binary, dilateFirst);
+                } else if (pass == 1) {
+                    int dilateSecond = operation == 2 || operation == 5;
+                    float value = morphAt(outputImage, options, x, y, width, height, binary, dilateSecond);
+                    float pixel = readPixel(inputImage, idx, binary);
+                    if (operation == 5)
+                        value = pixel - value;
+                    else if (operation == 6)
+                        value = value - pixel;
+                    inputImage[idx] = value;
+                } else {
+                    outputImage[idx] = inputImage[idx];
+                }
+            }
+            "#,
+            "morphology",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let offsets = self.effective_offsets();
+
+        let mut options = vec![
+            self.operation as i32 as f32,
+            self.binary as i32 as f32,
+            offsets.len() as f32,
+        ];
+        for (x, y) in offsets {
+            options.push(x as f32);
+            options.push(y as f32);
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Rgb
+    }
+
+    fn get_schedule(&self, dimensions: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        let passes = match self.operation {
+            MorphologyOperation::Erode
+            | MorphologyOperation::Dilate
+            | MorphologyOperation::Gradient => 1,
+            _ => 3,
+        };
+        Some((passes, dimensions))
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let count = options[2] as usize;
+        let offsets: Vec<(i32, i32)> = (0..count)
+            .map(|i| (options[3 + i * 2] as i32, options[4 + i * 2] as i32))
+            .collect();
+
+        let pixels: Vec<f32> = if self.binary {
+            pixels
+                .iter()
+                .map(|&pixel| if pixel >= 0.5 { 1.0 } else { 0.0 })
+                .collect()
+        } else {
+            pixels.to_vec()
+        };
+
+        let erode = |image: &[f32]| morph(image, &offsets, dimensions, 1, f32::min, f32::MAX);
+        let dilate = |image: &[f32]| morph(image, &offsets, dimensions, -1, f32::max, f32::MIN);
+        let subtract = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a - b).collect();
+
+        match self.operation {
+            MorphologyOperation::Erode => erode(&pixels),
+            MorphologyOperation::Dilate => dilate(&pixels),
+            MorphologyOperation::Open => dilate(&erode(&pixels)),
+            MorphologyOperation::Close => erode(&dilate(&pixels)),
+            MorphologyOperation::Gradient => subtract(&dilate(&pixels), &erode(&pixels)),
+            MorphologyOperation::TopHat => subtract(&pixels, &dilate(&erode(&pixels))),
+            MorphologyOperation::BlackHat => subtract(&erode(&dilate(&pixels)), &pixels),
+        }
+    }
+}
+
+fn morph(
+    pixels: &[f32],
+    offsets: &[(i32, i32)],
+    dimensions: (u32, u32),
+    direction: i32,
+    combine: fn(f32, f32) -> f32,
+    initial: f32,
+) -> Vec<f32> {
+    let (width, height) = (dimensions.0 as i32, dimensions.1 as i32);
+
+    let mut output = vec![0.0; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut value = initial;
+            for &(dx, dy) in offsets {
+                let (nx, ny) = (x + direction * dx, y + direction * dy);
+                if nx >= 0 && ny >= 0 && nx < width && ny < height {
+                    value = combine(value, pixels[(ny * width + nx) as usize]);
+                }
+            }
+
+            let index = (y * width + x) as usize;
+            output[index] = if value == initial {
+                pixels[index]
+            } else {
+                value
+            };
+        }
+    }
+    output
+}
+
+// Zhang-Suen thinning of the thresholded image down to one pixel wide centrelines
+pub struct Thinning {
+    // None keeps going until nothing changes
+    pub max_iterations: Option<u32>,
+}
+
+impl Thinning {
+    pub fn new() -> Self {
+        Self {
+            max_iterations: None,
+        }
+    }
+
+    // Each iteration peels at most one pixel from every side, so thicker shapes cannot exist
+    fn iterations(&self, dimensions: (u32, u32)) -> u32 {
+        let bound = dimensions.0.min(dimensions.1) / 2 + 1;
+        self.max_iterations.map_or(bound, |limit| limit.min(bound))
+    }
+}
+
+impl Default for Thinning {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImageFilter for Thinning {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            int pixelAt(__global const float* image, int x, int y, int width, int height) {
+                return x >= 0 && y >= 0 && x < width && y < height && image[y * width + x] >= 0.5f;
+            }
+
+            // Deletes removable boundary pixels for one of the two sub-iterations
+            float thinPixel(__global const float* image, int x, int y, int width, int height, int second) {
+                if (!pixelAt(image, x, y, width, height))
+                    return 0.0f;
+
+                // Clockwise from the north
+                int p[8] = {
+                    pixelAt(image, x, y - 1, width, height),
+                    pixelAt(image, x + 1, y - 1, width, height),
+                    pixelAt(image, x + 1, y, width, height),
+                    pixelAt(image, x + 1, y + 1, width, height),
+                    pixelAt(image, x, y + 1, width, height),
+                    pixelAt(image, x - 1, y + 1, width, height),
+                    pixelAt(image, x - 1, y, width, height),
+                    pixelAt(image, x - 1, y - 1, width, height)
+                };
+
+                int neighbours = 0, transitions = 0;
+                for (int i = 0; i < 8; i++) {
+                    neighbours += p[i];
+                    transitions += p[i] < p[(i + 1) % 8];
+                }
+
+                int corners = second
+                    ? p[0] * p[2] * p[6] == 0 && p[0] * p[4] * p[6] == 0
+                    : p[0] * p[2] * p[4] == 0 && p[2] * p[4] * p[6] == 0;
+                int removable = neighbours >= 2 && neighbours <= 6 && transitions == 1 && corners;
+                return removable ? 0.0f : 1.0f;
+            }
+
+            // Sub-iterations ping-pong between the two buffers and end in the output
+            __kernel void thinning(
+                __global float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height,
+                const int pass) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                if (pass == 0)
+                    outputImage[idx] = inputImage[idx] >= 0.5f ? 1.0f : 0.0f;
+                else if (pass & 1)
+                    inputImage[idx] = thinPixel(outputImage, x, y, width, height, 0);
+                else
+                    outputImage[idx] = thinPixel(inputImage, x, y, width, height, 1);
+            }
+            "#,
+            "thinning",
+        ))
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Grayscale
+    }
+
+    fn get_schedule(&self, dimensions: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        Some((1 + 2 * self.iterations(dimensions), dimensions))
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as i32, dimensions.1 as i32);
+        let mut image: Vec<bool> = pixels.iter().map(|&pixel| pixel >= 0.5).collect();
+
+        let at = |image: &[bool], x: i32, y: i32| {
+            x >= 0 && y >= 0 && x < width && y < height && image[(y * width + x) as usize]
+        };
+
+        for _ in 0..self.iterations(dimensions) {
+            let mut changed = false;
+            for second in [false, true] {
+                let removed: Vec<usize> = (0..width * height)
+                    .filter(|&i| {
+                        let (x, y) = (i % width, i / width);
+                        if !image[i as usize] {
+                            return false;
+                        }
+
+                        // Clockwise from the north
+                        let p = [
+                            at(&image, x, y - 1),
+                            at(&image, x + 1, y - 1),
+                            at(&image, x + 1, y),
+                            at(&image, x + 1, y + 1),
+                            at(&image, x, y + 1),
+                            at(&image, x - 1, y + 1),
+                            at(&image, x - 1, y),
+                            at(&image, x - 1, y - 1),
+                        ]
+                        .map(u8::from);
+                        let neighbours: u8 = p.iter().sum();
+                        let transitions = (0..8).filter(|&i| p[i] < p[(i + 1) % 8]).count();
+
+                        let corners = if second {
+                            p[0] * p[2] * p[6] == 0 && p[0] * p[4] * p[6] == 0
+                        } else {
+                            p[0] * p[2] * p[4] == 0 && p[2] * p[4] * p[6] == 0
+                        };
+                        (2..=6).contains(&neighbours) && transitions == 1 && corners
+                    })
+                    .map(|i| i as usize)
+                    .collect();
+
+                changed |= !removed.is_empty();
+                for i in removed {
+                    image[i] = false;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        image
+            .iter()
+            .map(|&set| if set { 1.0 } else { 0.0 })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn morphology(operation: MorphologyOperation, pixels: &[f32]) -> Vec<f32> {
+        let filter = Morphology::new(operation, StructuringElement::Rectangle(3, 3));
+        run(&filter, pixels, (7, 7))
+    }
+
+    // A 3x3 block with an isolated pixel in the corner
+    fn block_and_speck() -> Vec<f32> {
+        (0..49)
+            .map(|i| {
+                let (x, y) = (i % 7, i / 7);
+                let block = (2..=4).contains(&x) && (2..=4).contains(&y);
+                if block || (x, y) == (0, 6) {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn erode_and_dilate_a_single_pixel() {
+        let mut pixel = vec![0.0; 49];
+        pixel[24] = 1.0;
+
+        assert!(morphology(MorphologyOperation::Erode, &pixel)
+            .iter()
+            .all(|&value| value == 0.0));
+        let dilated = morphology(MorphologyOperation::Dilate, &pixel);
+        assert_eq!(dilated.iter().sum::<f32>(), 9.0);
+        assert_eq!(dilated[16], 1.0);
+        assert_eq!(dilated[32], 1.0);
+    }
+
+    #[test]
+    fn opening_removes_specks_and_keeps_blocks() {
+        let image = block_and_speck();
+        let opened = morphology(MorphologyOperation::Open, &image);
+
+        assert_eq!(opened[42], 0.0);
+        assert_eq!(opened.iter().sum::<f32>(), 9.0);
+        assert_eq!(morphology(MorphologyOperation::Open, &opened), opened);
+    }
+
+    #[test]
+    fn opening_shrinks_and_closing_grows() {
+        let image: Vec<f32> = (0..49).map(|i| ((i * 37) % 11) as f32 / 10.0).collect();
+        let opened = morphology(MorphologyOperation::Open, &image);
+        let closed = morphology(MorphologyOperation::Close, &image);
+
+        for i in 0..49 {
+            assert!(opened[i] <= image[i] && image[i] <= closed[i]);
+        }
+        assert_eq!(morphology(MorphologyOperation::Close, &closed), closed);
+    }
+
+    #[test]
+    fn hats_are_the_differences_from_open_and_close() {
+        let image = block_and_speck();
+        let top_hat = morphology(MorphologyOperation::TopHat, &image);
+        let black_hat = morphology(MorphologyOperation::BlackHat, &image);
+        let opened = morphology(MorphologyOperation::Open, &image);
+        let closed = morphology(MorphologyOperation::Close, &image);
+
+        for i in 0..49 {
+            assert_eq!(top_hat[i], image[i] - opened[i]);
+            assert_eq!(black_hat[i], closed[i] - image[i]);
+        }
+        assert_eq!(top_hat[42], 1.0);
+    }
+
+    #[test]
+    fn gradient_outlines_the_block() {
+        let gradient = morphology(MorphologyOperation::Gradient, &block_and_speck());
+        // Centre of the block and far background have no edge
+        assert_eq!(gradient[24], 0.0);
+        assert_eq!(gradient[6], 0.0);
+        assert_eq!(gradient[17], 1.0);
+    }
+
+    #[test]
+    fn iterations_match_a_larger_element() {
+        let image = block_and_speck();
+        let twice = Morphology {
+            iterations: 2,
+            ..Morphology::new(
+                MorphologyOperation::Dilate,
+                StructuringElement::Rectangle(3, 3),
+            )
+        };
+        let larger = Morphology::new(
+            MorphologyOperation::Dilate,
+            StructuringElement::Rectangle(5, 5),
+        );
+        assert_eq!(run(&twice, &image, (7, 7)), run(&larger, &image, (7, 7)));
+    }
+
+    #[test]
+    fn element_shapes() {
+        assert_eq!(StructuringElement::Cross(3).offsets().len(), 5);
+        assert_eq!(StructuringElement::Ellipse(5, 5).offsets().len(), 21);
+        assert_eq!(
+            StructuringElement::custom(2, 1, vec![true, false]),
+            Ok(StructuringElement::Custom(CustomElement {
+                width: 2,
+                height: 1,
+                mask: vec![true, false]
+            }))
+        );
+    }
+
+    #[test]
+    fn custom_element_must_match_its_size() {
+        assert_eq!(
+            StructuringElement::custom(3, 3, vec![true; 8]),
+            Err(FilterError::SizeMismatch {
+                name: "Structuring element mask",
+                expected: 9,
+                actual: 8
+            })
+        );
+    }
+
+    #[test]
+    fn thinning_leaves_a_one_pixel_line() {
+        let dimensions = (9, 5);
+        let bar: Vec<f32> = (0..45)
+            .map(|i| {
+                if (1..=3).contains(&(i / 9)) && (1..=7).contains(&(i % 9)) {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let thinned = run(&Thinning::new(), &bar, dimensions);
+
+        assert!(thinned.iter().sum::<f32>() > 0.0);
+        for x in 0..9 {
+            let column: f32 = (0..5).map(|y| thinned[y * 9 + x]).sum();
+            assert!(column <= 1.0);
+        }
+    }
+
+    #[test]
+    fn thinning_keeps_the_hole_of_a_ring() {
+        let dimensions = (9, 9);
+        let ring: Vec<f32> = (0..81)
+            .map(|i| {
+                let (x, y) = (i % 9, i / 9);
+                let outer = (1..=7).contains(&x) && (1..=7).contains(&y);
+                let inner = (3..=5).contains(&x) && (3..=5).contains(&y);
+                (outer && !inner) as i32 as f32
+            })
+            .collect();
+        let thinned = run(&Thinning::new(), &ring, dimensions);
+
+        assert_eq!(thinned[4 * 9 + 4], 0.0);
+        // A closed loop still crosses every row and column through the centre twice
+        assert_eq!((0..9).map(|x| thinned[4 * 9 + x]).sum::<f32>(), 2.0);
+        assert_eq!((0..9).map(|y| thinned[y * 9 + 4]).sum::<f32>(), 2.0);
+    }
+}
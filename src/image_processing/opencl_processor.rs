@@ -1,9 +1,11 @@
-use ocl::ProQue;
+use ocl::{Platform, ProQue};
 
 pub struct OpenCLProcessor<'a, 'b> {
     pixels: &'a [f32],
     options: &'b [f32],
     dimensions: (u32, u32),
+    output_dimensions: (u32, u32),
+    scratch_size: usize,
 }
 
 impl<'a, 'b> OpenCLProcessor<'a, 'b> {
@@ -16,10 +18,42 @@ impl<'a, 'b> OpenCLProcessor<'a, 'b> {
             pixels,
             options,
             dimensions,
+            output_dimensions: dimensions,
+            scratch_size: 0,
         }
     }
 
+    pub fn with_output_dimensions(mut self, output_dimensions: (u32, u32)) -> Self {
+        self.output_dimensions = output_dimensions;
+        self
+    }
+
+    // Device buffer of this many floats kept across the passes of a scheduled kernel, which
+    // takes it as an extra argument after the pass index
+    pub fn with_scratch(mut self, scratch_size: usize) -> Self {
+        self.scratch_size = scratch_size;
+        self
+    }
+
+    pub fn is_available() -> bool {
+        Platform::first().is_ok()
+    }
+
     pub fn process(&self, filter: (&str, &str)) -> Vec<f32> {
+        self.run(filter, None)
+    }
+
+    // Launches the kernel `passes` times, passing the pass index as an extra argument
+    pub fn process_scheduled(
+        &self,
+        filter: (&str, &str),
+        passes: u32,
+        work_size: (u32, u32),
+    ) -> Vec<f32> {
+        self.run(filter, Some((passes, work_size)))
+    }
+
+    fn run(&self, filter: (&str, &str), schedule: Option<(u32, (u32, u32))>) -> Vec<f32> {
         let pro_que = ProQue::builder()
             .src(filter.0)
             .dims(self.dimensions)
@@ -30,12 +64,19 @@ impl<'a, 'b> OpenCLProcessor<'a, 'b> {
             .create_buffer::<f32>()
             .expect("Failed to create input buffer");
 
+        let output_len = (self.output_dimensions.0 * self.output_dimensions.1) as usize;
         let output_buffer = pro_que
-            .create_buffer::<f32>()
+            .buffer_builder::<f32>()
+            .len(output_len.max(1))
+            .fill_val(0.0)
+            .build()
             .expect("Failed to create output buffer");
 
         let options_buffer = pro_que
-            .create_buffer::<f32>()
+            .buffer_builder::<f32>()
+            .len(self.options.len().max(1))
+            .fill_val(0.0)
+            .build()
             .expect("Failed to create options buffer");
 
         input_buffer
@@ -43,29 +84,62 @@ impl<'a, 'b> OpenCLProcessor<'a, 'b> {
             .enq()
             .expect("Failed to write to input buffer");
 
-        if self.options.len() > 0 {
+        if !self.options.is_empty() {
             options_buffer
                 .write(self.options)
                 .enq()
                 .expect("Failed to write to options buffer");
         }
 
-        let kernel = pro_que
-            .kernel_builder(filter.1)
+        let scratch_buffer = if self.scratch_size > 0 {
+            Some(
+                pro_que
+                    .buffer_builder::<f32>()
+                    .len(self.scratch_size)
+                    .build()
+                    .expect("Failed to create scratch buffer"),
+            )
+        } else {
+            None
+        };
+
+        let mut builder = pro_que.kernel_builder(filter.1);
+        builder
             .arg(&input_buffer)
             .arg(&output_buffer)
             .arg(&options_buffer)
-            .arg(&(self.dimensions.0 as i32))
-            .arg(&(self.dimensions.1 as i32))
-            .build()
-            .expect("Failed to create kernel");
+            .arg(self.dimensions.0 as i32)
+            .arg(self.dimensions.1 as i32);
+        if schedule.is_some() {
+            builder.arg(0i32);
+        }
+        if let Some(buffer) = &scratch_buffer {
+            builder.arg(buffer);
+        }
+        builder.global_work_size(self.output_dimensions);
+        let kernel = builder.build().expect("Failed to create kernel");
 
-        unsafe {
-            // Execute the Sobel kernel
-            kernel.enq().expect("Failed to enqueue kernel");
+        match schedule {
+            Some((passes, work_size)) => {
+                for pass in 0..passes {
+                    kernel
+                        .set_arg(5, pass as i32)
+                        .expect("Failed to set pass argument");
+                    unsafe {
+                        kernel
+                            .cmd()
+                            .global_work_size(work_size)
+                            .enq()
+                            .expect("Failed to enqueue kernel");
+                    }
+                }
+            }
+            None => unsafe {
+                kernel.enq().expect("Failed to enqueue kernel");
+            },
         }
 
-        let mut output_pixels = vec![0.0f32; self.pixels.len()];
+        let mut output_pixels = vec![0.0f32; output_len];
         output_buffer
             .read(&mut output_pixels)
             .enq()
@@ -0,0 +1,323 @@
+use super::error::{check_size, FilterError};
+use super::filters::{ColorMode, GaussianBlur, ImageFilter};
+use super::image_converter::ImageConverter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PyramidKind {
+    Gaussian,
+    // Band-pass differences between Gaussian levels, ending with the coarsest Gaussian level
+    Laplacian,
+}
+
+pub struct Pyramid {
+    pub kind: PyramidKind,
+    // Finest first, each level half the size of the one before
+    pub levels: Vec<(Vec<f32>, (u32, u32))>,
+}
+
+// Shows one level of the pyramid at its own size, Laplacian levels offset to mid grey
+pub struct PyramidLevel {
+    pub kind: PyramidKind,
+    pub level: u32,
+}
+
+// Blends the overlay into the input band by band, so seams are as wide as each band's detail
+pub struct MultiBandBlend {
+    // Checked against the dimensions in `new`, images of any other size pass through unchanged
+    overlay: Vec<u32>,
+    // Weight of the overlay at each pixel, 0 to 1
+    mask: Vec<f32>,
+    dimensions: (u32, u32),
+    pub levels: u32,
+}
+
+fn blur(pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+    let options = GaussianBlur.compute_options(pixels, dimensions);
+    GaussianBlur.process_cpu(pixels, &options, dimensions)
+}
+
+// Blurs and keeps every other pixel, rounding odd sizes up
+pub fn downsample(pixels: &[f32], dimensions: (u32, u32)) -> (Vec<f32>, (u32, u32)) {
+    let width = dimensions.0 as usize;
+    let blurred = blur(pixels, dimensions);
+    let smaller = (dimensions.0.div_ceil(2), dimensions.1.div_ceil(2));
+
+    let output = (0..smaller.0 as usize * smaller.1 as usize)
+        .map(|i| {
+            let (x, y) = (i % smaller.0 as usize, i / smaller.0 as usize);
+            blurred[2 * y * width + 2 * x]
+        })
+        .collect();
+    (output, smaller)
+}
+
+// Repeats every pixel over a 2x2 block of the larger size and blurs the blocks away
+pub fn upsample(pixels: &[f32], dimensions: (u32, u32), larger: (u32, u32)) -> Vec<f32> {
+    let width = dimensions.0 as usize;
+    let repeated: Vec<f32> = (0..larger.0 as usize * larger.1 as usize)
+        .map(|i| {
+            let (x, y) = (i % larger.0 as usize, i / larger.0 as usize);
+            pixels[y / 2 * width + x / 2]
+        })
+        .collect();
+    blur(&repeated, larger)
+}
+
+impl Pyramid {
+    // Stops early once a level is a single pixel
+    pub fn gaussian(pixels: &[f32], dimensions: (u32, u32), levels: u32) -> Self {
+        let mut pyramid = vec![(pixels.to_vec(), dimensions)];
+        while pyramid.len() < levels.max(1) as usize {
+            let (image, dimensions) = pyramid.last().expect("Pyramid has no levels");
+            if dimensions.0 <= 1 && dimensions.1 <= 1 {
+                break;
+            }
+            pyramid.push(downsample(image, *dimensions));
+        }
+
+        Self {
+            kind: PyramidKind::Gaussian,
+            levels: pyramid,
+        }
+    }
+
+    pub fn laplacian(pixels: &[f32], dimensions: (u32, u32), levels: u32) -> Self {
+        let gaussian = Self::gaussian(pixels, dimensions, levels).levels;
+
+        let mut pyramid: Vec<(Vec<f32>, (u32, u32))> = gaussian
+            .windows(2)
+            .map(|pair| {
+                let ((fine, dimensions), (coarse, coarse_dimensions)) = (&pair[0], &pair[1]);
+                let expanded = upsample(coarse, *coarse_dimensions, *dimensions);
+                let detail = fine.iter().zip(&expanded).map(|(a, b)| a - b).collect();
+                (detail, *dimensions)
+            })
+            .collect();
+        pyramid.push(gaussian.last().expect("Pyramid has no levels").clone());
+
+        Self {
+            kind: PyramidKind::Laplacian,
+            levels: pyramid,
+        }
+    }
+
+    // Collapses a Laplacian pyramid back into the image, a Gaussian one is its first level
+    pub fn reconstruct(&self) -> Vec<f32> {
+        match self.kind {
+            PyramidKind::Gaussian => self.levels[0].0.clone(),
+            PyramidKind::Laplacian => {
+                let (coarsest, dimensions) = self.levels.last().expect("Pyramid has no levels");
+                let (image, _) = self.levels.iter().rev().skip(1).fold(
+                    (coarsest.clone(), *dimensions),
+                    |(image, dimensions), (detail, larger)| {
+                        let expanded = upsample(&image, dimensions, *larger);
+                        let sum = expanded.iter().zip(detail).map(|(a, b)| a + b).collect();
+                        (sum, *larger)
+                    },
+                );
+                image
+            }
+        }
+    }
+}
+
+// Multi-band blending of two single channel images, the mask weighting the second one
+pub fn blend(
+    base: &[f32],
+    overlay: &[f32],
+    mask: &[f32],
+    dimensions: (u32, u32),
+    levels: u32,
+) -> Vec<f32> {
+    let base = Pyramid::laplacian(base, dimensions, levels);
+    let overlay = Pyramid::laplacian(overlay, dimensions, levels);
+    let mask = Pyramid::gaussian(mask, dimensions, levels);
+
+    let levels = base
+        .levels
+        .iter()
+        .zip(&overlay.levels)
+        .zip(&mask.levels)
+        .map(|(((base, dimensions), (overlay, _)), (mask, _))| {
+            let level = base
+                .iter()
+                .zip(overlay)
+                .zip(mask)
+                .map(|((base, overlay), weight)| base + (overlay - base) * weight)
+                .collect();
+            (level, *dimensions)
+        })
+        .collect();
+
+    Pyramid {
+        kind: PyramidKind::Laplacian,
+        levels,
+    }
+    .reconstruct()
+}
+
+// Runs an operation on each colour channel of packed pixels and packs the results again
+fn per_channel(pixels: &[f32], operation: impl Fn(usize, &[f32]) -> Vec<f32>) -> Vec<f32> {
+    let (r, g, b) = ImageConverter::decompose_rgb(&ImageConverter::convert_packed_to_rgb(pixels));
+    let (r, g, b) = (operation(0, &r), operation(1, &g), operation(2, &b));
+
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+    (0..r.len())
+        .map(|i| ((to_byte(r[i]) << 16) | (to_byte(g[i]) << 8) | to_byte(b[i])) as f32)
+        .collect()
+}
+
+impl PyramidLevel {
+    pub fn new(kind: PyramidKind, level: u32) -> Self {
+        Self { kind, level }
+    }
+}
+
+// Pyramids are only built on the CPU, so neither stage has a kernel
+impl ImageFilter for PyramidLevel {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn output_dimensions(&self, dimensions: (u32, u32)) -> (u32, u32) {
+        (0..self.level).fold(dimensions, |(width, height), _| {
+            if width <= 1 && height <= 1 {
+                (width, height)
+            } else {
+                (width.div_ceil(2), height.div_ceil(2))
+            }
+        })
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        per_channel(pixels, |_, channel| {
+            // One level more so that a Laplacian level is a band rather than the residual
+            let pyramid = match self.kind {
+                PyramidKind::Gaussian => Pyramid::gaussian(channel, dimensions, self.level + 1),
+                PyramidKind::Laplacian => Pyramid::laplacian(channel, dimensions, self.level + 2),
+            };
+            let index = (self.level as usize).min(pyramid.levels.len() - 1);
+            let level = &pyramid.levels[index].0;
+
+            let offset = if index + 1 < pyramid.levels.len() && self.kind == PyramidKind::Laplacian
+            {
+                0.5
+            } else {
+                0.0
+            };
+            level.iter().map(|value| value + offset).collect()
+        })
+    }
+}
+
+impl MultiBandBlend {
+    pub fn new(
+        overlay: Vec<u32>,
+        mask: Vec<f32>,
+        dimensions: (u32, u32),
+    ) -> Result<Self, FilterError> {
+        check_size("Overlay", overlay.len(), dimensions)?;
+        check_size("Mask", mask.len(), dimensions)?;
+        Ok(Self {
+            overlay,
+            mask,
+            dimensions,
+            levels: 5,
+        })
+    }
+}
+
+impl ImageFilter for MultiBandBlend {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        if dimensions != self.dimensions {
+            return pixels.to_vec();
+        }
+
+        let (r, g, b) = ImageConverter::decompose_rgb(&self.overlay);
+        let overlay = [r, g, b];
+        per_channel(pixels, |channel, base| {
+            blend(base, &overlay[channel], &self.mask, dimensions, self.levels)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    fn ramp(count: usize) -> Vec<f32> {
+        (0..count).map(|i| ((i * 37) % 11) as f32 / 10.0).collect()
+    }
+
+    #[test]
+    fn gaussian_levels_halve_rounding_up() {
+        let pyramid = Pyramid::gaussian(&ramp(35), (7, 5), 10);
+        let sizes: Vec<(u32, u32)> = pyramid.levels.iter().map(|level| level.1).collect();
+        assert_eq!(sizes, vec![(7, 5), (4, 3), (2, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn laplacian_pyramid_reconstructs_the_image() {
+        let pixels = ramp(63);
+        let pyramid = Pyramid::laplacian(&pixels, (9, 7), 4);
+        assert_eq!(pyramid.levels.len(), 4);
+        for (a, b) in pyramid.reconstruct().iter().zip(&pixels) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn level_output_matches_its_dimensions() {
+        let pixels: Vec<f32> = ramp(35).iter().map(|v| (v * 255.0) as u32 as f32).collect();
+        for kind in [PyramidKind::Gaussian, PyramidKind::Laplacian] {
+            let filter = PyramidLevel::new(kind, 1);
+            assert_eq!(filter.output_dimensions((7, 5)), (4, 3));
+            assert_eq!(run(&filter, &pixels, (7, 5)).len(), 12);
+        }
+    }
+
+    #[test]
+    fn blend_follows_a_uniform_mask() {
+        let (base, overlay) = (ramp(30), vec![0.25; 30]);
+        for (weight, expected) in [(0.0, &base), (1.0, &overlay)] {
+            let blended = blend(&base, &overlay, &[weight; 30], (6, 5), 3);
+            for (a, b) in blended.iter().zip(expected) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn blend_inputs_must_match_the_image() {
+        assert!(MultiBandBlend::new(vec![0; 12], vec![0.0; 12], (4, 3)).is_ok());
+        assert!(MultiBandBlend::new(vec![0; 11], vec![0.0; 12], (4, 3)).is_err());
+        assert!(MultiBandBlend::new(vec![0; 12], vec![0.0; 6], (4, 3)).is_err());
+    }
+
+    #[test]
+    fn full_mask_shows_the_overlay() {
+        let filter = MultiBandBlend::new(vec![0xFF336699; 12], vec![1.0; 12], (4, 3)).unwrap();
+        let output = run(&filter, &[0x102030 as f32; 12], (4, 3));
+        assert!(output.iter().all(|&value| value == 0x336699 as f32));
+
+        // Any other size is left alone
+        assert_eq!(run(&filter, &[1.0; 6], (3, 2)), vec![1.0; 6]);
+    }
+}
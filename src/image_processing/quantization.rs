@@ -0,0 +1,619 @@
+use std::collections::HashMap;
+
+use super::dithering::{
+    color_distance, unpack_color, DiffusionMatrix, ErrorDiffusionDithering, OrderedDithering,
+    ThresholdMap,
+};
+use super::filters::{ColorMode, ImageFilter};
+use super::image_converter::ImageConverter;
+
+// Leaves average everything below them, so six levels keep the tree small at no cost in accuracy
+const OCTREE_DEPTH: usize = 6;
+const KMEANS_ITERATIONS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaletteMethod {
+    MedianCut,
+    Octree,
+    // Refines a median cut palette with Lloyd iterations
+    KMeans,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuantizationDither {
+    None,
+    Ordered(ThresholdMap),
+    ErrorDiffusion(DiffusionMatrix),
+}
+
+// 0xRRGGBB colours
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<u32>,
+}
+
+pub struct ColorQuantization {
+    pub method: PaletteMethod,
+    pub colors: u32,
+    pub dither: QuantizationDither,
+}
+
+#[derive(Clone, Copy, Default)]
+struct OctreeNode {
+    // Zero marks a missing child, the root is never a child
+    children: [usize; 8],
+    sum: [f64; 3],
+    count: f64,
+    leaf: bool,
+}
+
+impl Palette {
+    pub fn extract(pixels: &[u32], colors: u32, method: PaletteMethod) -> Self {
+        let colors = colors.max(1) as usize;
+
+        let mut counts: HashMap<u32, f32> = HashMap::new();
+        for &pixel in pixels {
+            *counts.entry(pixel & 0xFFFFFF).or_insert(0.0) += 1.0;
+        }
+        let (unique, weights): (Vec<u32>, Vec<f32>) = counts.into_iter().unzip();
+        let (r, g, b) = ImageConverter::decompose_rgb(&unique);
+        let samples: Vec<([f32; 3], f32)> = (0..unique.len())
+            .map(|i| ([r[i], g[i], b[i]], weights[i]))
+            .collect();
+
+        let centres = match method {
+            PaletteMethod::MedianCut => median_cut(&samples, colors),
+            PaletteMethod::Octree => octree(&samples, colors),
+            PaletteMethod::KMeans => kmeans(&samples, median_cut(&samples, colors)),
+        };
+
+        let mut colors: Vec<u32> = centres.into_iter().map(pack_color).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        Self { colors }
+    }
+
+    // Grid of square swatches, one per colour, as opaque RGB pixels
+    pub fn swatch(&self, swatch_size: u32, columns: u32) -> (Vec<u32>, (u32, u32)) {
+        let swatch_size = swatch_size.max(1);
+        let columns = columns.clamp(1, self.colors.len().max(1) as u32);
+        let rows = (self.colors.len() as u32).div_ceil(columns).max(1);
+        let (width, height) = (columns * swatch_size, rows * swatch_size);
+
+        let packed: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let column = (i % width) / swatch_size;
+                let row = (i / width) / swatch_size;
+                self.colors
+                    .get((row * columns + column) as usize)
+                    .map_or(0.0, |&color| color as f32)
+            })
+            .collect();
+
+        (
+            ImageConverter::convert_packed_to_rgb(&packed),
+            (width, height),
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .colors
+            .iter()
+            .map(|&color| {
+                format!(
+                    "{{\"hex\": \"#{:06x}\", \"r\": {}, \"g\": {}, \"b\": {}}}",
+                    color,
+                    (color >> 16) & 0xFF,
+                    (color >> 8) & 0xFF,
+                    color & 0xFF
+                )
+            })
+            .collect();
+        format!("[\n  {}\n]", entries.join(",\n  "))
+    }
+}
+
+impl ColorQuantization {
+    pub fn new(method: PaletteMethod, colors: u32) -> Self {
+        Self {
+            method,
+            colors,
+            dither: QuantizationDither::None,
+        }
+    }
+}
+
+impl ImageFilter for ColorQuantization {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        match self.dither {
+            QuantizationDither::None => Some((QUANTIZATION_KERNELS, "quantize")),
+            QuantizationDither::Ordered(map) => OrderedDithering::new(map).get_kernel(),
+            QuantizationDither::ErrorDiffusion(_) => {
+                Some((QUANTIZATION_KERNELS, "quantizeDiffusion"))
+            }
+        }
+    }
+
+    fn compute_options(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let rgb = ImageConverter::convert_packed_to_rgb(pixels);
+        let palette = Palette::extract(&rgb, self.colors, self.method).colors;
+
+        if let QuantizationDither::Ordered(map) = self.dither {
+            let dithering = OrderedDithering {
+                palette: Some(palette),
+                ..OrderedDithering::new(map)
+            };
+            return dithering.compute_options(pixels, dimensions);
+        }
+
+        let mut options = vec![palette.len() as f32];
+        options.extend(palette.iter().map(|&color| color as f32));
+        if let QuantizationDither::ErrorDiffusion(matrix) = self.dither {
+            let entries = matrix.entries();
+            options.extend([
+                ErrorDiffusionDithering::new(matrix).wavefront_step() as f32,
+                entries.len() as f32,
+            ]);
+            for (dx, dy, weight) in entries {
+                options.extend([dx as f32, dy as f32, weight]);
+            }
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn get_schedule(&self, dimensions: (u32, u32)) -> Option<(u32, (u32, u32))> {
+        match self.dither {
+            QuantizationDither::ErrorDiffusion(matrix) => {
+                ErrorDiffusionDithering::new(matrix).get_schedule(dimensions)
+            }
+            _ => None,
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        if let QuantizationDither::Ordered(map) = self.dither {
+            return OrderedDithering::new(map).process_cpu(pixels, options, dimensions);
+        }
+
+        let count = options[0] as usize;
+        let palette: Vec<[f32; 3]> = options[1..1 + count]
+            .iter()
+            .map(|&color| unpack_color(color))
+            .collect();
+        let nearest = |color: [f32; 3]| {
+            (0..count)
+                .min_by(|&a, &b| {
+                    color_distance(color, palette[a]).total_cmp(&color_distance(color, palette[b]))
+                })
+                .unwrap_or(0)
+        };
+
+        if self.dither == QuantizationDither::None {
+            return pixels
+                .iter()
+                .map(|&pixel| options[1 + nearest(unpack_color(pixel))])
+                .collect();
+        }
+
+        // Same pull-based scheme and error encoding as the kernel, so both backends agree
+        let (width, height) = (dimensions.0 as i32, dimensions.1 as i32);
+        let entries = &options[3 + count..];
+        let mut image = pixels.to_vec();
+        let mut output = vec![0.0; pixels.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let idx = (y * width + x) as usize;
+                let mut value = unpack_color(image[idx]);
+                for entry in entries.chunks(3) {
+                    let (sx, sy) = (x - entry[0] as i32, y - entry[1] as i32);
+                    if sx >= 0 && sy >= 0 && sx < width && sy < height {
+                        let error = decode_error(image[(sy * width + sx) as usize]);
+                        for channel in 0..3 {
+                            value[channel] += entry[2] * error[channel];
+                        }
+                    }
+                }
+
+                let best = nearest(value);
+                output[idx] = options[1 + best];
+                image[idx] = encode_error([
+                    value[0] - palette[best][0],
+                    value[1] - palette[best][1],
+                    value[2] - palette[best][2],
+                ]);
+            }
+        }
+        output
+    }
+}
+
+const QUANTIZATION_KERNELS: &str = r#"
+    float3 unpackColor(float packed) {
+        uint pixel = (uint)packed;
+        return (float3)(
+            (float)((pixel >> 16) & 0xFF),
+            (float)((pixel >> 8) & 0xFF),
+            (float)(pixel & 0xFF)) / 255.0f;
+    }
+
+    int nearestColor(float3 color, __global const float* palette, int count) {
+        int best = 0;
+        float bestDistance = FLT_MAX;
+        for (int i = 0; i < count; i++) {
+            float3 difference = color - unpackColor(palette[i]);
+            float distance = dot(difference, difference);
+            if (distance < bestDistance) {
+                bestDistance = distance;
+                best = i;
+            }
+        }
+        return best;
+    }
+
+    // Residual error clamped to about +-0.5 and stored with 8 bits per channel in one float,
+    // the clamp also keeps colours from bleeding far with sparse palettes
+    float encodeError(float3 error) {
+        uint3 bytes = convert_uint3(clamp(round(error * 255.0f) + 128.0f, 0.0f, 255.0f));
+        return (float)((bytes.x << 16) | (bytes.y << 8) | bytes.z);
+    }
+
+    float3 decodeError(float encoded) {
+        return unpackColor(encoded) - 128.0f / 255.0f;
+    }
+
+    __kernel void quantize(
+        __global const float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height) {
+
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        if (x >= width || y >= height)
+            return;
+
+        int idx = y * width + x;
+        int count = (int)options[0];
+        outputImage[idx] = options[1 + nearestColor(unpackColor(inputImage[idx]), options + 1, count)];
+    }
+
+    // Wavefront scheduled like errorDiffusion, processed pixels hold their encoded error
+    __kernel void quantizeDiffusion(
+        __global float* inputImage,
+        __global float* outputImage,
+        __global const float* options,
+        const int width,
+        const int height,
+        const int pass) {
+
+        int count = (int)options[0];
+        __global const float* palette = options + 1;
+        int step = (int)options[1 + count];
+        int entries = (int)options[2 + count];
+        __global const float* matrix = options + 3 + count;
+
+        int y = get_global_id(0);
+        int x = pass - step * y;
+        if (y >= height || x < 0 || x >= width)
+            return;
+
+        int idx = y * width + x;
+        float3 value = unpackColor(inputImage[idx]);
+        for (int i = 0; i < entries; i++) {
+            int sx = x - (int)matrix[i * 3];
+            int sy = y - (int)matrix[i * 3 + 1];
+            if (sx >= 0 && sy >= 0 && sx < width && sy < height)
+                value += matrix[i * 3 + 2] * decodeError(inputImage[sy * width + sx]);
+        }
+
+        int best = nearestColor(value, palette, count);
+        outputImage[idx] = palette[best];
+        inputImage[idx] = encodeError(value - unpackColor(palette[best]));
+    }
+"#;
+
+fn pack_color(color: [f32; 3]) -> u32 {
+    color.iter().fold(0, |packed, &channel| {
+        (packed << 8) | (channel.clamp(0.0, 1.0) * 255.0).round() as u32
+    })
+}
+
+fn encode_error(error: [f32; 3]) -> f32 {
+    pack_color(error.map(|channel| channel + 128.0 / 255.0)) as f32
+}
+
+fn decode_error(encoded: f32) -> [f32; 3] {
+    unpack_color(encoded).map(|channel| channel - 128.0 / 255.0)
+}
+
+fn weighted_mean(samples: &[([f32; 3], f32)]) -> [f32; 3] {
+    let total: f32 = samples.iter().map(|(_, weight)| weight).sum();
+    let mut mean = [0.0; 3];
+    for (color, weight) in samples {
+        for channel in 0..3 {
+            mean[channel] += color[channel] * weight / total;
+        }
+    }
+    mean
+}
+
+// Repeatedly splits the box with the widest channel range at its weighted median
+fn median_cut(samples: &[([f32; 3], f32)], colors: usize) -> Vec<[f32; 3]> {
+    let widest = |bucket: &[([f32; 3], f32)]| {
+        (0..3)
+            .map(|channel| {
+                let values = bucket.iter().map(|(color, _)| color[channel]);
+                let range =
+                    values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min);
+                (channel, range)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0))
+    };
+
+    let mut buckets = vec![samples.to_vec()];
+    while buckets.len() < colors {
+        let Some(index) = (0..buckets.len())
+            .filter(|&i| buckets[i].len() > 1)
+            .max_by(|&a, &b| widest(&buckets[a]).1.total_cmp(&widest(&buckets[b]).1))
+        else {
+            break;
+        };
+
+        let mut bucket = buckets.swap_remove(index);
+        let (channel, _) = widest(&bucket);
+        bucket.sort_by(|a, b| a.0[channel].total_cmp(&b.0[channel]));
+
+        let half: f32 = bucket.iter().map(|(_, weight)| weight).sum::<f32>() / 2.0;
+        let mut cumulative = 0.0;
+        let split = bucket
+            .iter()
+            .position(|(_, weight)| {
+                cumulative += weight;
+                cumulative >= half
+            })
+            .unwrap_or(0)
+            .clamp(0, bucket.len() - 2)
+            + 1;
+
+        let upper = bucket.split_off(split);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+
+    buckets
+        .iter()
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| weighted_mean(bucket))
+        .collect()
+}
+
+// Builds the full colour tree and folds the least populated deepest nodes into their parents
+fn octree(samples: &[([f32; 3], f32)], colors: usize) -> Vec<[f32; 3]> {
+    let mut nodes = vec![OctreeNode::default()];
+    let mut leaves = 0;
+
+    for &(color, weight) in samples {
+        let bytes = pack_color(color);
+        let mut node = 0;
+        for level in 0..=OCTREE_DEPTH {
+            let current = &mut nodes[node];
+            for (sum, channel) in current.sum.iter_mut().zip(color) {
+                *sum += (channel * weight) as f64;
+            }
+            current.count += weight as f64;
+
+            if level == OCTREE_DEPTH {
+                if !current.leaf {
+                    current.leaf = true;
+                    leaves += 1;
+                }
+                break;
+            }
+
+            let shift = 7 - level;
+            let child = (((bytes >> (16 + shift)) & 1) << 2
+                | ((bytes >> (8 + shift)) & 1) << 1
+                | ((bytes >> shift) & 1)) as usize;
+            if nodes[node].children[child] == 0 {
+                nodes.push(OctreeNode::default());
+                nodes[node].children[child] = nodes.len() - 1;
+            }
+            node = nodes[node].children[child];
+        }
+    }
+
+    // Inner nodes by depth, the least populated last so they are folded first
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); OCTREE_DEPTH];
+    let mut stack = vec![(0, 0)];
+    while let Some((node, level)) = stack.pop() {
+        if nodes[node].leaf {
+            continue;
+        }
+        levels[level].push(node);
+        stack.extend(
+            nodes[node]
+                .children
+                .iter()
+                .filter(|&&c| c != 0)
+                .map(|&c| (c, level + 1)),
+        );
+    }
+    for level in levels.iter_mut() {
+        level.sort_by(|&a, &b| nodes[b].count.total_cmp(&nodes[a].count));
+    }
+    while leaves > colors {
+        let Some(node) = levels.iter_mut().rev().find_map(|level| level.pop()) else {
+            break;
+        };
+
+        let children = nodes[node].children.iter().filter(|&&c| c != 0).count();
+        nodes[node].children = [0; 8];
+        nodes[node].leaf = true;
+        leaves = leaves + 1 - children;
+    }
+
+    let mut palette = Vec::new();
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.leaf {
+            palette.push(node.sum.map(|sum| (sum / node.count) as f32));
+        } else {
+            stack.extend(node.children.iter().filter(|&&c| c != 0));
+        }
+    }
+    palette
+}
+
+// Lloyd iterations from the given centres, empty clusters keep their previous centre
+fn kmeans(samples: &[([f32; 3], f32)], mut centres: Vec<[f32; 3]>) -> Vec<[f32; 3]> {
+    let mut assignments = vec![usize::MAX; samples.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (assignment, (color, _)) in assignments.iter_mut().zip(samples) {
+            let nearest = (0..centres.len())
+                .min_by(|&a, &b| {
+                    color_distance(*color, centres[a])
+                        .total_cmp(&color_distance(*color, centres[b]))
+                })
+                .unwrap_or(0);
+            changed |= *assignment != nearest;
+            *assignment = nearest;
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![([0.0f64; 3], 0.0f64); centres.len()];
+        for ((color, weight), &assignment) in samples.iter().zip(&assignments) {
+            let (sum, total) = &mut sums[assignment];
+            for channel in 0..3 {
+                sum[channel] += (color[channel] * weight) as f64;
+            }
+            *total += *weight as f64;
+        }
+        for (centre, (sum, total)) in centres.iter_mut().zip(sums) {
+            if total > 0.0 {
+                *centre = sum.map(|sum| (sum / total) as f32);
+            }
+        }
+    }
+    centres
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [u32; 4] = [0x000000, 0x2040C0, 0xC04020, 0xFFFFFF];
+
+    // Each of the colours repeated with slightly perturbed copies around it
+    fn clustered() -> Vec<u32> {
+        (0..64)
+            .map(|i| {
+                let color = COLORS[i % 4];
+                let jitter = (i / 4 % 3) as u32;
+                if color == 0xFFFFFF {
+                    color - jitter
+                } else {
+                    color + jitter
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn palette_keeps_the_colours_of_small_images() {
+        let pixels: Vec<u32> = (0..16).map(|i| COLORS[i % 4]).collect();
+        for method in [
+            PaletteMethod::MedianCut,
+            PaletteMethod::Octree,
+            PaletteMethod::KMeans,
+        ] {
+            assert_eq!(Palette::extract(&pixels, 4, method).colors, COLORS.to_vec());
+        }
+    }
+
+    #[test]
+    fn palette_finds_the_clusters() {
+        for method in [
+            PaletteMethod::MedianCut,
+            PaletteMethod::Octree,
+            PaletteMethod::KMeans,
+        ] {
+            let palette = Palette::extract(&clustered(), 4, method);
+            assert_eq!(palette.colors.len(), 4, "{method:?}");
+            for (found, expected) in palette.colors.iter().zip(COLORS) {
+                let error =
+                    color_distance(unpack_color(*found as f32), unpack_color(expected as f32));
+                assert!(error < 1e-3, "{method:?} {found:06x}");
+            }
+        }
+    }
+
+    #[test]
+    fn quantization_only_outputs_palette_colours() {
+        let pixels = ImageConverter::convert_rgb_to_packed(&clustered());
+        for dither in [
+            QuantizationDither::None,
+            QuantizationDither::ErrorDiffusion(DiffusionMatrix::FloydSteinberg),
+        ] {
+            let filter = ColorQuantization {
+                dither,
+                ..ColorQuantization::new(PaletteMethod::MedianCut, 2)
+            };
+            let options = filter.compute_options(&pixels, (8, 8));
+            let palette = &options[1..1 + options[0] as usize];
+            let output = filter.process_cpu(&pixels, &options, (8, 8));
+            assert!(output.iter().all(|value| palette.contains(value)));
+        }
+    }
+
+    #[test]
+    fn error_diffusion_preserves_the_mean() {
+        // Two colour palette from a black and white image, applied to mid grey
+        let filter = ColorQuantization {
+            dither: QuantizationDither::ErrorDiffusion(DiffusionMatrix::FloydSteinberg),
+            ..ColorQuantization::new(PaletteMethod::MedianCut, 2)
+        };
+        let options = filter.compute_options(&[0.0, 0xFFFFFF as f32], (2, 1));
+        assert_eq!(&options[..3], &[2.0, 0.0, 0xFFFFFF as f32]);
+
+        let grey = vec![0x808080 as f32; 256];
+        let output = filter.process_cpu(&grey, &options, (16, 16));
+        let white = output.iter().filter(|&&value| value != 0.0).count();
+        assert!((white as f32 / 256.0 - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn encoded_error_round_trips() {
+        let error = [0.25, -0.125, 0.0];
+        for (decoded, expected) in decode_error(encode_error(error)).iter().zip(error) {
+            assert!((decoded - expected).abs() < 1.0 / 255.0);
+        }
+    }
+
+    #[test]
+    fn swatch_lays_colours_out_in_a_grid() {
+        let palette = Palette {
+            colors: vec![0x112233, 0x445566, 0x778899],
+        };
+        let (pixels, dimensions) = palette.swatch(2, 2);
+        assert_eq!(dimensions, (4, 4));
+        assert_eq!(pixels[0] & 0xFFFFFF, 0x112233);
+        assert_eq!(pixels[3] & 0xFFFFFF, 0x445566);
+        assert_eq!(pixels[8] & 0xFFFFFF, 0x778899);
+        assert_eq!(pixels[15] & 0xFFFFFF, 0x000000);
+        assert!(palette
+            .to_json()
+            .contains("{\"hex\": \"#445566\", \"r\": 68, \"g\": 85, \"b\": 102}"));
+    }
+}
@@ -0,0 +1,300 @@
+use super::error::{check_size, FilterError};
+use super::filters::{ColorMode, ImageFilter, SobelFilter};
+use super::image_converter::ImageConverter;
+
+// Added to the energy per unit of mask, far above any Sobel magnitude
+const MASK_WEIGHT: f32 = 1000.0;
+
+// Resizes by removing or duplicating the connected paths of least Sobel energy, width first
+pub struct SeamCarving {
+    pub width: u32,
+    pub height: u32,
+    // Positive values protect pixels and negative ones are carved first, checked against its
+    // dimensions in `with_mask` and ignored for images of any other size
+    mask: Option<(Vec<f32>, (u32, u32))>,
+}
+
+impl SeamCarving {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            mask: None,
+        }
+    }
+
+    pub fn with_mask(
+        mut self,
+        mask: Vec<f32>,
+        dimensions: (u32, u32),
+    ) -> Result<Self, FilterError> {
+        check_size("Mask", mask.len(), dimensions)?;
+        self.mask = Some((mask, dimensions));
+        Ok(self)
+    }
+
+    pub fn carve(&self, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        // There are no seams to remove or duplicate, so an empty image comes out black
+        if pixels.is_empty() {
+            let (width, height) = self.output_dimensions(dimensions);
+            return vec![0.0; (width * height) as usize];
+        }
+
+        let mask = match &self.mask {
+            Some((mask, mask_dimensions)) if *mask_dimensions == dimensions => mask.clone(),
+            _ => vec![0.0; pixels.len()],
+        };
+
+        let (pixels, mask, dimensions) =
+            carve_width(pixels.to_vec(), mask, dimensions, self.width.max(1));
+
+        // Heights are carved as widths of the transposed image
+        let pixels = transpose(&pixels, dimensions);
+        let mask = transpose(&mask, dimensions);
+        let (pixels, _, dimensions) = carve_width(
+            pixels,
+            mask,
+            (dimensions.1, dimensions.0),
+            self.height.max(1),
+        );
+        transpose(&pixels, dimensions)
+    }
+}
+
+// Seams are found one after another, so carving only runs on the CPU
+impl ImageFilter for SeamCarving {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        ColorMode::Packed
+    }
+
+    fn output_dimensions(&self, _: (u32, u32)) -> (u32, u32) {
+        (self.width.max(1), self.height.max(1))
+    }
+
+    fn process_cpu(&self, pixels: &[f32], _: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        self.carve(pixels, dimensions)
+    }
+}
+
+// Sobel magnitude of the luminance with the unfiltered border copied from its inner neighbour
+fn energy(pixels: &[f32], mask: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    let grayscale =
+        ImageConverter::convert_rgb_to_grayscale(&ImageConverter::convert_packed_to_rgb(pixels));
+    let options = SobelFilter.compute_options(&grayscale, dimensions);
+    let sobel = SobelFilter.process_cpu(&grayscale, &options, dimensions);
+
+    (0..width * height)
+        .map(|i| {
+            let (mut x, mut y) = (i % width, i / width);
+            if width >= 3 {
+                x = x.clamp(1, width - 2);
+            }
+            if height >= 3 {
+                y = y.clamp(1, height - 2);
+            }
+            sobel[y * width + x] + mask[i] * MASK_WEIGHT
+        })
+        .collect()
+}
+
+// Column of the cheapest 8-connected top to bottom path in every row
+fn find_seam(energy: &[f32], dimensions: (u32, u32)) -> Vec<usize> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+
+    let mut cost = energy[..width].to_vec();
+    let mut parents = vec![0usize; width * height];
+    for y in 1..height {
+        let previous = cost.clone();
+        for x in 0..width {
+            let parent = (x.saturating_sub(1)..=(x + 1).min(width - 1))
+                .min_by(|&a, &b| previous[a].total_cmp(&previous[b]))
+                .expect("Row has no pixels");
+            parents[y * width + x] = parent;
+            cost[x] = previous[parent] + energy[y * width + x];
+        }
+    }
+
+    let mut x = (0..width)
+        .min_by(|&a, &b| cost[a].total_cmp(&cost[b]))
+        .expect("Row has no pixels");
+    let mut seam = vec![0; height];
+    for y in (0..height).rev() {
+        seam[y] = x;
+        x = parents[y * width + x];
+    }
+    seam
+}
+
+fn remove_seam<T: Copy>(data: &[T], width: usize, seam: &[usize]) -> Vec<T> {
+    data.chunks(width)
+        .zip(seam)
+        .flat_map(|(row, &x)| row[..x].iter().chain(&row[x + 1..]).copied())
+        .collect()
+}
+
+fn carve_width(
+    mut pixels: Vec<f32>,
+    mut mask: Vec<f32>,
+    dimensions: (u32, u32),
+    target: u32,
+) -> (Vec<f32>, Vec<f32>, (u32, u32)) {
+    let (mut width, height) = dimensions;
+
+    while width > target {
+        let seam = find_seam(&energy(&pixels, &mask, (width, height)), (width, height));
+        pixels = remove_seam(&pixels, width as usize, &seam);
+        mask = remove_seam(&mask, width as usize, &seam);
+        width -= 1;
+    }
+
+    // Enlarging duplicates the seams that would be removed first, in batches of at most
+    // half the width so the same seam is not picked over and over
+    while width < target {
+        let count = (target - width).min(width.div_ceil(2));
+        let mut duplicated = vec![false; (width * height) as usize];
+
+        let (mut remaining, mut remaining_mask) = (pixels.clone(), mask.clone());
+        let mut columns: Vec<usize> = (0..(width * height) as usize)
+            .map(|i| i % width as usize)
+            .collect();
+        for removed in 0..count {
+            let dimensions = (width - removed, height);
+            let seam = find_seam(&energy(&remaining, &remaining_mask, dimensions), dimensions);
+            for (y, &x) in seam.iter().enumerate() {
+                let column = columns[y * dimensions.0 as usize + x];
+                duplicated[y * width as usize + column] = true;
+            }
+            remaining = remove_seam(&remaining, dimensions.0 as usize, &seam);
+            remaining_mask = remove_seam(&remaining_mask, dimensions.0 as usize, &seam);
+            columns = remove_seam(&columns, dimensions.0 as usize, &seam);
+        }
+
+        // Each duplicate is the average of the seam pixel and its right neighbour
+        let mut enlarged = Vec::with_capacity(((width + count) * height) as usize);
+        let mut enlarged_mask = Vec::with_capacity(enlarged.capacity());
+        for (i, &pixel) in pixels.iter().enumerate() {
+            enlarged.push(pixel);
+            enlarged_mask.push(mask[i]);
+            if duplicated[i] {
+                let right = if (i + 1) % width as usize == 0 {
+                    pixel
+                } else {
+                    pixels[i + 1]
+                };
+                enlarged.push(average(pixel, right));
+                enlarged_mask.push(mask[i]);
+            }
+        }
+
+        pixels = enlarged;
+        mask = enlarged_mask;
+        width += count;
+    }
+
+    (pixels, mask, (width, height))
+}
+
+fn average(a: f32, b: f32) -> f32 {
+    let (a, b) = (a as u32, b as u32);
+    let channel = |shift: u32| (((a >> shift) & 0xFF) + ((b >> shift) & 0xFF)).div_ceil(2) << shift;
+    (channel(16) | channel(8) | channel(0)) as f32
+}
+
+fn transpose<T: Copy>(data: &[T], dimensions: (u32, u32)) -> Vec<T> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    (0..width * height)
+        .map(|i| data[(i % height) * width + i / height])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flat grey image with a bright vertical stripe in column 2
+    fn striped(dimensions: (u32, u32)) -> Vec<f32> {
+        (0..dimensions.0 * dimensions.1)
+            .map(|i| {
+                if i % dimensions.0 == 2 {
+                    0xFFFFFF as f32
+                } else {
+                    0x404040 as f32
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seam_follows_the_cheapest_path() {
+        let mut energy = vec![1.0; 20];
+        for (y, x) in [(0, 3), (1, 2), (2, 2), (3, 1)] {
+            energy[y * 5 + x] = 0.0;
+        }
+        assert_eq!(find_seam(&energy, (5, 4)), vec![3, 2, 2, 1]);
+    }
+
+    #[test]
+    fn carving_removes_one_column_per_seam() {
+        let dimensions = (8, 6);
+        let carving = SeamCarving::new(5, 6);
+        let output = carving.carve(&striped(dimensions), dimensions);
+
+        assert_eq!(output.len(), 30);
+        // The stripe has the highest energy, so it survives in every row
+        for row in output.chunks(5) {
+            assert_eq!(row.iter().filter(|&&p| p == 0xFFFFFF as f32).count(), 1);
+        }
+    }
+
+    #[test]
+    fn enlarging_duplicates_seams() {
+        let (pixels, _, dimensions) = carve_width(striped((6, 4)), vec![0.0; 24], (6, 4), 10);
+        assert_eq!(dimensions, (10, 4));
+        assert_eq!(pixels.len(), 40);
+    }
+
+    #[test]
+    fn output_matches_the_requested_size() {
+        let carving = SeamCarving::new(4, 3);
+        let output = carving.process_cpu(&striped((6, 5)), &[], (6, 5));
+        assert_eq!(carving.output_dimensions((6, 5)), (4, 3));
+        assert_eq!(output.len(), 12);
+    }
+
+    #[test]
+    fn mask_protects_pixels() {
+        let dimensions = (6, 4);
+        let pixels = vec![0x404040 as f32; 24];
+        let mut mask = vec![0.0; 24];
+        let mut marked = pixels.clone();
+        for y in 0..4 {
+            mask[y * 6] = 1.0;
+            marked[y * 6] = 0x808080 as f32;
+        }
+
+        let carving = SeamCarving::new(2, 4).with_mask(mask, dimensions).unwrap();
+        let output = carving.carve(&marked, dimensions);
+        for row in output.chunks(2) {
+            assert_eq!(row[0], 0x808080 as f32);
+        }
+    }
+
+    #[test]
+    fn mask_must_match_its_dimensions() {
+        assert!(SeamCarving::new(2, 2)
+            .with_mask(vec![0.0; 5], (3, 2))
+            .is_err());
+    }
+
+    #[test]
+    fn empty_image_comes_out_black() {
+        let carving = SeamCarving::new(3, 2);
+        assert_eq!(carving.carve(&[], (0, 0)), vec![0.0; 6]);
+        assert_eq!(carving.carve(&[], (0, 4)), vec![0.0; 6]);
+    }
+}
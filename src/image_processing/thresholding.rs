@@ -0,0 +1,473 @@
+use super::filters::ImageFilter;
+use super::histogram::histogram;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlobalThresholdMethod {
+    Otsu,
+    Triangle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdaptiveThresholdMethod {
+    // Local mean minus the offset
+    Mean,
+    // Gaussian-weighted local mean minus the offset
+    Gaussian,
+    // Local mean plus k standard deviations
+    Niblack,
+    // Local mean scaled by (1 + k * (deviation / range - 1))
+    Sauvola,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThresholdOutput {
+    Binary,
+    // The threshold each pixel was compared against
+    Threshold,
+}
+
+pub struct GlobalThreshold {
+    pub method: GlobalThresholdMethod,
+    pub output: ThresholdOutput,
+}
+
+pub struct AdaptiveThreshold {
+    pub method: AdaptiveThresholdMethod,
+    // Odd side length of the local window
+    pub window: u32,
+    pub k: f32,
+    pub offset: f32,
+    // Dynamic range of the standard deviation for Sauvola
+    pub range: f32,
+    pub output: ThresholdOutput,
+}
+
+impl GlobalThreshold {
+    pub fn new(method: GlobalThresholdMethod) -> Self {
+        Self {
+            method,
+            output: ThresholdOutput::Binary,
+        }
+    }
+
+    pub fn threshold(&self, pixels: &[f32]) -> f32 {
+        let histogram = histogram(pixels.iter().copied());
+        let bin = match self.method {
+            GlobalThresholdMethod::Otsu => otsu_bin(&histogram),
+            GlobalThresholdMethod::Triangle => triangle_bin(&histogram),
+        };
+        // Pixels in the threshold bin and below are background
+        (bin as f32 + 0.5) / 255.0
+    }
+}
+
+impl ImageFilter for GlobalThreshold {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void globalThreshold(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                float threshold = options[0];
+                if ((int)options[1] == 0)
+                    outputImage[idx] = inputImage[idx] > threshold ? 1.0f : 0.0f;
+                else
+                    outputImage[idx] = threshold;
+            }
+            "#,
+            "globalThreshold",
+        ))
+    }
+
+    fn compute_options(&self, pixels: &[f32], _: (u32, u32)) -> Vec<f32> {
+        vec![self.threshold(pixels), self.output as i32 as f32]
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let threshold = options[0];
+        pixels
+            .iter()
+            .map(|&pixel| match self.output {
+                ThresholdOutput::Binary if pixel > threshold => 1.0,
+                ThresholdOutput::Binary => 0.0,
+                ThresholdOutput::Threshold => threshold,
+            })
+            .collect()
+    }
+}
+
+impl AdaptiveThreshold {
+    pub fn new(method: AdaptiveThresholdMethod, window: u32) -> Self {
+        let k = match method {
+            AdaptiveThresholdMethod::Niblack => -0.2,
+            AdaptiveThresholdMethod::Sauvola => 0.34,
+            _ => 0.0,
+        };
+
+        Self {
+            method,
+            window,
+            k,
+            offset: 0.0,
+            range: 0.5,
+            output: ThresholdOutput::Binary,
+        }
+    }
+
+    fn window_size(&self) -> usize {
+        (self.window.max(3) | 1) as usize
+    }
+
+    // Same sigma rule of thumb as OpenCV uses for a given kernel size
+    fn gaussian_weights(&self) -> Vec<f32> {
+        let size = self.window_size();
+        let half = (size / 2) as isize;
+        let sigma = 0.3 * ((size as f32 - 1.0) * 0.5 - 1.0) + 0.8;
+
+        (0..size * size)
+            .map(|i| {
+                let x = (i % size) as isize - half;
+                let y = (i / size) as isize - half;
+                (-((x * x + y * y) as f32) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect()
+    }
+}
+
+impl ImageFilter for AdaptiveThreshold {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            __kernel void adaptiveThreshold(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int method = (int)options[0];
+                int window = (int)options[1];
+                int half = window / 2;
+
+                // Windows are truncated at the image borders
+                float sum = 0.0f, squares = 0.0f, weights = 0.0f;
+                for (int ky = -half; ky <= half; ky++) {
+                    for (int kx = -half; kx <= half; kx++) {
+                        int nx = x + kx;
+                        int ny = y + ky;
+                        if (nx < 0 || ny < 0 || nx >= width || ny >= height)
+                            continue;
+
+                        float pixel = inputImage[ny * width + nx];
+                        float weight = method == 1 ? options[6 + (ky + half) * window + (kx + half)] : 1.0f;
+                        sum += weight * pixel;
+                        squares += weight * pixel * pixel;
+                        weights += weight;
+                    }
+                }
+
+                float mean = sum / weights;
+                float deviation = sqrt(max(squares / weights - mean * mean, 0.0f));
+                float k = options[2];
+                float offset = options[3];
+                float range = options[4];
+
+                float threshold;
+                switch (method) {
+                    case 0:
+                    case 1: threshold = mean - offset; break;
+                    case 2: threshold = mean + k * deviation; break;
+                    default: threshold = mean * (1.0f + k * (deviation / range - 1.0f)); break;
+                }
+
+                int idx = y * width + x;
+                if ((int)options[5] == 0)
+                    outputImage[idx] = inputImage[idx] > threshold ? 1.0f : 0.0f;
+                else
+                    outputImage[idx] = threshold;
+            }
+            "#,
+            "adaptiveThreshold",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let mut options = vec![
+            self.method as i32 as f32,
+            self.window_size() as f32,
+            self.k,
+            self.offset,
+            self.range,
+            self.output as i32 as f32,
+        ];
+        if self.method == AdaptiveThresholdMethod::Gaussian {
+            options.extend(self.gaussian_weights());
+        }
+        options
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let (width, height) = (dimensions.0 as isize, dimensions.1 as isize);
+        let window = options[1] as isize;
+        let half = window / 2;
+        let (k, offset, range) = (options[2], options[3], options[4]);
+
+        // Integral images make the box statistics independent of the window size
+        let stride = (width + 1) as usize;
+        let mut sums = vec![0.0f64; stride * (height + 1) as usize];
+        let mut squares = vec![0.0f64; stride * (height + 1) as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let pixel = pixels[y * width as usize + x] as f64;
+                let i = (y + 1) * stride + x + 1;
+                sums[i] = pixel + sums[i - 1] + sums[i - stride] - sums[i - stride - 1];
+                squares[i] =
+                    pixel * pixel + squares[i - 1] + squares[i - stride] - squares[i - stride - 1];
+            }
+        }
+        let area = |table: &[f64], x0: usize, y0: usize, x1: usize, y1: usize| {
+            table[y1 * stride + x1] - table[y0 * stride + x1] - table[y1 * stride + x0]
+                + table[y0 * stride + x0]
+        };
+
+        let mut output = vec![0.0; pixels.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (x - half).max(0) as usize;
+                let y0 = (y - half).max(0) as usize;
+                let x1 = (x + half + 1).min(width) as usize;
+                let y1 = (y + half + 1).min(height) as usize;
+
+                let (mean, deviation) = if self.method == AdaptiveThresholdMethod::Gaussian {
+                    let (mut sum, mut weights) = (0.0, 0.0);
+                    for ny in y0..y1 {
+                        for nx in x0..x1 {
+                            let kx = nx as isize - x + half;
+                            let ky = ny as isize - y + half;
+                            let weight = options[6 + (ky * window + kx) as usize];
+                            sum += weight * pixels[ny * width as usize + nx];
+                            weights += weight;
+                        }
+                    }
+                    (sum / weights, 0.0)
+                } else {
+                    let count = ((x1 - x0) * (y1 - y0)) as f64;
+                    let mean = area(&sums, x0, y0, x1, y1) / count;
+                    let variance = area(&squares, x0, y0, x1, y1) / count - mean * mean;
+                    (mean as f32, variance.max(0.0).sqrt() as f32)
+                };
+
+                let threshold = match self.method {
+                    AdaptiveThresholdMethod::Mean | AdaptiveThresholdMethod::Gaussian => {
+                        mean - offset
+                    }
+                    AdaptiveThresholdMethod::Niblack => mean + k * deviation,
+                    AdaptiveThresholdMethod::Sauvola => {
+                        mean * (1.0 + k * (deviation / range - 1.0))
+                    }
+                };
+
+                let idx = (y * width + x) as usize;
+                output[idx] = match self.output {
+                    ThresholdOutput::Binary if pixels[idx] > threshold => 1.0,
+                    ThresholdOutput::Binary => 0.0,
+                    ThresholdOutput::Threshold => threshold,
+                };
+            }
+        }
+        output
+    }
+}
+
+// Maximises the between-class variance
+fn otsu_bin(histogram: &[f32]) -> usize {
+    let total: f32 = histogram.iter().sum();
+    let weighted_total: f32 = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| i as f32 * c)
+        .sum();
+
+    let (mut background, mut weighted_background) = (0.0, 0.0);
+    let (mut best, mut best_variance) = (0, -1.0);
+    for (i, &count) in histogram.iter().enumerate() {
+        background += count;
+        weighted_background += i as f32 * count;
+        let foreground = total - background;
+        if background == 0.0 || foreground == 0.0 {
+            continue;
+        }
+
+        let mean_background = weighted_background / background;
+        let mean_foreground = (weighted_total - weighted_background) / foreground;
+        let variance = background * foreground * (mean_background - mean_foreground).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = i;
+        }
+    }
+    best
+}
+
+// Bin furthest from the line joining the histogram peak to the far end of the longer tail
+fn triangle_bin(histogram: &[f32]) -> usize {
+    let Some(first) = histogram.iter().position(|&count| count > 0.0) else {
+        return 0;
+    };
+    let last = histogram
+        .iter()
+        .rposition(|&count| count > 0.0)
+        .unwrap_or(first);
+    let peak = (first..=last)
+        .max_by(|&a, &b| histogram[a].total_cmp(&histogram[b]))
+        .unwrap_or(first);
+
+    let end = if peak - first > last - peak {
+        first
+    } else {
+        last
+    };
+    // A single occupied bin has no tail to draw the line along
+    if end == peak {
+        return peak;
+    }
+    let (peak_height, end_height) = (histogram[peak], histogram[end]);
+    let span = end as f32 - peak as f32;
+
+    let range = if end < peak { end..=peak } else { peak..=end };
+    range
+        .max_by(|&a, &b| {
+            // Perpendicular distance up to a constant factor
+            let distance = |i: usize| {
+                let t = (i as f32 - peak as f32) / span;
+                peak_height + t * (end_height - peak_height) - histogram[i]
+            };
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap_or(peak)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processing::histogram::bin;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    // Dark background with a brighter square, both slightly noisy
+    fn bimodal() -> Vec<f32> {
+        (0..100)
+            .map(|i| {
+                let (x, y) = (i % 10, i / 10);
+                let noise = (i % 3) as f32 * 0.02;
+                if (3..7).contains(&x) && (3..7).contains(&y) {
+                    0.7 + noise
+                } else {
+                    0.2 + noise
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn otsu_separates_two_classes() {
+        let filter = GlobalThreshold::new(GlobalThresholdMethod::Otsu);
+        let threshold = filter.threshold(&bimodal());
+        assert!(threshold > 0.24 && threshold < 0.7);
+
+        let output = run(&filter, &bimodal(), (10, 10));
+        assert_eq!(output.iter().sum::<f32>(), 16.0);
+        assert_eq!(output[44], 1.0);
+    }
+
+    #[test]
+    fn triangle_finds_the_foot_of_the_peak() {
+        let filter = GlobalThreshold::new(GlobalThresholdMethod::Triangle);
+        let threshold = filter.threshold(&bimodal());
+        assert!(threshold > 0.24 && threshold < 0.7);
+    }
+
+    #[test]
+    fn triangle_handles_a_single_intensity() {
+        let filter = GlobalThreshold::new(GlobalThresholdMethod::Triangle);
+        let threshold = filter.threshold(&[0.5; 16]);
+        assert_eq!(threshold, (bin(0.5) as f32 + 0.5) / 255.0);
+        assert!(run(&filter, &[0.5; 16], (4, 4)).iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn threshold_output_reports_the_threshold() {
+        let filter = GlobalThreshold {
+            output: ThresholdOutput::Threshold,
+            ..GlobalThreshold::new(GlobalThresholdMethod::Otsu)
+        };
+        let output = run(&filter, &bimodal(), (10, 10));
+        assert!(output.iter().all(|&v| v == filter.threshold(&bimodal())));
+    }
+
+    #[test]
+    fn adaptive_threshold_follows_uneven_lighting() {
+        // Dots brighter than their surroundings on a background ramp
+        let pixels: Vec<f32> = (0..256)
+            .map(|i| {
+                let (x, y) = (i % 16, i / 16);
+                let background = 0.1 + 0.02 * x as f32;
+                if x % 4 == 1 && y % 4 == 1 {
+                    background + 0.1
+                } else {
+                    background
+                }
+            })
+            .collect();
+
+        for method in [
+            AdaptiveThresholdMethod::Mean,
+            AdaptiveThresholdMethod::Gaussian,
+        ] {
+            let filter = AdaptiveThreshold {
+                // A negative offset keeps flat background below the threshold
+                offset: -0.03,
+                ..AdaptiveThreshold::new(method, 5)
+            };
+            let output = run(&filter, &pixels, (16, 16));
+            for (i, &value) in output.iter().enumerate() {
+                let dot = i % 16 % 4 == 1 && i / 16 % 4 == 1;
+                assert_eq!(value == 1.0, dot, "{method:?} {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn local_statistics_thresholds_on_flat_images() {
+        let threshold = |method| {
+            let filter = AdaptiveThreshold {
+                output: ThresholdOutput::Threshold,
+                ..AdaptiveThreshold::new(method, 3)
+            };
+            run(&filter, &[0.5; 25], (5, 5))[12]
+        };
+        // No deviation, so Niblack is the mean and Sauvola scales it by 1 - k
+        assert!((threshold(AdaptiveThresholdMethod::Niblack) - 0.5).abs() < 1e-6);
+        assert!((threshold(AdaptiveThresholdMethod::Sauvola) - 0.5 * 0.66).abs() < 1e-6);
+    }
+}
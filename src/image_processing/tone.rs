@@ -0,0 +1,327 @@
+use super::filters::{ColorMode, ImageFilter};
+
+const LUT_SIZE: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToneOperation {
+    // Contrast scales around mid grey, brightness is added afterwards
+    BrightnessContrast {
+        brightness: f32,
+        contrast: f32,
+    },
+    // Values above one brighten the midtones
+    Gamma(f32),
+    Levels {
+        input: (f32, f32),
+        gamma: f32,
+        output: (f32, f32),
+    },
+    // Control points in 0..1, joined by a monotone cubic spline
+    Curve(Vec<(f32, f32)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToneTarget {
+    Rgb,
+    Luminance,
+    // Separate red, green and blue operations, applied before the shared ones
+    Channels([Vec<ToneOperation>; 3]),
+}
+
+pub struct ToneAdjustment {
+    pub operations: Vec<ToneOperation>,
+    pub target: ToneTarget,
+}
+
+impl ToneOperation {
+    pub fn apply(&self, value: f32) -> f32 {
+        let result = match self {
+            ToneOperation::BrightnessContrast {
+                brightness,
+                contrast,
+            } => (value - 0.5) * contrast + 0.5 + brightness,
+            ToneOperation::Gamma(gamma) => value.max(0.0).powf(1.0 / gamma.max(f32::EPSILON)),
+            ToneOperation::Levels {
+                input,
+                gamma,
+                output,
+            } => {
+                let range = (input.1 - input.0).max(f32::EPSILON);
+                let normalized = ((value - input.0) / range).clamp(0.0, 1.0);
+                let corrected = normalized.powf(1.0 / gamma.max(f32::EPSILON));
+                output.0 + corrected * (output.1 - output.0)
+            }
+            ToneOperation::Curve(points) => monotone_cubic(points, value),
+        };
+        result.clamp(0.0, 1.0)
+    }
+}
+
+impl ToneAdjustment {
+    pub fn new(operations: Vec<ToneOperation>) -> Self {
+        Self {
+            operations,
+            target: ToneTarget::Rgb,
+        }
+    }
+
+    pub fn lut(&self, channel_operations: &[ToneOperation]) -> Vec<f32> {
+        (0..LUT_SIZE)
+            .map(|i| {
+                let value = i as f32 / (LUT_SIZE - 1) as f32;
+                channel_operations
+                    .iter()
+                    .chain(&self.operations)
+                    .fold(value, |value, operation| operation.apply(value))
+            })
+            .collect()
+    }
+}
+
+impl ImageFilter for ToneAdjustment {
+    fn get_kernel(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            r#"
+            float applyLut(__global const float* lut, int size, float value) {
+                float position = clamp(value, 0.0f, 1.0f) * (size - 1);
+                int index = min((int)position, size - 2);
+                return mix(lut[index], lut[index + 1], position - index);
+            }
+
+            uint toByte(float value) {
+                return (uint)(clamp(value, 0.0f, 1.0f) * 255.0f + 0.5f);
+            }
+
+            __kernel void toneAdjustment(
+                __global const float* inputImage,
+                __global float* outputImage,
+                __global const float* options,
+                const int width,
+                const int height) {
+
+                int x = get_global_id(0);
+                int y = get_global_id(1);
+
+                if (x >= width || y >= height)
+                    return;
+
+                int idx = y * width + x;
+                int count = (int)options[0];
+                int size = (int)options[1];
+                __global const float* luts = options + 2;
+
+                if (count == 1) {
+                    outputImage[idx] = applyLut(luts, size, inputImage[idx]);
+                    return;
+                }
+
+                // One table per channel of a packed pixel
+                uint pixel = (uint)inputImage[idx];
+                float r = applyLut(luts, size, ((pixel >> 16) & 0xFF) / 255.0f);
+                float g = applyLut(luts + size, size, ((pixel >> 8) & 0xFF) / 255.0f);
+                float b = applyLut(luts + 2 * size, size, (pixel & 0xFF) / 255.0f);
+                outputImage[idx] = (float)((toByte(r) << 16) | (toByte(g) << 8) | toByte(b));
+            }
+            "#,
+            "toneAdjustment",
+        ))
+    }
+
+    fn compute_options(&self, _: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let luts = match &self.target {
+            ToneTarget::Channels(channels) => channels.iter().map(|ops| self.lut(ops)).collect(),
+            _ => vec![self.lut(&[])],
+        };
+
+        let mut options = vec![luts.len() as f32, LUT_SIZE as f32];
+        for lut in luts {
+            options.extend(lut);
+        }
+        options
+    }
+
+    fn color_mode(&self) -> ColorMode {
+        match self.target {
+            ToneTarget::Rgb => ColorMode::Rgb,
+            ToneTarget::Luminance => ColorMode::Luminance,
+            ToneTarget::Channels(_) => ColorMode::Packed,
+        }
+    }
+
+    fn process_cpu(&self, pixels: &[f32], options: &[f32], _: (u32, u32)) -> Vec<f32> {
+        let count = options[0] as usize;
+        let size = options[1] as usize;
+        let luts: Vec<&[f32]> = options[2..].chunks(size).take(count).collect();
+
+        let apply = |lut: &[f32], value: f32| {
+            let position = value.clamp(0.0, 1.0) * (size - 1) as f32;
+            let index = (position as usize).min(size - 2);
+            let fraction = position - index as f32;
+            lut[index] * (1.0 - fraction) + lut[index + 1] * fraction
+        };
+        let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+
+        pixels
+            .iter()
+            .map(|&pixel| {
+                if count == 1 {
+                    return apply(luts[0], pixel);
+                }
+
+                let packed = pixel as u32;
+                let r = apply(luts[0], ((packed >> 16) & 0xFF) as f32 / 255.0);
+                let g = apply(luts[1], ((packed >> 8) & 0xFF) as f32 / 255.0);
+                let b = apply(luts[2], (packed & 0xFF) as f32 / 255.0);
+                ((to_byte(r) << 16) | (to_byte(g) << 8) | to_byte(b)) as f32
+            })
+            .collect()
+    }
+}
+
+// Fritsch-Carlson interpolation, which never overshoots between control points
+fn monotone_cubic(points: &[(f32, f32)], value: f32) -> f32 {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points.dedup_by(|a, b| a.0 == b.0);
+
+    match points.len() {
+        0 => return value,
+        1 => return points[0].1,
+        _ => {}
+    }
+
+    let n = points.len();
+    if value <= points[0].0 {
+        return points[0].1;
+    }
+    if value >= points[n - 1].0 {
+        return points[n - 1].1;
+    }
+
+    let secants: Vec<f32> = points
+        .windows(2)
+        .map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0))
+        .collect();
+
+    let mut tangents = vec![0.0; n];
+    tangents[0] = secants[0];
+    tangents[n - 1] = secants[n - 2];
+    for i in 1..n - 1 {
+        tangents[i] = if secants[i - 1] * secants[i] <= 0.0 {
+            0.0
+        } else {
+            (secants[i - 1] + secants[i]) / 2.0
+        };
+    }
+    for i in 0..n - 1 {
+        if secants[i] == 0.0 {
+            tangents[i] = 0.0;
+            tangents[i + 1] = 0.0;
+            continue;
+        }
+        let alpha = tangents[i] / secants[i];
+        let beta = tangents[i + 1] / secants[i];
+        let magnitude = alpha * alpha + beta * beta;
+        if magnitude > 9.0 {
+            let scale = 3.0 / magnitude.sqrt();
+            tangents[i] = scale * alpha * secants[i];
+            tangents[i + 1] = scale * beta * secants[i];
+        }
+    }
+
+    let i = points
+        .windows(2)
+        .position(|pair| value < pair[1].0)
+        .unwrap_or(n - 2);
+    let (x0, y0) = points[i];
+    let (x1, y1) = points[i + 1];
+    let h = x1 - x0;
+    let t = (value - x0) / h;
+    let (t2, t3) = (t * t, t * t * t);
+
+    (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+        + (t3 - 2.0 * t2 + t) * h * tangents[i]
+        + (-2.0 * t3 + 3.0 * t2) * y1
+        + (t3 - t2) * h * tangents[i + 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(filter: &impl ImageFilter, pixels: &[f32], dimensions: (u32, u32)) -> Vec<f32> {
+        let options = filter.compute_options(pixels, dimensions);
+        filter.process_cpu(pixels, &options, dimensions)
+    }
+
+    #[test]
+    fn brightness_and_contrast_pivot_on_mid_grey() {
+        let operation = ToneOperation::BrightnessContrast {
+            brightness: 0.1,
+            contrast: 2.0,
+        };
+        assert!((operation.apply(0.5) - 0.6).abs() < 1e-6);
+        assert!((operation.apply(0.6) - 0.8).abs() < 1e-6);
+        assert_eq!(operation.apply(0.9), 1.0);
+    }
+
+    #[test]
+    fn gamma_above_one_brightens_midtones() {
+        let operation = ToneOperation::Gamma(2.0);
+        assert!((operation.apply(0.25) - 0.5).abs() < 1e-6);
+        assert_eq!(operation.apply(0.0), 0.0);
+        assert_eq!(operation.apply(1.0), 1.0);
+    }
+
+    #[test]
+    fn levels_remap_the_input_range() {
+        let operation = ToneOperation::Levels {
+            input: (0.2, 0.6),
+            gamma: 1.0,
+            output: (0.1, 0.9),
+        };
+        assert!((operation.apply(0.1) - 0.1).abs() < 1e-6);
+        assert!((operation.apply(0.4) - 0.5).abs() < 1e-6);
+        assert!((operation.apply(0.8) - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn curves_pass_through_their_points_without_overshoot() {
+        let points = vec![(0.0, 0.0), (0.3, 0.6), (0.35, 0.62), (1.0, 1.0)];
+        for &(x, y) in &points {
+            assert!((monotone_cubic(&points, x) - y).abs() < 1e-6);
+        }
+        let samples: Vec<f32> = (0..=100)
+            .map(|i| monotone_cubic(&points, i as f32 / 100.0))
+            .collect();
+        assert!(samples.windows(2).all(|pair| pair[0] <= pair[1] + 1e-6));
+    }
+
+    #[test]
+    fn identity_curve_leaves_pixels_unchanged() {
+        let filter = ToneAdjustment::new(vec![ToneOperation::Curve(vec![(0.0, 0.0), (1.0, 1.0)])]);
+        let pixels = [0.0, 0.123, 0.5, 0.777, 1.0];
+        let output = run(&filter, &pixels, (5, 1));
+        for (a, b) in output.iter().zip(&pixels) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn channel_operations_only_touch_their_channel() {
+        let filter = ToneAdjustment {
+            target: ToneTarget::Channels([
+                vec![ToneOperation::Levels {
+                    input: (0.0, 1.0),
+                    gamma: 1.0,
+                    output: (1.0, 1.0),
+                }],
+                vec![],
+                vec![],
+            ]),
+            ..ToneAdjustment::new(vec![])
+        };
+        let output = run(&filter, &[0x204060 as f32], (1, 1));
+        assert_eq!(output[0] as u32, 0xFF4060);
+    }
+}
@@ -1,5 +1,11 @@
 use minifb::{Key, Window, WindowOptions};
 
+use crate::image_processing::filters::ImageFilter;
+use crate::image_processing::geometry::{Interpolation, Resize, ResizeMode};
+use crate::image_processing::image_processor::ImageProcessor;
+
+const MAX_WINDOW_SIZE: (usize, usize) = (1920, 1080);
+
 pub struct Cell {
     image: Vec<u32>,
     width: usize,
@@ -25,12 +31,18 @@ impl Viewer {
         processed: Vec<Vec<u32>>,
         image_dimensions: (u32, u32),
     ) -> Viewer {
-        let cell_width = image_dimensions.0 as usize;
-        let cell_height = image_dimensions.1 as usize;
-
         let grid_cols = processed.len().min(3);
         let grid_rows = (processed.len() as f32 / grid_cols as f32).ceil() as usize + 1;
 
+        // Shrink the cells so the whole grid fits on screen
+        let (cell_width, cell_height) = Self::fit_cell(image_dimensions, grid_cols, grid_rows);
+        let cell_dimensions = (cell_width as u32, cell_height as u32);
+        let original = Self::resize(original, image_dimensions, cell_dimensions);
+        let processed: Vec<Vec<u32>> = processed
+            .into_iter()
+            .map(|image| Self::resize(image, image_dimensions, cell_dimensions))
+            .collect();
+
         let window_width = cell_width * grid_cols;
         let window_height = cell_height * grid_rows;
 
@@ -84,6 +96,36 @@ impl Viewer {
         }
     }
 
+    fn fit_cell(image_dimensions: (u32, u32), cols: usize, rows: usize) -> (usize, usize) {
+        let (width, height) = (image_dimensions.0 as f32, image_dimensions.1 as f32);
+        let scale = (MAX_WINDOW_SIZE.0 as f32 / (width * cols as f32))
+            .min(MAX_WINDOW_SIZE.1 as f32 / (height * rows as f32))
+            .min(1.0);
+
+        (
+            ((width * scale) as usize).max(1),
+            ((height * scale) as usize).max(1),
+        )
+    }
+
+    fn resize(image: Vec<u32>, dimensions: (u32, u32), target: (u32, u32)) -> Vec<u32> {
+        if dimensions == target {
+            return image;
+        }
+        // An empty image has nothing to scale and shows as a black cell
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            return vec![0; (target.0 * target.1) as usize];
+        }
+
+        let resize: Vec<Box<dyn ImageFilter>> = vec![Box::new(Resize::new(
+            ResizeMode::Exact(target.0, target.1),
+            Interpolation::Bilinear,
+        ))];
+        ImageProcessor::new(&image, dimensions, &resize)
+            .process_chain()
+            .0
+    }
+
     pub fn run(&mut self) {
         let window = &mut self.viewport.window;
         while window.is_open() && !window.is_key_down(Key::Escape) {
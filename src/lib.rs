@@ -0,0 +1,3 @@
+pub mod image_processing;
+pub mod image_viewer;
+pub mod utility;
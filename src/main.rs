@@ -1,14 +1,12 @@
-mod image_processing;
-mod image_viewer;
-mod utility;
-
-use image_processing::filters::{
-    BayerOrderedDithering, CannyFilter, GaussianBlur, ImageFilter, LaplacianSharpening,
-    PrewittFilter, SobelFilter,
+use image_processing::image_processing::dithering::{
+    DiffusionMatrix, ErrorDiffusionDithering, OrderedDithering, ThresholdMap,
+};
+use image_processing::image_processing::filters::{
+    CannyFilter, GaussianBlur, ImageFilter, LaplacianSharpening, PrewittFilter, SobelFilter,
 };
-use image_processing::image_processor::ImageProcessor;
-use image_viewer::Viewer;
-use utility::Utility;
+use image_processing::image_processing::image_processor::ImageProcessor;
+use image_processing::image_viewer::Viewer;
+use image_processing::utility::Utility;
 
 fn main() {
     let files = Utility::list_input_output_image_files();
@@ -18,7 +16,10 @@ fn main() {
         Box::new(CannyFilter),
         Box::new(GaussianBlur),
         Box::new(LaplacianSharpening),
-        Box::new(BayerOrderedDithering),
+        Box::new(OrderedDithering::new(ThresholdMap::Bayer(4))),
+        Box::new(ErrorDiffusionDithering::new(
+            DiffusionMatrix::FloydSteinberg,
+        )),
     ];
 
     let mut handles = vec![];
@@ -41,8 +42,8 @@ fn prepare_images(
     file: &str,
     kernels: &[Box<dyn ImageFilter>],
 ) -> (Vec<u32>, Vec<Vec<u32>>, (u32, u32)) {
-    let (input, dimensions) = Utility::image_file_to_rgb(&file);
-    let processor = ImageProcessor::new(&input, dimensions, &kernels);
+    let (input, dimensions) = Utility::image_file_to_rgb(file);
+    let processor = ImageProcessor::new(&input, dimensions, kernels);
     let output = processor.process_image();
     (input, output, dimensions)
 }
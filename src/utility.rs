@@ -1,4 +1,4 @@
-use image::open;
+use image::{open, RgbImage};
 use std::fs;
 
 pub struct Utility;
@@ -47,4 +47,16 @@ impl Utility {
 
         (pixels, image.dimensions())
     }
+
+    pub fn rgb_to_image_file(pixels: &[u32], dimensions: (u32, u32), file: &str) {
+        let bytes: Vec<u8> = pixels
+            .iter()
+            .flat_map(|p| [(p >> 16) as u8, (p >> 8) as u8, *p as u8])
+            .collect();
+
+        RgbImage::from_raw(dimensions.0, dimensions.1, bytes)
+            .expect("Pixel count does not match the dimensions")
+            .save(file)
+            .expect("Failed to save image");
+    }
 }